@@ -1,25 +1,38 @@
-use std::{path::PathBuf, slice::Iter, sync::mpsc::Receiver};
+use std::{collections::HashMap, path::PathBuf};
 
 use crate::{
-    looper,
+    backend::{FfmpegBackend, MediaInfo},
+    ffmpeg, hash,
+    history::History,
+    job::{CancelToken, JobError, JobEvent, LogLevel},
+    job_history::{JobHistory, JobOutcome, JobRecord},
+    looper::{self, CrossfadeCurve, CrossfadePlacement, Section},
+    naming::{self, NameValues, OverwritePolicy},
+    project::{self, Project, ProjectTimes, Region, SourceStatus},
+    provision::{self, ProvisionEvent},
+    recipe::Recipe,
+    render::{self, LoopSpec, LoopSpecBuilder, OutputFormat},
+    script::{RenderScript, Shell},
+    settings::{ConsoleVerbosity, Settings, Theme},
+    timecode::{self, TimeInput, Unit},
     ui::{
         console::create_console_view,
         error::error_window,
         ffmpeg::{ffmpeg_info, initial_ffmpeg_info},
         footer::add_footer,
         header::add_header,
+        history::history_panel,
+        jobs::job_history_panel,
+        overwrite::overwrite_window,
         parameters::create_param_grid,
+        project::{project_bar, project_details},
+        recipe::recipe_window,
+        settings::settings_window,
+        timeline::loop_timeline,
     },
 };
 
-#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, Default)]
-pub enum Unit {
-    #[default]
-    Milliseconds,
-    Seconds,
-}
-
-#[derive(Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub enum ConsoleText {
     Program(String),
     Success(String),
@@ -27,14 +40,82 @@ pub enum ConsoleText {
     Stderr(String),
 }
 
+impl ConsoleText {
+    pub fn text(&self) -> &str {
+        match self {
+            ConsoleText::Program(text)
+            | ConsoleText::Success(text)
+            | ConsoleText::Stdout(text)
+            | ConsoleText::Stderr(text) => text,
+        }
+    }
+}
+
+/// How the console is filtered, and which of its lines are selected
+pub struct ConsoleView {
+    pub show_program: bool,
+    pub show_success: bool,
+    pub show_stdout: bool,
+    pub show_stderr: bool,
+    /// Only lines containing this are shown, ignoring case
+    pub search: String,
+    /// Whether runs of ffmpeg banner and progress lines are shown as one line
+    pub collapse: bool,
+    /// Indices of the selected console lines
+    pub selected: std::collections::BTreeSet<usize>,
+    // The line a Shift+click selects from
+    pub anchor: Option<usize>,
+    /// The errors of the last failed job, kept above the console until it is cleared
+    pub pinned: Vec<String>,
+}
+
+impl ConsoleView {
+    fn set_verbosity(&mut self, verbosity: ConsoleVerbosity) {
+        self.show_stdout = verbosity != ConsoleVerbosity::Quiet;
+        self.collapse = verbosity != ConsoleVerbosity::Verbose;
+    }
+
+    /// Pins the last error lines of `lines` above the console, after a job failed
+    pub fn pin_errors(&mut self, lines: &[ConsoleText]) {
+        let errors: Vec<String> = lines
+            .iter()
+            .filter_map(|line| match line {
+                ConsoleText::Stderr(text) => Some(text.clone()),
+                _ => None,
+            })
+            .collect();
+        self.pinned = errors[errors.len().saturating_sub(PINNED_ERRORS)..].to_vec();
+    }
+}
+
+impl Default for ConsoleView {
+    fn default() -> Self {
+        Self {
+            show_program: true,
+            show_success: true,
+            show_stdout: true,
+            show_stderr: true,
+            search: String::new(),
+            collapse: true,
+            selected: Default::default(),
+            anchor: None,
+            pinned: Vec::new(),
+        }
+    }
+}
+
+// How many of a failed job's error lines are pinned, the last ones being the most telling
+const PINNED_ERRORS: usize = 8;
+
 pub enum TimeVariable {
     Start,
     End,
     Crossfade,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Default)]
-struct AppUnits {
+// Units persisted before they moved into the settings, read so they carry over
+#[derive(serde::Deserialize, Default, PartialEq)]
+struct LegacyUnits {
     start_unit: Unit,
     end_unit: Unit,
     crossfade_unit: Unit,
@@ -43,20 +124,72 @@ struct AppUnits {
 #[derive(serde::Deserialize, serde::Serialize, Default)]
 struct AppToolPaths {
     ffmpeg_path: String,
+    // Base URL to download ffmpeg from instead of the pinned build's own URL
+    #[serde(default)]
+    ffmpeg_source: String,
+}
+
+// How many sources have their settings remembered before the least recently used are forgotten
+const REMEMBERED_SOURCES: usize = 200;
+
+// The settings last used with a source, keyed by the source's SHA-256 so renamed files still match
+#[derive(serde::Deserialize, serde::Serialize)]
+struct RememberedSource {
+    times: ProjectTimes,
+    // Seconds since the Unix epoch
+    last_used: u64,
 }
 
+// Times are kept in seconds, the units only decide how they are displayed
 #[derive(Default)]
 struct AppTimes {
-    start_time: u32,
-    end_time: u32,
-    crossfade_duration: u16,
+    start_time: f64,
+    end_time: f64,
+    crossfade_duration: f64,
+    crossfade_placement: CrossfadePlacement,
+    crossfade_curve: CrossfadeCurve,
     loop_count: u8,
 }
 
+// Everything a project file holds besides the source and times
+#[derive(Default)]
+struct AppProject {
+    // Where the project was last opened from or saved to
+    path: Option<PathBuf>,
+    regions: Vec<Region>,
+    notes: String,
+    output_path: Option<PathBuf>,
+    // The project's output settings, the settings' until one is opened or they change
+    output_format: Option<OutputFormat>,
+    mp3_quality: Option<u8>,
+    // Name typed in for the next region
+    region_name: String,
+}
+
+// The part of the app state whose edits can be undone
+#[derive(Clone, PartialEq)]
+struct EditState {
+    times: ProjectTimes,
+    // The units the start, end and crossfade are shown in
+    units: [Unit; 3],
+    regions: Vec<Region>,
+}
+
+#[derive(Default)]
+struct AppTimeInputs {
+    start: TimeInput,
+    end: TimeInput,
+    crossfade: TimeInput,
+}
+
 #[derive(Default)]
 struct AppToolState {
     ffmpeg_path_check: bool,
     ffmpeg_loading: bool,
+    ffmpeg_status: String,
+    ffmpeg_progress: Option<(u64, Option<u64>)>,
+    // Checked once per ffmpeg path, None until the current path has been checked
+    ffmpeg_capabilities: Option<CapabilitiesResult>,
 }
 
 #[derive(Default)]
@@ -67,20 +200,63 @@ struct AppError {
 
 #[derive(Default)]
 struct AppChannels {
-    ffmpeg_rx: Option<std::sync::mpsc::Receiver<Result<std::path::PathBuf, String>>>,
-    running_rx: Option<std::sync::mpsc::Receiver<Result<ConsoleText, String>>>,
-    running_finished: Option<std::sync::mpsc::Receiver<Result<bool, String>>>,
+    ffmpeg_rx: Option<std::sync::mpsc::Receiver<ProvisionEvent>>,
+    job_rx: Option<std::sync::mpsc::Receiver<JobEvent>>,
+    source_rx: Option<std::sync::mpsc::Receiver<SourceDetails>>,
+    // The ffmpeg path being checked and what the check found
+    capabilities_rx: Option<std::sync::mpsc::Receiver<(String, CapabilitiesResult)>>,
+}
+
+type CapabilitiesResult = Result<ffmpeg::Capabilities, String>;
+
+// What probing and hashing a source found, worked out on a worker thread
+struct SourceDetails {
+    path: PathBuf,
+    info: MediaInfo,
+    sha256: Option<String>,
+    // Whether the settings remembered for the source and its recipe are offered once it is loaded
+    restore: bool,
+}
+
+#[derive(Default)]
+struct AppJob {
+    step: String,
+    progress: Option<(usize, usize)>,
+    // What is being rendered and when it started, for the job history
+    spec: Option<LoopSpec>,
+    started: Option<(std::time::Instant, u64)>,
+    cancel: Option<CancelToken>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Default)]
 pub struct App {
     tools: AppToolPaths,
-    units: AppUnits,
+    #[serde(default, rename = "units", skip_serializing)]
+    legacy_units: LegacyUnits,
+    #[serde(default)]
+    remembered_sources: HashMap<String, RememberedSource>,
+    #[serde(default)]
+    settings: Settings,
 
     #[serde(skip)]
     file: egui::DroppedFile,
     #[serde(skip)]
+    source_info: MediaInfo,
+    // SHA-256 of the current source, None if it could not be read
+    #[serde(skip)]
+    source_hash: Option<String>,
+    #[serde(skip)]
     times: AppTimes,
+    #[serde(skip)]
+    time_inputs: AppTimeInputs,
+    #[serde(skip)]
+    project: AppProject,
+    // Created from the state on the first frame
+    #[serde(skip)]
+    history: Option<History<EditState>>,
+    // A dropped loop whose recipe can be restored, and the path it was dropped from
+    #[serde(skip)]
+    recipe_prompt: Option<(PathBuf, Recipe)>,
 
     #[serde(skip)]
     error: AppError,
@@ -92,21 +268,46 @@ pub struct App {
     #[serde(skip)]
     file_load: bool,
     #[serde(skip)]
+    job: AppJob,
+    #[serde(skip)]
     running: bool,
     #[serde(skip)]
     success: bool,
     #[serde(skip)]
     console: Vec<ConsoleText>,
+    #[serde(skip)]
+    console_view: ConsoleView,
+    // Loaded the first time it is needed
+    #[serde(skip)]
+    job_history: Option<JobHistory>,
+    #[serde(skip)]
+    selected_job: Option<u64>,
+    // An existing output a render is waiting on permission to replace, and whether it is a test
+    #[serde(skip)]
+    overwrite_prompt: Option<(PathBuf, bool)>,
+    #[serde(skip)]
+    settings_window: bool,
 }
 
 impl App {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         // Load previous app state (if any).
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+        let mut app: App = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+            .unwrap_or_default();
+        let legacy_units = std::mem::take(&mut app.legacy_units);
+        if legacy_units != LegacyUnits::default() {
+            app.settings.start_unit = legacy_units.start_unit;
+            app.settings.end_unit = legacy_units.end_unit;
+            app.settings.crossfade_unit = legacy_units.crossfade_unit;
         }
-
-        Default::default()
+        // Each session starts from the default crossfade and console filters
+        app.times.crossfade_duration = app.settings.crossfade_s;
+        app.times.crossfade_placement = app.settings.placement;
+        app.times.crossfade_curve = app.settings.curve;
+        app.console_view.set_verbosity(app.settings.console);
+        app
     }
 
     fn handle_inputs(&mut self, ctx: &egui::Context) {
@@ -127,12 +328,16 @@ impl App {
                     },
                     None => "".to_string(),
                 };
-                if target_file.name.ends_with(".wav")
+                if path == project::PROJECT_EXTENSION {
+                    if let Some(project_path) = target_file.path.clone() {
+                        self.load_project(&project_path);
+                    }
+                } else if target_file.name.ends_with(".wav")
                     || target_file.name.ends_with(".mp3")
                     || path.ends_with("wav")
                     || path.ends_with("mp3")
                 {
-                    self.file = target_file.clone();
+                    self.set_source(target_file.clone(), true);
                 } else {
                     self.error.message = format!(
                         "You can only use .wav or .mp3 files. Your file was: {}",
@@ -144,219 +349,1423 @@ impl App {
         });
     }
 
+    // Switches to a new source, probing and hashing it on a worker thread.
+    // With `restore`, the settings remembered for it and its recipe are offered once that is done.
+    fn set_source(&mut self, file: egui::DroppedFile, restore: bool) {
+        // The settings used with the old source are remembered before they are replaced
+        self.remember_source_settings();
+        self.file = file;
+        self.source_info = MediaInfo::default();
+        self.source_hash = None;
+        // Replacing the receiver drops whatever an earlier source was still working out
+        self.channels.source_rx = None;
+        let path = match &self.file.path {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.channels.source_rx = Some(rx);
+        let ffmpeg_path = self.tools.ffmpeg_path.clone();
+        std::thread::spawn(move || {
+            let info = ffmpeg::probe(&ffmpeg_path, &path.display().to_string()).unwrap_or_default();
+            let sha256 = hash::sha256_file(&path).ok();
+            let _ = tx.send(SourceDetails {
+                path,
+                info,
+                sha256,
+                restore,
+            });
+        });
+    }
+
+    // Takes in the details of the source once its worker has them
+    fn receive_source(&mut self, ctx: &egui::Context) {
+        let details = match &self.channels.source_rx {
+            Some(rx) => match rx.try_recv() {
+                Ok(details) => details,
+                Err(std::sync::mpsc::TryRecvError::Empty) => {
+                    ctx.request_repaint();
+                    return;
+                }
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    self.channels.source_rx = None;
+                    return;
+                }
+            },
+            None => return,
+        };
+        self.channels.source_rx = None;
+        if self.file.path.as_ref() != Some(&details.path) {
+            return;
+        }
+        self.source_info = details.info;
+        self.source_hash = details.sha256;
+        if details.restore {
+            self.restore_source_settings();
+            self.offer_recipe();
+        }
+    }
+
+    // Records the current times against the current source
+    fn remember_source_settings(&mut self) {
+        let hash = match &self.source_hash {
+            Some(hash) => hash.clone(),
+            None => return,
+        };
+        let last_used = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.remembered_sources.insert(
+            hash,
+            RememberedSource {
+                times: self.project_times(),
+                last_used,
+            },
+        );
+        while self.remembered_sources.len() > REMEMBERED_SOURCES {
+            let oldest = self
+                .remembered_sources
+                .iter()
+                .min_by_key(|(_, remembered)| remembered.last_used)
+                .map(|(hash, _)| hash.clone());
+            match oldest {
+                Some(hash) => self.remembered_sources.remove(&hash),
+                None => break,
+            };
+        }
+    }
+
+    // Restores the times last used with the current source, if it has been used before
+    fn restore_source_settings(&mut self) {
+        let times = self
+            .source_hash
+            .as_ref()
+            .and_then(|hash| self.remembered_sources.get(hash))
+            .map(|remembered| remembered.times.clone());
+        if let Some(times) = times {
+            self.set_times(&times);
+            self.console.push(ConsoleText::Program(
+                "Restored the settings last used with this file.".to_string(),
+            ));
+        }
+    }
+
+    // Offers to restore the recipe if the current source is a loop EchoBlend rendered
+    fn offer_recipe(&mut self) {
+        let recipe = self
+            .source_info
+            .comment
+            .as_deref()
+            .and_then(Recipe::from_comment);
+        self.recipe_prompt = self.file.path.clone().zip(recipe);
+    }
+
+    pub fn recipe_prompt(&self) -> Option<&Recipe> {
+        self.recipe_prompt.as_ref().map(|(_, recipe)| recipe)
+    }
+
+    pub fn dismiss_recipe(&mut self) {
+        self.recipe_prompt = None;
+    }
+
+    /// Opens the song the dropped loop was rendered from with the settings it was rendered with
+    pub fn restore_recipe(&mut self) {
+        let (rendered, recipe) = match self.recipe_prompt.take() {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        let dir = rendered
+            .parent()
+            .unwrap_or(std::path::Path::new("."))
+            .to_path_buf();
+        // The song is usually still next to its loop, otherwise ask where it went
+        let source = recipe.find_source(&dir).or_else(|| {
+            rfd::FileDialog::new()
+                .set_title(format!("Locate {}", recipe.source_name))
+                .add_filter("Audio File", &["wav", "mp3"])
+                .set_directory(&dir)
+                .set_file_name(&recipe.source_name)
+                .pick_file()
+        });
+        let source = match source {
+            Some(source) => source,
+            None => return,
+        };
+        if !recipe.is_source(&source) {
+            self.error.message = format!(
+                "{} is not the file {} was rendered from, so the loop points may not line up.",
+                source.display(),
+                rendered.display()
+            );
+            self.error.window = true;
+        }
+        self.set_source(source_file(&source), false);
+        self.set_times(&recipe.times);
+        self.console.push(ConsoleText::Program(format!(
+            "Restored the recipe of {}, rendered by EchoBlend {}.",
+            rendered.display(),
+            recipe.echoblend_version
+        )));
+    }
+
+    fn project_times(&self) -> ProjectTimes {
+        ProjectTimes {
+            start_s: self.times.start_time,
+            end_s: self.times.end_time,
+            crossfade_s: self.times.crossfade_duration,
+            placement: self.times.crossfade_placement,
+            curve: self.times.crossfade_curve,
+            loop_count: self.times.loop_count,
+        }
+    }
+
+    fn set_times(&mut self, times: &ProjectTimes) {
+        self.times = AppTimes {
+            start_time: times.start_s,
+            end_time: times.end_s,
+            crossfade_duration: times.crossfade_s,
+            crossfade_placement: times.placement,
+            crossfade_curve: times.curve,
+            loop_count: times.loop_count,
+        };
+        self.time_inputs = AppTimeInputs::default();
+    }
+
     pub fn ffmpeg_button_functionality(&mut self) {
         if let Some(path) = rfd::FileDialog::new().pick_file() {
             if let Err(e) = std::process::Command::new(&path).output() {
                 self.error.message = format!("Failed to run FFMPEG: {}", e);
                 self.error.window = true;
             } else {
-                self.tools.ffmpeg_path = path.display().to_string();
+                self.set_ffmpeg_path(path.display().to_string());
+            }
+        }
+    }
+
+    fn set_ffmpeg_path(&mut self, path: String) {
+        self.tools.ffmpeg_path = path;
+        self.tool_state.ffmpeg_capabilities = None;
+    }
+
+    // Check the version, filters and encoders of the current ffmpeg on a worker thread if it hasn't been checked yet
+    fn check_ffmpeg(&mut self, ctx: &egui::Context) {
+        if self.tools.ffmpeg_path.is_empty() || self.tool_state.ffmpeg_capabilities.is_some() {
+            return;
+        }
+        if let Some(rx) = &self.channels.capabilities_rx {
+            match rx.try_recv() {
+                // A check of a path that has since been replaced is dropped and the new path checked
+                Ok((path, capabilities)) => {
+                    if path == self.tools.ffmpeg_path {
+                        self.tool_state.ffmpeg_capabilities = Some(capabilities);
+                    }
+                }
+                Err(std::sync::mpsc::TryRecvError::Empty) => {
+                    ctx.request_repaint();
+                    return;
+                }
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    self.tool_state.ffmpeg_capabilities =
+                        Some(Err("The check stopped unexpectedly".to_string()));
+                }
             }
+            self.channels.capabilities_rx = None;
+            ctx.request_repaint();
+            return;
         }
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.channels.capabilities_rx = Some(rx);
+        let ffmpeg_path = self.tools.ffmpeg_path.clone();
+        std::thread::spawn(move || {
+            let capabilities = ffmpeg::detect_capabilities(&ffmpeg_path);
+            let _ = tx.send((ffmpeg_path, capabilities));
+        });
+        ctx.request_repaint();
     }
 
     pub fn can_loop(&self) -> Result<(), String> {
+        self.can_render()?;
+        if self.file.path.is_none() {
+            return Err("Please provide a file to loop.".to_string());
+        }
+        // The output name and the checks below need the song's tags and length
+        if self.channels.source_rx.is_some() {
+            return Err("The song is still being read.".to_string());
+        }
+        self.check_time_inputs()?;
+
+        looper::validate_times(
+            self.get_time_var_ms(TimeVariable::Start),
+            self.get_time_var_ms(TimeVariable::End),
+            self.get_time_var_ms(TimeVariable::Crossfade),
+            self.times.crossfade_placement,
+            self.source_info.duration_s,
+        )
+    }
+
+    // A time field that doesn't parse still holds its last valid time, which must not be rendered
+    fn check_time_inputs(&self) -> Result<(), String> {
+        for (name, input) in [
+            ("start time", &self.time_inputs.start),
+            ("end time", &self.time_inputs.end),
+            ("crossfade", &self.time_inputs.crossfade),
+        ] {
+            if let Some(error) = &input.error {
+                return Err(format!("Fix the {} first: {}", name, error));
+            }
+        }
+        Ok(())
+    }
+
+    // Whether a job can be started, whatever the source and times
+    fn can_render(&self) -> Result<(), String> {
         if self.running {
             return Err("A loop is already running.".to_string());
         }
         if self.tools.ffmpeg_path.is_empty() {
             return Err("Please provide the path to the FFMPEG executable.".to_string());
         }
-        if self.file.path.is_none() {
-            return Err("Please provide a file to loop.".to_string());
+        match &self.tool_state.ffmpeg_capabilities {
+            Some(Err(e)) => return Err(format!("FFMPEG could not be checked: {}", e)),
+            Some(Ok(capabilities)) => {
+                if let Some(missing) = capabilities.missing_summary() {
+                    return Err(format!(
+                        "The selected FFMPEG cannot create loops. {}.",
+                        missing
+                    ));
+                }
+            }
+            None => return Err("FFMPEG is still being checked.".to_string()),
         }
+        Ok(())
+    }
 
-        let start = self.get_time_var_ms(TimeVariable::Start);
-        let end = self.get_time_var_ms(TimeVariable::End);
-        let crossfade = self.get_time_var_ms(TimeVariable::Crossfade);
-        if start >= end {
-            return Err(format!(
-                "The start time must be less than the end time. Start: {}, End: {}",
-                start, end
-            ));
+    pub fn open_file_dialog_and_create_loop(&mut self, file_name: &str, test_loop: bool) {
+        let mut dialog = rfd::FileDialog::new().add_filter("WAV File", &["wav"]);
+        if self.supports_mp3_output() {
+            dialog = dialog.add_filter("MP3 File", &["mp3"]);
         }
-        if crossfade >= start {
-            return Err(format!(
-                "The crossfade duration must be less than the start time. Crossfade: {}, Start: {}",
-                crossfade, start
-            ));
+        // Suggest a name from the template, in the folder and format of the loop's last output
+        let (directory, extension) = match &self.project.output_path {
+            Some(output) => (
+                output
+                    .parent()
+                    .unwrap_or(std::path::Path::new("."))
+                    .to_path_buf(),
+                output
+                    .extension()
+                    .map_or("wav".to_string(), |ext| ext.to_string_lossy().into_owned()),
+            ),
+            None => (self.default_output_dir(), self.default_format().to_string()),
+        };
+        let name = match self.output_name() {
+            Ok(name) if test_loop => format!("{}_test", name),
+            Ok(name) => name,
+            Err(_) => file_name.to_string(),
+        };
+        dialog = dialog
+            .set_file_name(format!("{}.{}", name, extension))
+            .set_directory(directory);
+        if let Some(path) = dialog.save_file() {
+            // The save dialog has already asked about replacing an existing file
+            let policy = match self.settings.overwrite {
+                OverwritePolicy::Ask => OverwritePolicy::Overwrite,
+                policy => policy,
+            };
+            self.create_loop_as(&path, test_loop, policy);
         }
-        if crossfade >= end - start {
-            return Err(format!("The crossfade duration must be less than the loop duration. Crossfade: {}, Loop Duration: {}", crossfade, end - start));
+    }
+
+    // The output folder from the settings, or the working directory if it is not set
+    fn default_output_dir(&self) -> PathBuf {
+        match self.settings.output_dir.trim() {
+            "" => std::env::current_dir().unwrap_or_default(),
+            dir => PathBuf::from(dir),
         }
-        Ok(())
     }
 
-    pub fn open_file_dialog_and_create_loop(&mut self, file_name: &str, test_loop: bool) {
-        self.console.clear();
+    // The format of the loop's last output, or else of the project or the settings
+    fn output_format(&self) -> OutputFormat {
+        self.project
+            .output_path
+            .as_deref()
+            .and_then(OutputFormat::from_path)
+            .or(self.project.output_format)
+            .unwrap_or(self.settings.format)
+    }
+
+    // The extension of the output format, WAV if the selected ffmpeg can't write MP3
+    fn default_format(&self) -> &'static str {
+        match self.output_format() {
+            OutputFormat::Mp3 if self.supports_mp3_output() => OutputFormat::Mp3.extension(),
+            _ => OutputFormat::Wav.extension(),
+        }
+    }
+
+    // The file name the template gives with the current settings, without an extension
+    fn output_name(&self) -> Result<String, String> {
+        let input = match &self.file.path {
+            Some(input) => input,
+            None => return Err("Please provide a file to loop.".to_string()),
+        };
+        let duration_s = self.loop_plan().map_or(0.0, |sections| {
+            sections
+                .last()
+                .map_or(0.0, |last| f64::from(last.start_s + last.duration_s))
+        });
+        naming::expand(
+            &self.settings.name_template,
+            &NameValues {
+                input,
+                title: self.source_info.title.as_deref(),
+                loop_count: self.times.loop_count,
+                duration_s,
+                start_s: self.times.start_time,
+                end_s: self.times.end_time,
+            },
+        )
+    }
+
+    // Renders into `output`, unless it exists and `policy` says otherwise
+    fn create_loop_as(
+        &mut self,
+        output: &std::path::Path,
+        test_loop: bool,
+        policy: OverwritePolicy,
+    ) {
+        if policy == OverwritePolicy::Ask && output.exists() {
+            self.overwrite_prompt = Some((output.to_path_buf(), test_loop));
+            return;
+        }
+        match naming::resolve_output(output, policy) {
+            Some(output) => self.create_loop(output, test_loop),
+            None => self.console.push(ConsoleText::Program(format!(
+                "Skipped, {} already exists.",
+                output.display()
+            ))),
+        }
+    }
+
+    /// The existing file a render is waiting on permission to replace
+    pub fn overwrite_prompt(&self) -> Option<&std::path::Path> {
+        self.overwrite_prompt
+            .as_ref()
+            .map(|(path, _)| path.as_path())
+    }
+
+    /// Answers the overwrite prompt, `None` cancelling the render
+    pub fn answer_overwrite(&mut self, policy: Option<OverwritePolicy>) {
+        if let (Some((output, test_loop)), Some(policy)) = (self.overwrite_prompt.take(), policy) {
+            self.create_loop_as(&output, test_loop, policy);
+        }
+    }
+
+    /// What the template names the loop with the current settings, or why it can't
+    pub fn output_name_preview(&self) -> Result<String, String> {
+        self.output_name()
+    }
+
+    // Renders the current source with the current settings into `output`
+    fn create_loop(&mut self, output: PathBuf, test_loop: bool) {
+        let input = match &self.file.path {
+            Some(input) => input.clone(),
+            None => return,
+        };
+        if output.extension().is_some_and(|ext| ext == "mp3") && !self.supports_mp3_output() {
+            self.error.message =
+                "The selected FFMPEG has no MP3 encoder (libmp3lame), save the loop as .wav instead."
+                    .to_string();
+            self.error.window = true;
+            return;
+        }
+        self.clear_console();
         self.success = false;
+        if !test_loop {
+            self.project.output_path = Some(output.clone());
+        }
+        self.remember_source_settings();
+        let mut builder = self
+            .spec_builder(input, output)
+            .mp3_quality(self.mp3_quality())
+            .test(test_loop)
+            .keep_intermediates(self.settings.keep_intermediates);
+        let scratch_dir = self.settings.scratch_dir.trim();
+        if !scratch_dir.is_empty() {
+            builder = builder.scratch_dir(scratch_dir);
+        }
+        let spec = match builder.build() {
+            Ok(spec) => spec,
+            Err(e) => {
+                self.error.message = e;
+                self.error.window = true;
+                return;
+            }
+        };
+        self.running = true;
+        let started_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        self.job = AppJob {
+            spec: Some(spec.clone()),
+            started: Some((std::time::Instant::now(), started_at)),
+            ..Default::default()
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.channels.job_rx = Some(rx);
+        self.job.cancel = Some(render::spawn(
+            spec,
+            FfmpegBackend::new(self.tools.ffmpeg_path.clone()),
+            tx,
+        ));
+    }
+
+    // Adds the job that just ended, and its whole log, to the job history
+    fn record_job(&mut self, outcome: JobOutcome, outputs: Vec<PathBuf>) {
+        let (spec, (started, id)) = match (self.job.spec.take(), self.job.started.take()) {
+            (Some(spec), Some(started)) => (spec, started),
+            _ => return,
+        };
+        let record = JobRecord {
+            id,
+            input: spec.input().to_path_buf(),
+            output: spec.output().to_path_buf(),
+            test: spec.is_test(),
+            times: ProjectTimes {
+                start_s: spec.start_s(),
+                end_s: spec.end_s(),
+                crossfade_s: spec.crossfade_s(),
+                placement: spec.placement(),
+                curve: spec.curve(),
+                loop_count: spec.loop_count(),
+            },
+            outputs,
+            duration_s: started.elapsed().as_secs_f64(),
+            outcome,
+            log: self.console.clone(),
+        };
+        if let Err(e) = self.job_history_mut().add(record) {
+            self.console.push(ConsoleText::Stdout(format!(
+                "The job was not kept in the history: {}",
+                e
+            )));
+        }
+    }
+
+    pub fn job_history_mut(&mut self) -> &mut JobHistory {
+        self.job_history.get_or_insert_with(|| {
+            // Kept next to the downloaded ffmpeg, or in the working directory if there is no data folder
+            let dir = directories_next::ProjectDirs::from("", "", "Echo Blend")
+                .map_or_else(|| PathBuf::from("."), |dirs| dirs.data_dir().to_path_buf())
+                .join("history");
+            JobHistory::load(&dir)
+        })
+    }
+
+    pub fn selected_job_mut(&mut self) -> &mut Option<u64> {
+        &mut self.selected_job
+    }
+
+    fn job_record(&mut self, id: u64) -> Option<JobRecord> {
+        self.job_history_mut()
+            .records()
+            .iter()
+            .find(|record| record.id == id)
+            .cloned()
+    }
+
+    /// Opens a past job's song with the settings it was rendered with
+    pub fn reopen_job(&mut self, id: u64) -> bool {
+        let record = match self.job_record(id) {
+            Some(record) => record,
+            None => return false,
+        };
+        if !record.input.exists() {
+            self.error.message = format!("{} no longer exists.", record.input.display());
+            self.error.window = true;
+            return false;
+        }
+        if self.file.path.as_ref() != Some(&record.input) {
+            self.set_source(source_file(&record.input), false);
+        }
+        self.set_times(&record.times);
+        true
+    }
+
+    /// Renders a past job again with the same song, settings and output
+    pub fn rerun_job(&mut self, id: u64) {
+        if let Err(e) = self.can_render() {
+            self.error.message = e;
+            self.error.window = true;
+            return;
+        }
+        let record = match self.job_record(id) {
+            Some(record) => record,
+            None => return,
+        };
+        if self.reopen_job(id) {
+            self.create_loop_as(&record.output, record.test, self.settings.overwrite);
+        }
+    }
+
+    /// Shows a file in the system's file manager
+    pub fn reveal(&mut self, path: &std::path::Path) {
+        let result = if cfg!(target_os = "windows") {
+            std::process::Command::new("explorer")
+                .arg(format!("/select,{}", path.display()))
+                .spawn()
+        } else if cfg!(target_os = "macos") {
+            std::process::Command::new("open")
+                .arg("-R")
+                .arg(path)
+                .spawn()
+        } else {
+            // Most Linux file managers cannot select a file, so its folder is opened instead
+            std::process::Command::new("xdg-open")
+                .arg(path.parent().unwrap_or(std::path::Path::new(".")))
+                .spawn()
+        };
+        if let Err(e) = result {
+            self.error.message = format!("Failed to show {}: {}", path.display(), e);
+            self.error.window = true;
+        }
+    }
+
+    fn spec_builder(&self, input: PathBuf, output: PathBuf) -> LoopSpecBuilder {
+        LoopSpec::builder(input, output)
+            .start(self.times.start_time)
+            .end(self.times.end_time)
+            .crossfade(self.times.crossfade_duration)
+            .placement(self.times.crossfade_placement)
+            .curve(self.times.crossfade_curve)
+            .loop_count(self.times.loop_count)
+    }
+
+    /// The parts the loop will be rendered from with the current settings, or why it can't be
+    pub fn loop_plan(&self) -> Result<Vec<Section>, String> {
+        let input = match &self.file.path {
+            Some(path) => path.clone(),
+            None => return Err("Drop a song to preview its loop.".to_string()),
+        };
+        self.check_time_inputs()?;
+        let duration_s = match self.source_info.duration_s {
+            Some(duration_s) => duration_s,
+            None => return Err("The length of the song is not known yet.".to_string()),
+        };
+        looper::validate_times(
+            self.get_time_var_ms(TimeVariable::Start),
+            self.get_time_var_ms(TimeVariable::End),
+            self.get_time_var_ms(TimeVariable::Crossfade),
+            self.times.crossfade_placement,
+            Some(duration_s),
+        )?;
+        // The output only has to be named for the spec to build, the plan doesn't depend on it
+        let spec = self
+            .spec_builder(input, PathBuf::from("preview.wav"))
+            .build()?;
+        Ok(looper::plan(&spec, duration_s))
+    }
+
+    // The commands rendering the current loop would run, written to its last output or next to the song
+    fn render_script(&self) -> Result<RenderScript, String> {
+        let input = match &self.file.path {
+            Some(path) => path.clone(),
+            None => return Err("Please provide a file to loop.".to_string()),
+        };
+        self.check_time_inputs()?;
+        let output = self.project.output_path.clone().unwrap_or_else(|| {
+            let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+            input.with_file_name(format!("{}_loop.{}", stem, self.default_format()))
+        });
+        let spec = self
+            .spec_builder(input, output)
+            .mp3_quality(self.mp3_quality())
+            .keep_intermediates(self.settings.keep_intermediates)
+            .build()?;
+        RenderScript::new(&spec, &self.tools.ffmpeg_path)
+    }
+
+    /// Copies a script of the render commands for this system's shell to the clipboard
+    pub fn copy_commands(&mut self, ctx: &egui::Context) {
+        match self.render_script() {
+            Ok(script) => {
+                ctx.output_mut(|output| output.copied_text = script.to_script(Shell::native()));
+                self.console.push(ConsoleText::Program(
+                    "Copied the render commands to the clipboard".to_string(),
+                ));
+            }
+            Err(e) => {
+                self.error.message = e;
+                self.error.window = true;
+            }
+        }
+    }
+
+    /// Saves the render commands as a shell or PowerShell script, picked by the file's extension
+    pub fn export_script(&mut self) {
+        let script = match self.render_script() {
+            Ok(script) => script,
+            Err(e) => {
+                self.error.message = e;
+                self.error.window = true;
+                return;
+            }
+        };
+        let mut dialog = rfd::FileDialog::new();
+        dialog = match Shell::native() {
+            Shell::Posix => dialog
+                .add_filter("Shell Script", &["sh"])
+                .add_filter("PowerShell Script", &["ps1"])
+                .set_file_name("render_loop.sh"),
+            Shell::PowerShell => dialog
+                .add_filter("PowerShell Script", &["ps1"])
+                .add_filter("Shell Script", &["sh"])
+                .set_file_name("render_loop.ps1"),
+        };
+        let path = match dialog.save_file() {
+            Some(path) => path,
+            None => return,
+        };
+        match std::fs::write(&path, script.to_script(Shell::for_path(&path))) {
+            Ok(_) => self.console.push(ConsoleText::Program(format!(
+                "Saved render script {}",
+                path.display()
+            ))),
+            Err(e) => {
+                self.error.message = format!("Failed to save {}: {}", path.display(), e);
+                self.error.window = true;
+            }
+        }
+    }
+
+    pub fn source_info(&self) -> &MediaInfo {
+        &self.source_info
+    }
+
+    // The current session as a project, hashing the source
+    fn to_project(&self) -> Result<Project, String> {
+        let source = self
+            .file
+            .path
+            .clone()
+            .ok_or("Load a file before saving a project.")?;
+        let mut project = Project::new(source)?;
+        project.times = self.project_times();
+        project.regions = self.project.regions.clone();
+        project.output.path = self.project.output_path.clone();
+        project.output.format = Some(self.output_format());
+        project.output.mp3_quality = Some(self.mp3_quality());
+        project.notes = self.project.notes.clone();
+        Ok(project)
+    }
+
+    /// Saves to the project's file, asking where first if it has none or `save_as` is set
+    pub fn save_project(&mut self, save_as: bool) {
+        let path = match &self.project.path {
+            Some(path) if !save_as => Some(path.clone()),
+            _ => {
+                let name = self
+                    .file
+                    .path
+                    .as_ref()
+                    .and_then(|source| source.file_stem())
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "project".to_string());
+                rfd::FileDialog::new()
+                    .add_filter("EchoBlend Project", &[project::PROJECT_EXTENSION])
+                    .set_file_name(format!("{}.{}", name, project::PROJECT_EXTENSION))
+                    .save_file()
+            }
+        };
+        let path = match path {
+            Some(path) => path,
+            None => return,
+        };
+        match self.to_project().and_then(|project| project.save(&path)) {
+            Ok(_) => {
+                self.console.push(ConsoleText::Program(format!(
+                    "Saved project {}",
+                    path.display()
+                )));
+                self.project.path = Some(path);
+            }
+            Err(e) => {
+                self.error.message = e;
+                self.error.window = true;
+            }
+        }
+    }
+
+    pub fn open_project(&mut self) {
         if let Some(path) = rfd::FileDialog::new()
-            .add_filter("WAV File", &["wav"])
-            .add_filter("MP3 File", &["mp3"])
-            .set_file_name(file_name)
-            .set_directory(std::env::current_dir().unwrap())
-            .save_file()
+            .add_filter("EchoBlend Project", &[project::PROJECT_EXTENSION])
+            .pick_file()
         {
-            self.running = true;
-            let (tx, rx) = std::sync::mpsc::channel();
-            let (tx_finish, rx_finish) = std::sync::mpsc::channel();
-            self.channels.running_rx = Some(rx);
-            self.channels.running_finished = Some(rx_finish);
-            looper::create_loop(
-                self.get_time_var_s(TimeVariable::Start),
-                self.get_time_var_s(TimeVariable::End),
-                self.get_time_var_s(TimeVariable::Crossfade),
-                self.times.loop_count,
-                self.tools.ffmpeg_path.clone(),
-                self.file.path.clone().unwrap().display().to_string(),
-                path.display().to_string(),
-                tx,
-                tx_finish,
-                test_loop,
-            );
+            self.load_project(&path);
         }
     }
 
-    pub fn get_time_var_ms(&self, var: TimeVariable) -> u32 {
-        let ms = match var {
-            TimeVariable::Start => self.times.start_time,
-            TimeVariable::End => self.times.end_time,
-            TimeVariable::Crossfade => self.times.crossfade_duration as u32,
+    fn load_project(&mut self, path: &std::path::Path) {
+        let project = match Project::load(path) {
+            Ok(project) => project,
+            Err(e) => {
+                self.error.message = e;
+                self.error.window = true;
+                return;
+            }
         };
-        ms * match var {
-            TimeVariable::Start => match self.units.start_unit {
-                Unit::Milliseconds => 1,
-                Unit::Seconds => 1000,
-            },
-            TimeVariable::End => match self.units.end_unit {
-                Unit::Milliseconds => 1,
-                Unit::Seconds => 1000,
-            },
-            TimeVariable::Crossfade => match self.units.crossfade_unit {
-                Unit::Milliseconds => 1,
-                Unit::Seconds => 1000,
-            },
+        match project.source_status() {
+            SourceStatus::Unchanged => {}
+            SourceStatus::Changed => {
+                self.error.message = format!(
+                    "{} has changed since the project was saved. Check the loop points still line up.",
+                    project.source.path.display()
+                );
+                self.error.window = true;
+            }
+            SourceStatus::Missing => {
+                self.error.message = format!(
+                    "The project's song {} could not be found. Drop it onto the window to use the project's settings with it.",
+                    project.source.path.display()
+                );
+                self.error.window = true;
+            }
         }
+        if project.source.path.exists() {
+            self.set_source(source_file(&project.source.path), false);
+        }
+        self.set_times(&project.times);
+        self.project = AppProject {
+            path: Some(path.to_path_buf()),
+            regions: project.regions,
+            notes: project.notes,
+            output_path: project.output.path,
+            output_format: project.output.format,
+            mp3_quality: project.output.mp3_quality,
+            region_name: String::new(),
+        };
+        self.success = false;
+    }
+
+    pub fn has_source(&self) -> bool {
+        self.file.path.is_some()
+    }
+
+    pub fn project_path(&self) -> Option<&std::path::Path> {
+        self.project.path.as_deref()
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.project.regions
+    }
+
+    /// Remembers the current loop points under the typed in name
+    pub fn add_region(&mut self) {
+        let name = match self.project.region_name.trim() {
+            "" => format!("Region {}", self.project.regions.len() + 1),
+            name => name.to_string(),
+        };
+        self.project.regions.push(Region {
+            name,
+            start_s: self.times.start_time,
+            end_s: self.times.end_time,
+        });
+        self.project.region_name.clear();
+    }
+
+    pub fn remove_region(&mut self, index: usize) {
+        if index < self.project.regions.len() {
+            self.project.regions.remove(index);
+        }
+    }
+
+    /// Sets the loop points to a region's
+    pub fn use_region(&mut self, index: usize) {
+        if let Some(region) = self.project.regions.get(index) {
+            self.times.start_time = region.start_s;
+            self.times.end_time = region.end_s;
+            self.time_inputs = AppTimeInputs::default();
+        }
+    }
+
+    pub fn region_name_mut(&mut self) -> &mut String {
+        &mut self.project.region_name
+    }
+
+    pub fn notes_mut(&mut self) -> &mut String {
+        &mut self.project.notes
+    }
+
+    fn edit_state(&self) -> EditState {
+        EditState {
+            times: self.project_times(),
+            units: [
+                self.settings.start_unit,
+                self.settings.end_unit,
+                self.settings.crossfade_unit,
+            ],
+            regions: self.project.regions.clone(),
+        }
+    }
+
+    fn apply_edit_state(&mut self, state: EditState) {
+        self.set_times(&state.times);
+        [
+            self.settings.start_unit,
+            self.settings.end_unit,
+            self.settings.crossfade_unit,
+        ] = state.units;
+        self.project.regions = state.regions;
+    }
+
+    // Records any edit made since the last entry, once it is finished
+    fn track_history(&mut self, ctx: &egui::Context) {
+        // Typing a time or dragging a value is one edit however many frames it takes
+        if ctx.wants_keyboard_input() || ctx.input(|i| i.pointer.any_down()) {
+            return;
+        }
+        self.record_edit();
+    }
+
+    fn record_edit(&mut self) {
+        let state = self.edit_state();
+        let history = self
+            .history
+            .get_or_insert_with(|| History::new(state.clone()));
+        if *history.current() != state {
+            let label = describe_edit(history.current(), &state);
+            history.record(label, state);
+        }
+    }
+
+    pub fn undo(&mut self) {
+        self.record_edit();
+        if let Some(state) = self.history.as_mut().and_then(|h| h.undo()).cloned() {
+            self.apply_edit_state(state);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        self.record_edit();
+        if let Some(state) = self.history.as_mut().and_then(|h| h.redo()).cloned() {
+            self.apply_edit_state(state);
+        }
+    }
+
+    pub fn jump_history(&mut self, index: usize) {
+        self.record_edit();
+        if let Some(state) = self.history.as_mut().and_then(|h| h.jump(index)).cloned() {
+            self.apply_edit_state(state);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.as_ref().is_some_and(|h| h.can_undo())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.as_ref().is_some_and(|h| h.can_redo())
+    }
+
+    /// The label of every history entry, oldest first, and the index of the current one
+    pub fn history_labels(&self) -> (Vec<String>, usize) {
+        self.history.as_ref().map_or((Vec::new(), 0), |h| {
+            let (labels, current) = h.labels();
+            (labels.into_iter().map(str::to_string).collect(), current)
+        })
+    }
+
+    pub fn get_time_var_ms(&self, var: TimeVariable) -> u32 {
+        (f64::from(self.get_time_var_s(var)) * 1000.0).round() as u32
     }
 
     pub fn get_time_var_s(&self, var: TimeVariable) -> f32 {
-        self.get_time_var_ms(var) as f32 / 1000.0
+        (match var {
+            TimeVariable::Start => self.times.start_time,
+            TimeVariable::End => self.times.end_time,
+            TimeVariable::Crossfade => self.times.crossfade_duration,
+        }) as f32
     }
 
-    pub fn start_time_params(&mut self) -> (&mut u32, &mut Unit) {
-        (&mut self.times.start_time, &mut self.units.start_unit)
+    pub fn start_time_params(&mut self) -> (&mut f64, &mut Unit, &mut TimeInput) {
+        (
+            &mut self.times.start_time,
+            &mut self.settings.start_unit,
+            &mut self.time_inputs.start,
+        )
     }
 
-    pub fn end_time_params(&mut self) -> (&mut u32, &mut Unit) {
-        (&mut self.times.end_time, &mut self.units.end_unit)
+    pub fn end_time_params(&mut self) -> (&mut f64, &mut Unit, &mut TimeInput) {
+        (
+            &mut self.times.end_time,
+            &mut self.settings.end_unit,
+            &mut self.time_inputs.end,
+        )
     }
 
-    pub fn crossfade_params(&mut self) -> (&mut u16, &mut Unit) {
+    pub fn crossfade_params(&mut self) -> (&mut f64, &mut Unit, &mut TimeInput) {
         (
             &mut self.times.crossfade_duration,
-            &mut self.units.crossfade_unit,
+            &mut self.settings.crossfade_unit,
+            &mut self.time_inputs.crossfade,
         )
     }
 
+    pub fn get_sample_rate(&self) -> Option<u32> {
+        self.source_info.sample_rate
+    }
+
+    pub fn crossfade_placement(&mut self) -> &mut CrossfadePlacement {
+        &mut self.times.crossfade_placement
+    }
+
+    pub fn crossfade_curve(&mut self) -> &mut CrossfadeCurve {
+        &mut self.times.crossfade_curve
+    }
+
     pub fn get_loop_count(&mut self) -> &mut u8 {
         &mut self.times.loop_count
     }
 
     pub fn clear_console(&mut self) {
         self.console.clear();
+        self.console_view.selected.clear();
+        self.console_view.anchor = None;
+        self.console_view.pinned.clear();
+    }
+
+    pub fn console_mut(&mut self) -> (&[ConsoleText], &mut ConsoleView) {
+        (&self.console, &mut self.console_view)
     }
 
-    pub fn iter_console(&self) -> Iter<'_, ConsoleText> {
-        self.console.iter()
+    /// Copies the selected console lines, in order, to the clipboard
+    pub fn copy_console_selection(&self, ctx: &egui::Context) {
+        let lines: Vec<&str> = self
+            .console_view
+            .selected
+            .iter()
+            .filter_map(|&i| self.console.get(i))
+            .map(ConsoleText::text)
+            .collect();
+        ctx.output_mut(|output| output.copied_text = lines.join("\n"));
     }
 
-    pub fn set_ffmpeg_channel(&mut self, rx: Receiver<Result<PathBuf, String>>) {
-        self.channels.ffmpeg_rx = Some(rx)
+    /// Saves every console line, whatever is filtered out, to a text file
+    pub fn save_console(&mut self) {
+        let path = match rfd::FileDialog::new()
+            .add_filter("Log File", &["log", "txt"])
+            .set_file_name("echoblend.log")
+            .save_file()
+        {
+            Some(path) => path,
+            None => return,
+        };
+        let mut log: Vec<&str> = self.console.iter().map(ConsoleText::text).collect();
+        log.push("");
+        if let Err(e) = std::fs::write(&path, log.join("\n")) {
+            self.error.message = format!("Failed to save {}: {}", path.display(), e);
+            self.error.window = true;
+        }
     }
 
-    pub fn set_ffmpeg_loading(&mut self, load: bool) {
-        self.tool_state.ffmpeg_loading = load;
+    pub fn download_ffmpeg(&mut self) {
+        match provision::ProvisionConfig::new(&self.tools.ffmpeg_source) {
+            Ok(config) => {
+                let (tx, rx) = std::sync::mpsc::channel();
+                provision::provision_ffmpeg(config, tx);
+                self.channels.ffmpeg_rx = Some(rx);
+                self.tool_state.ffmpeg_loading = true;
+                self.tool_state.ffmpeg_status.clear();
+                self.tool_state.ffmpeg_progress = None;
+            }
+            Err(e) => {
+                self.error.message = format!("Failed to download FFMPEG: {}", e);
+                self.error.window = true;
+            }
+        }
     }
 
     pub fn is_ffmpeg_loading(&self) -> bool {
         self.tool_state.ffmpeg_loading
     }
 
+    pub fn ffmpeg_download_status(&self) -> (&str, Option<(u64, Option<u64>)>) {
+        (
+            &self.tool_state.ffmpeg_status,
+            self.tool_state.ffmpeg_progress,
+        )
+    }
+
+    pub fn ffmpeg_source_mut(&mut self) -> &mut String {
+        &mut self.tools.ffmpeg_source
+    }
+
     pub fn get_ffmpeg_path(&self) -> String {
         self.tools.ffmpeg_path.clone()
     }
 
+    pub fn get_ffmpeg_capabilities(&self) -> Option<&CapabilitiesResult> {
+        self.tool_state.ffmpeg_capabilities.as_ref()
+    }
+
+    pub fn supports_mp3_output(&self) -> bool {
+        matches!(
+            &self.tool_state.ffmpeg_capabilities,
+            Some(Ok(capabilities)) if capabilities.mp3_output
+        )
+    }
+
+    /// The VBR quality MP3 loops are encoded at, the project's if it has one
+    pub fn mp3_quality(&self) -> u8 {
+        self.project
+            .mp3_quality
+            .unwrap_or(self.settings.mp3_quality)
+    }
+
+    pub fn settings_window_mut(&mut self) -> &mut bool {
+        &mut self.settings_window
+    }
+
+    /// Every preference, as shown in the settings window and exported
+    pub fn settings(&self) -> Settings {
+        self.settings.clone()
+    }
+
+    /// Replaces every preference. The default crossfade is used from the next session on.
+    pub fn apply_settings(&mut self, settings: Settings) {
+        if settings.console != self.settings.console {
+            self.console_view.set_verbosity(settings.console);
+        }
+        // Output settings changed since the project was opened apply to it as well
+        if settings.format != self.settings.format {
+            self.project.output_format = None;
+        }
+        if settings.mp3_quality != self.settings.mp3_quality {
+            self.project.mp3_quality = None;
+        }
+        self.settings = settings;
+    }
+
+    pub fn export_settings(&mut self) {
+        let path = match rfd::FileDialog::new()
+            .add_filter("EchoBlend Settings", &["json"])
+            .set_file_name("echoblend_settings.json")
+            .save_file()
+        {
+            Some(path) => path,
+            None => return,
+        };
+        match self.settings().save(&path) {
+            Ok(_) => self.console.push(ConsoleText::Program(format!(
+                "Exported settings to {}",
+                path.display()
+            ))),
+            Err(e) => {
+                self.error.message = e;
+                self.error.window = true;
+            }
+        }
+    }
+
+    pub fn import_settings(&mut self) {
+        let path = match rfd::FileDialog::new()
+            .add_filter("EchoBlend Settings", &["json"])
+            .pick_file()
+        {
+            Some(path) => path,
+            None => return,
+        };
+        match Settings::load(&path) {
+            Ok(settings) => {
+                self.apply_settings(settings);
+                self.console.push(ConsoleText::Program(format!(
+                    "Imported settings from {}",
+                    path.display()
+                )));
+            }
+            Err(e) => {
+                self.error.message = e;
+                self.error.window = true;
+            }
+        }
+    }
+
+    // Sets the visuals the theme asks for, following the system's if it is known
+    fn apply_theme(&self, ctx: &egui::Context, frame: &eframe::Frame) {
+        let dark_mode = match self.settings.theme {
+            Theme::Dark => true,
+            Theme::Light => false,
+            Theme::System => match frame.info().system_theme {
+                Some(theme) => theme == eframe::Theme::Dark,
+                None => return,
+            },
+        };
+        if ctx.style().visuals.dark_mode != dark_mode {
+            ctx.set_visuals(if dark_mode {
+                egui::Visuals::dark()
+            } else {
+                egui::Visuals::light()
+            });
+        }
+    }
+
     pub fn is_running(&self) -> bool {
         self.running
     }
 
+    /// Stops the running job after killing the step it is on, leaving no intermediate files
+    pub fn cancel_job(&mut self) {
+        if let Some(cancel) = &self.job.cancel {
+            cancel.cancel();
+            self.console
+                .push(ConsoleText::Program("Cancelling...".to_string()));
+        }
+    }
+
+    pub fn job_progress(&self) -> Option<(&str, usize, usize)> {
+        self.job
+            .progress
+            .map(|(done, total)| (self.job.step.as_str(), done, total))
+    }
+
     pub fn has_succeeded_running(&self) -> bool {
         self.success
     }
 }
 
+// A file opened by the app rather than dropped onto it
+fn source_file(path: &std::path::Path) -> egui::DroppedFile {
+    egui::DroppedFile {
+        name: path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        path: Some(path.to_path_buf()),
+        ..Default::default()
+    }
+}
+
+// Names what changed between two states for the history list
+fn describe_edit(before: &EditState, after: &EditState) -> String {
+    let time = |seconds: f64| timecode::format(seconds, Unit::Seconds);
+    let (b, a) = (&before.times, &after.times);
+    let mut changes = Vec::new();
+    if b.start_s != a.start_s {
+        changes.push(format!("Start {}", time(a.start_s)));
+    }
+    if b.end_s != a.end_s {
+        changes.push(format!("End {}", time(a.end_s)));
+    }
+    if b.crossfade_s != a.crossfade_s {
+        changes.push(format!("Crossfade {}", time(a.crossfade_s)));
+    }
+    if b.placement != a.placement {
+        changes.push(
+            match a.placement {
+                CrossfadePlacement::PreRoll => "Crossfade before start",
+                CrossfadePlacement::PostRoll => "Crossfade after end",
+                CrossfadePlacement::Centred => "Crossfade centred",
+            }
+            .to_string(),
+        );
+    }
+    if b.curve != a.curve {
+        changes.push(
+            match a.curve {
+                CrossfadeCurve::Linear => "Linear crossfade",
+                CrossfadeCurve::EqualPower => "Equal power crossfade",
+            }
+            .to_string(),
+        );
+    }
+    if b.loop_count != a.loop_count {
+        changes.push(format!("Loop count {}", a.loop_count));
+    }
+    if before.units != after.units {
+        changes.push("Units".to_string());
+    }
+    if before.regions != after.regions {
+        let added = after.regions.iter().find(|r| !before.regions.contains(r));
+        let removed = before.regions.iter().find(|r| !after.regions.contains(r));
+        changes.push(match (added, removed) {
+            (Some(region), None) => format!("Added region {}", region.name),
+            (None, Some(region)) => format!("Removed region {}", region.name),
+            _ => "Regions".to_string(),
+        });
+    }
+    changes.join(", ")
+}
+
 impl eframe::App for App {
     // Called by the frame work to save state before shutdown.
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.remember_source_settings();
         eframe::set_value(storage, eframe::APP_KEY, &self);
     }
 
     // Called each time the UI needs repainting, which may be many times per second.
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         // Base Style
         ctx.style_mut(|style| {
             style.interaction.tooltip_delay = 0.0;
             style.interaction.show_tooltips_only_when_still = false;
         });
+        self.apply_theme(ctx, frame);
 
         // Handle inputs and channels
         self.handle_inputs(ctx);
+        self.receive_source(ctx);
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::S))
+            && self.has_source()
+        {
+            self.save_project(false);
+        }
+        // Text fields keep their own undo while they are being edited
+        if !ctx.wants_keyboard_input() {
+            // Redo is checked first as Ctrl+Z also matches with Shift held
+            let (redo, undo) = ctx.input_mut(|i| {
+                let redo = i.consume_key(
+                    egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+                    egui::Key::Z,
+                ) || i.consume_key(egui::Modifiers::COMMAND, egui::Key::Y);
+                (redo, i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z))
+            });
+            if redo {
+                self.redo();
+            } else if undo {
+                self.undo();
+            }
+        }
+        self.check_ffmpeg(ctx);
 
-        handle_rx(
-            &mut self.channels.ffmpeg_rx,
-            |path| self.tools.ffmpeg_path = path.display().to_string(),
-            |e| {
-                self.error.message = format!("Failed to download FFMPEG: {}", e);
-                self.error.window = true;
-            },
-            &mut self.tool_state.ffmpeg_loading,
-            true,
-        );
+        // Drain every download event so the progress bar keeps up
+        if let Some(rx) = &self.channels.ffmpeg_rx {
+            let mut finished = false;
+            loop {
+                let event = match rx.try_recv() {
+                    Ok(event) => event,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => break,
+                    Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                        if !finished {
+                            self.error.message =
+                                "The FFMPEG download stopped unexpectedly.".to_string();
+                            self.error.window = true;
+                            finished = true;
+                        }
+                        break;
+                    }
+                };
+                match event {
+                    ProvisionEvent::Status(status) => self.tool_state.ffmpeg_status = status,
+                    ProvisionEvent::Progress { downloaded, total } => {
+                        self.tool_state.ffmpeg_progress = Some((downloaded, total))
+                    }
+                    ProvisionEvent::Finished(result) => {
+                        match result {
+                            Ok(path) => {
+                                self.tools.ffmpeg_path = path.display().to_string();
+                                self.tool_state.ffmpeg_capabilities = None;
+                            }
+                            Err(e) => {
+                                self.error.message = format!("Failed to download FFMPEG: {}", e);
+                                self.error.window = true;
+                            }
+                        }
+                        finished = true;
+                    }
+                }
+            }
+            if finished {
+                self.channels.ffmpeg_rx = None;
+                self.tool_state.ffmpeg_loading = false;
+            }
+        }
 
+        // Drain every job event so console lines don't lag behind
         let mut new_line = false;
-        handle_rx(
-            &mut self.channels.running_rx,
-            |res| {
+        if let Some(rx) = &self.channels.job_rx {
+            let mut result = None;
+            loop {
+                let event = match rx.try_recv() {
+                    Ok(event) => event,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => break,
+                    Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                        result.get_or_insert(Err(JobError::Stopped));
+                        break;
+                    }
+                };
                 new_line = true;
-                self.console.push(res);
-            },
-            |e| {
-                self.error.message = e;
-                self.error.window = true;
-            },
-            &mut self.running,
-            false,
-        );
-
-        handle_rx(
-            &mut self.channels.running_finished,
-            |res| {
-                if res {
-                    self.channels.running_rx = None;
-                    self.running = false;
+                match event {
+                    JobEvent::StepStarted(step) => {
+                        self.console.push(ConsoleText::Program(step.clone()));
+                        self.job.step = step;
+                    }
+                    JobEvent::Log { text, level } => self.console.push(match level {
+                        LogLevel::Info => ConsoleText::Program(text),
+                        LogLevel::Output => ConsoleText::Stdout(text),
+                        LogLevel::Error => ConsoleText::Stderr(text),
+                    }),
+                    JobEvent::Progress { done, total } => self.job.progress = Some((done, total)),
+                    JobEvent::Warning(text) => self.console.push(ConsoleText::Stdout(text)),
+                    JobEvent::Failed(e) => result = Some(Err(e)),
+                    JobEvent::Finished { outputs, summary } => {
+                        result = Some(Ok((outputs, summary)))
+                    }
+                }
+            }
+            let finished = result.is_some();
+            match result {
+                Some(Ok((outputs, summary))) => {
+                    for output in &outputs {
+                        self.console
+                            .push(ConsoleText::Program(format!("Wrote {}", output.display())));
+                    }
+                    self.console.push(ConsoleText::Success(summary.clone()));
                     self.success = true;
+                    self.record_job(JobOutcome::Succeeded { summary }, outputs);
                 }
-            },
-            |_| {},
-            &mut false,
-            true,
-        );
+                // Cancelling is not an error worth a window, only a line in the history
+                Some(Err(JobError::Cancelled)) => {
+                    self.console
+                        .push(ConsoleText::Program(JobError::Cancelled.to_string()));
+                    self.record_job(
+                        JobOutcome::Failed {
+                            error: JobError::Cancelled.to_string(),
+                        },
+                        Vec::new(),
+                    );
+                }
+                Some(Err(e)) => {
+                    self.console.push(ConsoleText::Stderr(e.to_string()));
+                    self.console_view.pin_errors(&self.console);
+                    self.record_job(
+                        JobOutcome::Failed {
+                            error: e.to_string(),
+                        },
+                        Vec::new(),
+                    );
+                    self.error.message = e.to_string();
+                    self.error.window = true;
+                }
+                None => {}
+            }
+            if finished {
+                self.channels.job_rx = None;
+                self.running = false;
+                self.job.progress = None;
+                self.job.cancel = None;
+            }
+        }
 
         // Window popup for errors
         error_window(ctx, &mut self.error.window, self.error.message.clone());
+        recipe_window(self, ctx);
+        overwrite_window(self, ctx);
+        settings_window(self, ctx);
 
         // Actual view
-        add_header(ctx);
+        add_header(self, ctx);
 
         // The central panel the region left after adding TopPanel's and SidePanel's
         egui::CentralPanel::default().show(ctx, |ui| {
@@ -365,7 +1774,7 @@ impl eframe::App for App {
                 if !self.tool_state.ffmpeg_path_check {
                     self.tool_state.ffmpeg_path_check = true;
                     if std::process::Command::new("ffmpeg").output().is_ok() {
-                        self.tools.ffmpeg_path = "ffmpeg".to_string();
+                        self.set_ffmpeg_path("ffmpeg".to_string());
                     }
                 }
                 initial_ffmpeg_info(self, ui);
@@ -377,46 +1786,34 @@ impl eframe::App for App {
             ui.separator();
 
             ui.horizontal(|ui| {
-                ui.label("Drag and drop a file to get started.\nYou should only use .wav or .mp3 files.\nThe file name will be displayed below.");
+                ui.label("Drag and drop a file to get started.\nYou should only use .wav or .mp3 files, or a .echoblend project.\nThe file name will be displayed below.");
                 if self.file_load {
                     ui.add(egui::widgets::Spinner::new());
                 }
             });
             ui.label(self.file.path.clone().unwrap_or_default().display().to_string());
 
+            project_bar(self, ui);
+
             ui.separator();
 
             create_param_grid(self, ui);
 
+            loop_timeline(self, ui);
+
+            project_details(self, ui);
+
+            history_panel(self, ui);
+
+            job_history_panel(self, ui);
+
             ui.separator();
 
             create_console_view(self, ctx, ui, new_line);
         });
 
         add_footer(ctx);
-    }
-}
 
-fn handle_rx<R, F, G>(
-    rx_option: &mut Option<std::sync::mpsc::Receiver<Result<R, String>>>,
-    on_success: F,
-    on_error: G,
-    loading_flag: &mut bool,
-    auto_kill: bool,
-) where
-    F: FnOnce(R),      // Success handler
-    G: FnOnce(String), // Error handler
-{
-    if let Some(rx) = rx_option {
-        if let Ok(result) = rx.try_recv() {
-            match result {
-                Ok(value) => on_success(value),
-                Err(e) => on_error(e),
-            }
-            if auto_kill {
-                *rx_option = None;
-                *loading_flag = false;
-            }
-        }
+        self.track_history(ctx);
     }
 }
@@ -0,0 +1,272 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    #[serde(rename = "7z")]
+    SevenZip,
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.xz")]
+    TarXz,
+}
+
+/// Extracts an archive into `target`.
+///
+/// Everything is unpacked into a staging directory next to `target` first and only moved into
+/// place once the whole archive has been extracted, so `target` is never left half written.
+pub fn extract(archive: &Path, kind: ArchiveKind, target: &Path) -> Result<(), String> {
+    let mut staging = target.as_os_str().to_owned();
+    staging.push(".partial");
+    let staging = PathBuf::from(staging);
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging).map_err(|e| e.to_string())?;
+    }
+    std::fs::create_dir_all(&staging).map_err(|e| e.to_string())?;
+
+    let result = match kind {
+        ArchiveKind::SevenZip => extract_7z(archive, &staging),
+        ArchiveKind::Zip => extract_zip(archive, &staging),
+        ArchiveKind::TarXz => extract_tar_xz(archive, &staging),
+    }
+    .and_then(|_| {
+        if target.exists() {
+            std::fs::remove_dir_all(target).map_err(|e| e.to_string())?;
+        }
+        std::fs::rename(&staging, target).map_err(|e| e.to_string())
+    });
+
+    if result.is_err() {
+        std::fs::remove_dir_all(&staging).unwrap_or_default();
+    }
+    result
+}
+
+// Resolves an entry name inside `root`, refusing names that would land outside of it (zip-slip)
+fn safe_join(root: &Path, name: &str) -> Result<PathBuf, String> {
+    let mut path = root.to_path_buf();
+    // Archives made on Windows may use backslashes as separators
+    for component in Path::new(&name.replace('\\', "/")).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(format!(
+                    "The archive entry \"{}\" points outside of the install directory",
+                    name
+                ));
+            }
+        }
+    }
+    Ok(path)
+}
+
+fn corrupt(archive: &Path, e: impl std::fmt::Display) -> String {
+    format!(
+        "The archive {} is corrupt or incomplete ({}). Delete it and download it again.",
+        archive.display(),
+        e
+    )
+}
+
+fn write_entry<R: std::io::Read + ?Sized>(path: &Path, reader: &mut R) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut file = File::create(path).map_err(|e| e.to_string())?;
+    std::io::copy(reader, &mut file).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn extract_7z(archive: &Path, staging: &Path) -> Result<(), String> {
+    let file = File::open(archive).map_err(|e| e.to_string())?;
+    let mut slip = None;
+    sevenz_rust::decompress_with_extract_fn(file, staging, |entry, reader, _| {
+        let path = match safe_join(staging, entry.name()) {
+            Ok(path) => path,
+            Err(e) => {
+                slip = Some(e);
+                return Ok(false);
+            }
+        };
+        if entry.is_directory() {
+            std::fs::create_dir_all(&path)?;
+        } else {
+            write_entry(&path, reader).map_err(sevenz_rust::Error::other)?;
+        }
+        Ok(true)
+    })
+    .map_err(|e| corrupt(archive, e))?;
+    slip.map_or(Ok(()), Err)
+}
+
+fn extract_zip(archive: &Path, staging: &Path) -> Result<(), String> {
+    let file = File::open(archive).map_err(|e| e.to_string())?;
+    let mut zip = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| corrupt(archive, e))?;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|e| corrupt(archive, e))?;
+        let path = safe_join(staging, entry.name())?;
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+            continue;
+        }
+        write_entry(&path, &mut entry).map_err(|e| corrupt(archive, e))?;
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+fn extract_tar_xz(archive: &Path, staging: &Path) -> Result<(), String> {
+    // Decompress to a plain tar next to the archive rather than holding it all in memory
+    let tar_path = archive.with_extension("");
+    let result = (|| {
+        let mut input = BufReader::new(File::open(archive).map_err(|e| e.to_string())?);
+        let mut output = File::create(&tar_path).map_err(|e| e.to_string())?;
+        lzma_rs::xz_decompress(&mut input, &mut output).map_err(|e| corrupt(archive, e))?;
+        drop(output);
+
+        let mut tar = tar::Archive::new(File::open(&tar_path).map_err(|e| e.to_string())?);
+        for entry in tar.entries().map_err(|e| corrupt(archive, e))? {
+            let mut entry = entry.map_err(|e| corrupt(archive, e))?;
+            let name = entry
+                .path()
+                .map_err(|e| corrupt(archive, e))?
+                .to_string_lossy()
+                .to_string();
+            let path = safe_join(staging, &name)?;
+            let kind = entry.header().entry_type();
+            if kind.is_dir() {
+                std::fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+            } else if kind.is_file() {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                }
+                // Unpacking to a checked path keeps the file mode, so ffmpeg stays executable
+                entry.unpack(&path).map_err(|e| corrupt(archive, e))?;
+            }
+            // Links and special files are not needed for ffmpeg and are skipped
+        }
+        Ok(())
+    })();
+    std::fs::remove_file(&tar_path).unwrap_or_default();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "echo_blend_archive_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn zip_with(dir: &Path, entries: &[(&str, &[u8])]) -> PathBuf {
+        let path = dir.join("test.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, contents) in entries {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn joins_names_inside_the_root() {
+        let root = Path::new("install");
+        assert_eq!(
+            safe_join(root, "ffmpeg/bin/ffmpeg").unwrap(),
+            root.join("ffmpeg").join("bin").join("ffmpeg")
+        );
+        assert_eq!(
+            safe_join(root, "./ffmpeg\\bin\\ffmpeg.exe").unwrap(),
+            root.join("ffmpeg").join("bin").join("ffmpeg.exe")
+        );
+    }
+
+    #[test]
+    fn rejects_parent_directories() {
+        let root = Path::new("install");
+        for name in ["../ffmpeg", "bin/../../ffmpeg", "..\\..\\ffmpeg.exe"] {
+            assert!(safe_join(root, name).is_err(), "{} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        let root = Path::new("install");
+        for name in ["/etc/passwd", "\\Windows\\ffmpeg.exe"] {
+            assert!(safe_join(root, name).is_err(), "{} was accepted", name);
+        }
+        #[cfg(windows)]
+        assert!(safe_join(root, "C:\\Windows\\ffmpeg.exe").is_err());
+    }
+
+    #[test]
+    fn extracts_a_zip() {
+        let dir = temp_dir("zip");
+        let archive = zip_with(&dir, &[("ffmpeg/bin/ffmpeg", b"binary")]);
+        let target = dir.join("install");
+        extract(&archive, ArchiveKind::Zip, &target).unwrap();
+        assert_eq!(
+            std::fs::read(target.join("ffmpeg").join("bin").join("ffmpeg")).unwrap(),
+            b"binary"
+        );
+        assert!(!dir.join("install.partial").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_zip_slip_entry() {
+        let dir = temp_dir("slip");
+        let archive = zip_with(
+            &dir,
+            &[("ffmpeg/bin/ffmpeg", b"binary"), ("../escaped", b"evil")],
+        );
+        let target = dir.join("install");
+        assert!(extract(&archive, ArchiveKind::Zip, &target).is_err());
+        assert!(!dir.join("escaped").exists());
+        assert!(!target.exists());
+        assert!(!dir.join("install.partial").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_corrupt_archive_leaves_no_partial_install() {
+        let dir = temp_dir("corrupt");
+        let archive = zip_with(&dir, &[("ffmpeg/bin/ffmpeg", &[7u8; 4096])]);
+        // Cut the archive short, losing its central directory
+        let bytes = std::fs::read(&archive).unwrap();
+        std::fs::write(&archive, &bytes[..bytes.len() / 2]).unwrap();
+        let target = dir.join("install");
+
+        // An earlier install is kept as it was
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("ffmpeg"), b"old").unwrap();
+        for kind in [ArchiveKind::Zip, ArchiveKind::TarXz, ArchiveKind::SevenZip] {
+            let error = extract(&archive, kind, &target).unwrap_err();
+            assert!(error.contains("corrupt"), "{}", error);
+            assert!(!dir.join("install.partial").exists());
+            assert_eq!(std::fs::read(target.join("ffmpeg")).unwrap(), b"old");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,528 @@
+use std::{io::Read, path::Path, sync::Mutex};
+
+#[cfg(feature = "native-engine")]
+use crate::ffmpeg;
+use crate::{
+    diagnostics::Diagnostic,
+    job::{CancelToken, JobSender},
+    looper::CrossfadeCurve,
+};
+
+/// What probing an input found, where the backend could tell
+#[derive(Debug, Default, Clone)]
+pub struct MediaInfo {
+    pub duration_s: Option<f32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    /// The file's title tag
+    pub title: Option<String>,
+    /// The file's comment tag, which holds the recipe of a loop EchoBlend rendered
+    pub comment: Option<String>,
+}
+
+/// A fade applied to a rendered segment, with times relative to the start of the source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fade {
+    In {
+        start_s: f32,
+        duration_s: f32,
+        curve: CrossfadeCurve,
+    },
+    Out {
+        start_s: f32,
+        duration_s: f32,
+        curve: CrossfadeCurve,
+    },
+}
+
+/// A part of the source audio to render
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Segment {
+    /// Where the segment starts, or the start of the source if `None`
+    pub start_s: Option<f32>,
+    /// How long the segment is, or until the end of the source if `None`
+    pub duration_s: Option<f32>,
+    pub fade: Option<Fade>,
+}
+
+/// Audio formats a file can be transcoded to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    /// 32-bit float PCM, used to cut compressed sources sample accurately
+    PcmFloat,
+    /// MP3 with gapless playback information, at a VBR quality from 0, the best, to 9, the smallest
+    Mp3 { quality: u8 },
+}
+
+/// What a backend is told about the step it is running
+pub struct StepContext<'a> {
+    /// Names the step for diagnostics, e.g. "Rendering intro..."
+    pub name: &'a str,
+    /// A directory private to the job for any files the backend needs besides the output
+    pub scratch_dir: &'a Path,
+    /// Where to send the backend's own output
+    pub tx: &'a JobSender,
+    /// Stops the step early once the job is cancelled, where the backend can
+    pub cancel: &'a CancelToken,
+}
+
+/// The media operations a loop is built from
+pub trait MediaBackend: Send + Sync {
+    fn probe(&self, input: &str) -> Result<MediaInfo, String>;
+
+    /// Decodes `input` to a 32-bit float WAV stream, used to analyse a rendered loop
+    fn decode_pcm(&self, input: &str) -> Result<Box<dyn Read + Send>, String>;
+
+    fn render_segment(
+        &self,
+        step: &StepContext<'_>,
+        input: &str,
+        segment: &Segment,
+        output: &str,
+    ) -> Result<(), Diagnostic>;
+
+    /// Mixes the inputs together without changing their volume, lasting as long as the first one
+    fn mix(&self, step: &StepContext<'_>, inputs: &[&str], output: &str) -> Result<(), Diagnostic>;
+
+    /// Joins the inputs one after another, tagging the output with `metadata`
+    fn concat(
+        &self,
+        step: &StepContext<'_>,
+        inputs: &[&str],
+        output: &str,
+        metadata: &[(&str, &str)],
+    ) -> Result<(), Diagnostic>;
+
+    /// Re-encodes the input, tagging the output with `metadata`
+    fn transcode(
+        &self,
+        step: &StepContext<'_>,
+        input: &str,
+        codec: Codec,
+        output: &str,
+        metadata: &[(&str, &str)],
+    ) -> Result<(), Diagnostic>;
+}
+
+/// Runs every operation as an ffmpeg subprocess, streaming its output as job events
+#[cfg(feature = "native-engine")]
+pub struct FfmpegBackend {
+    ffmpeg_path: String,
+}
+
+#[cfg(feature = "native-engine")]
+impl FfmpegBackend {
+    pub fn new(ffmpeg_path: impl Into<String>) -> Self {
+        Self {
+            ffmpeg_path: ffmpeg_path.into(),
+        }
+    }
+
+    fn run(&self, step: &StepContext<'_>, args: &[String]) -> Result<(), Diagnostic> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        ffmpeg::run_ffmpeg(&self.ffmpeg_path, step.name, &args, step.tx, step.cancel)
+    }
+}
+
+#[cfg(feature = "native-engine")]
+impl MediaBackend for FfmpegBackend {
+    fn probe(&self, input: &str) -> Result<MediaInfo, String> {
+        ffmpeg::probe(&self.ffmpeg_path, input)
+    }
+
+    fn decode_pcm(&self, input: &str) -> Result<Box<dyn Read + Send>, String> {
+        Ok(Box::new(ffmpeg::decode_pcm(&self.ffmpeg_path, input)?))
+    }
+
+    fn render_segment(
+        &self,
+        step: &StepContext<'_>,
+        input: &str,
+        segment: &Segment,
+        output: &str,
+    ) -> Result<(), Diagnostic> {
+        self.run(step, &segment_args(input, segment, output))
+    }
+
+    fn mix(&self, step: &StepContext<'_>, inputs: &[&str], output: &str) -> Result<(), Diagnostic> {
+        self.run(step, &mix_args(inputs, output))
+    }
+
+    fn concat(
+        &self,
+        step: &StepContext<'_>,
+        inputs: &[&str],
+        output: &str,
+        metadata: &[(&str, &str)],
+    ) -> Result<(), Diagnostic> {
+        // Create an ffmpeg concat list txt file
+        let concat_list_file_name = concat_list_path(step.scratch_dir);
+        if let Err(e) = std::fs::write(
+            &concat_list_file_name,
+            concat_list(step.scratch_dir, inputs),
+        ) {
+            return Err(Diagnostic {
+                step: step.name.trim_end_matches('.').to_string(),
+                explanation: "The list of segments to join could not be written.".to_string(),
+                fix:
+                    "Check there is free disk space and that the scratch folder can be written to."
+                        .to_string(),
+                detail: e.to_string(),
+            });
+        }
+        self.run(step, &concat_args(&concat_list_file_name, output, metadata))
+    }
+
+    fn transcode(
+        &self,
+        step: &StepContext<'_>,
+        input: &str,
+        codec: Codec,
+        output: &str,
+        metadata: &[(&str, &str)],
+    ) -> Result<(), Diagnostic> {
+        self.run(step, &transcode_args(input, codec, output, metadata))
+    }
+}
+
+// The ffmpeg arguments of each operation, shared with the exported render scripts
+
+#[cfg(feature = "native-engine")]
+pub(crate) fn segment_args(input: &str, segment: &Segment, output: &str) -> Vec<String> {
+    let mut args = vec!["-y".to_string(), "-i".to_string(), input.to_string()];
+    if let Some(start_s) = segment.start_s {
+        args.extend(["-ss".to_string(), start_s.to_string()]);
+    }
+    if let Some(duration_s) = segment.duration_s {
+        args.extend(["-t".to_string(), duration_s.to_string()]);
+    }
+    // Seeking after the input keeps timestamps relative to the source, which the fade times use
+    match segment.fade {
+        Some(Fade::In {
+            start_s,
+            duration_s,
+            curve,
+        }) => args.extend([
+            "-af".to_string(),
+            format!(
+                "afade=t=in:st={}:d={}:curve={}",
+                start_s,
+                duration_s,
+                afade_curve(curve)
+            ),
+        ]),
+        Some(Fade::Out {
+            start_s,
+            duration_s,
+            curve,
+        }) => args.extend([
+            "-af".to_string(),
+            format!(
+                "afade=t=out:st={}:d={}:curve={}",
+                start_s,
+                duration_s,
+                afade_curve(curve)
+            ),
+        ]),
+        None => {}
+    }
+    args.push(output.to_string());
+    args
+}
+
+#[cfg(feature = "native-engine")]
+pub(crate) fn mix_args(inputs: &[&str], output: &str) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    for input in inputs {
+        args.extend(["-i".to_string(), input.to_string()]);
+    }
+    args.extend([
+        "-filter_complex".to_string(),
+        format!(
+            "amix=inputs={}:duration=first:dropout_transition=0:normalize=0",
+            inputs.len()
+        ),
+        output.to_string(),
+    ]);
+    args
+}
+
+#[cfg(feature = "native-engine")]
+pub(crate) fn concat_list_path(scratch_dir: &Path) -> String {
+    scratch_dir.join("concat_list.txt").display().to_string()
+}
+
+/// The contents of the ffmpeg concat list in `scratch_dir` joining `inputs`
+#[cfg(feature = "native-engine")]
+pub(crate) fn concat_list(scratch_dir: &Path, inputs: &[&str]) -> String {
+    let mut list = String::new();
+    for input in inputs {
+        // ffmpeg resolves relative entries against the list's folder, so files in it are named alone
+        let path = Path::new(input);
+        let entry = match path.file_name() {
+            Some(name) if path.parent() == Some(scratch_dir) => name.to_string_lossy(),
+            _ => std::borrow::Cow::Borrowed(*input),
+        };
+        // Quotes inside a quoted concat list path are written as '\''
+        list.push_str(&format!("file '{}'\n", entry.replace('\'', "'\\''")));
+    }
+    list
+}
+
+#[cfg(feature = "native-engine")]
+pub(crate) fn concat_args(list: &str, output: &str, metadata: &[(&str, &str)]) -> Vec<String> {
+    let mut args: Vec<String> = ["-y", "-f", "concat", "-safe", "0", "-i", list, "-c", "copy"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    args.extend(metadata_args(metadata));
+    args.push(output.to_string());
+    args
+}
+
+#[cfg(feature = "native-engine")]
+pub(crate) fn transcode_args(
+    input: &str,
+    codec: Codec,
+    output: &str,
+    metadata: &[(&str, &str)],
+) -> Vec<String> {
+    let quality;
+    let args = match codec {
+        Codec::PcmFloat => vec!["-y", "-i", input, "-map", "0:a:0", "-c:a", "pcm_f32le"],
+        Codec::Mp3 { quality: q } => {
+            quality = q.to_string();
+            vec![
+                "-y",
+                "-i",
+                input,
+                "-c:a",
+                "libmp3lame",
+                "-q:a",
+                &quality,
+                "-write_xing",
+                "1",
+            ]
+        }
+    };
+    let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    args.extend(metadata_args(metadata));
+    args.push(output.to_string());
+    args
+}
+
+#[cfg(feature = "native-engine")]
+fn afade_curve(curve: CrossfadeCurve) -> &'static str {
+    match curve {
+        CrossfadeCurve::Linear => "tri",
+        CrossfadeCurve::EqualPower => "qsin",
+    }
+}
+
+#[cfg(feature = "native-engine")]
+fn metadata_args(metadata: &[(&str, &str)]) -> Vec<String> {
+    metadata
+        .iter()
+        .flat_map(|(key, value)| ["-metadata".to_string(), format!("{}={}", key, value)])
+        .collect()
+}
+
+/// An operation requested from a [`RecordingBackend`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    RenderSegment {
+        input: String,
+        segment: Segment,
+        output: String,
+    },
+    Mix {
+        inputs: Vec<String>,
+        output: String,
+    },
+    Concat {
+        inputs: Vec<String>,
+        output: String,
+        metadata: Vec<(String, String)>,
+    },
+    Transcode {
+        input: String,
+        codec: Codec,
+        output: String,
+        metadata: Vec<(String, String)>,
+    },
+}
+
+/// A backend that only records the operations it is asked to do, so a loop can be planned without ffmpeg
+#[derive(Default)]
+pub struct RecordingBackend {
+    /// Returned by `probe` for every input
+    pub info: MediaInfo,
+    calls: Mutex<Vec<Call>>,
+}
+
+impl RecordingBackend {
+    pub fn new(info: MediaInfo) -> Self {
+        Self {
+            info,
+            calls: Mutex::default(),
+        }
+    }
+
+    /// The operations requested so far, in order
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    fn record(&self, call: Call) -> Result<(), Diagnostic> {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(call);
+        }
+        Ok(())
+    }
+}
+
+impl MediaBackend for RecordingBackend {
+    fn probe(&self, _input: &str) -> Result<MediaInfo, String> {
+        Ok(self.info.clone())
+    }
+
+    fn decode_pcm(&self, _input: &str) -> Result<Box<dyn Read + Send>, String> {
+        Err("The recording backend does not produce audio".to_string())
+    }
+
+    fn render_segment(
+        &self,
+        _step: &StepContext<'_>,
+        input: &str,
+        segment: &Segment,
+        output: &str,
+    ) -> Result<(), Diagnostic> {
+        self.record(Call::RenderSegment {
+            input: input.to_string(),
+            segment: *segment,
+            output: output.to_string(),
+        })
+    }
+
+    fn mix(
+        &self,
+        _step: &StepContext<'_>,
+        inputs: &[&str],
+        output: &str,
+    ) -> Result<(), Diagnostic> {
+        self.record(Call::Mix {
+            inputs: inputs.iter().map(|i| i.to_string()).collect(),
+            output: output.to_string(),
+        })
+    }
+
+    fn concat(
+        &self,
+        _step: &StepContext<'_>,
+        inputs: &[&str],
+        output: &str,
+        metadata: &[(&str, &str)],
+    ) -> Result<(), Diagnostic> {
+        self.record(Call::Concat {
+            inputs: inputs.iter().map(|i| i.to_string()).collect(),
+            output: output.to_string(),
+            metadata: owned_metadata(metadata),
+        })
+    }
+
+    fn transcode(
+        &self,
+        _step: &StepContext<'_>,
+        input: &str,
+        codec: Codec,
+        output: &str,
+        metadata: &[(&str, &str)],
+    ) -> Result<(), Diagnostic> {
+        self.record(Call::Transcode {
+            input: input.to_string(),
+            codec,
+            output: output.to_string(),
+            metadata: owned_metadata(metadata),
+        })
+    }
+}
+
+/// A backend whose every step fails, or panics, to test how a job copes
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct FailingBackend {
+    pub panics: bool,
+}
+
+#[cfg(test)]
+impl FailingBackend {
+    fn fail(&self, step: &StepContext<'_>) -> Result<(), Diagnostic> {
+        if self.panics {
+            panic!("The test backend panicked in {}", step.name);
+        }
+        Err(Diagnostic {
+            step: step.name.to_string(),
+            explanation: "The test backend always fails".to_string(),
+            fix: String::new(),
+            detail: String::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+impl MediaBackend for FailingBackend {
+    fn probe(&self, _input: &str) -> Result<MediaInfo, String> {
+        Ok(MediaInfo::default())
+    }
+
+    fn decode_pcm(&self, _input: &str) -> Result<Box<dyn Read + Send>, String> {
+        Err("The failing backend does not produce audio".to_string())
+    }
+
+    fn render_segment(
+        &self,
+        step: &StepContext<'_>,
+        _input: &str,
+        _segment: &Segment,
+        _output: &str,
+    ) -> Result<(), Diagnostic> {
+        self.fail(step)
+    }
+
+    fn mix(
+        &self,
+        step: &StepContext<'_>,
+        _inputs: &[&str],
+        _output: &str,
+    ) -> Result<(), Diagnostic> {
+        self.fail(step)
+    }
+
+    fn concat(
+        &self,
+        step: &StepContext<'_>,
+        _inputs: &[&str],
+        _output: &str,
+        _metadata: &[(&str, &str)],
+    ) -> Result<(), Diagnostic> {
+        self.fail(step)
+    }
+
+    fn transcode(
+        &self,
+        step: &StepContext<'_>,
+        _input: &str,
+        _codec: Codec,
+        _output: &str,
+        _metadata: &[(&str, &str)],
+    ) -> Result<(), Diagnostic> {
+        self.fail(step)
+    }
+}
+
+fn owned_metadata(metadata: &[(&str, &str)]) -> Vec<(String, String)> {
+    metadata
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
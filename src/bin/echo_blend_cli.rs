@@ -0,0 +1,5 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+fn main() -> std::process::ExitCode {
+    echo_blend::run_cli(std::env::args().skip(1))
+}
@@ -0,0 +1,704 @@
+use std::{io::IsTerminal, path::PathBuf, process::ExitCode};
+
+use crate::{
+    backend::{FfmpegBackend, MediaBackend},
+    ffmpeg,
+    job::{CancelToken, JobError, JobEvent, LogLevel},
+    looper::{self, CrossfadeCurve, CrossfadePlacement},
+    naming::{self, NameValues, OverwritePolicy},
+    project::{Project, SourceStatus},
+    render::{render, LoopSpec, OutputFormat, DEFAULT_MP3_QUALITY},
+    script::{RenderScript, Shell},
+    timecode,
+};
+
+// Exit codes, so scripts can tell bad input apart from a failed render
+const EXIT_RENDER_FAILED: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_FFMPEG_UNAVAILABLE: u8 = 3;
+
+const USAGE: &str = "Render a seamless loop of a song without opening the EchoBlend window.
+
+Usage: echo_blend_cli --input <FILE> --output <FILE> --start <TIME> --end <TIME> [OPTIONS]
+       echo_blend_cli --project <FILE> [--output <FILE>] [OPTIONS]
+
+Options:
+  -P, --project <FILE>       An .echoblend project to take the song, times and output settings from,
+                             any of which the options below override
+  -i, --input <FILE>         The .wav or .mp3 file to loop
+  -o, --output <FILE>        Where to write the loop. The file name can hold the tokens {title},
+                             {stem}, {loops}, {duration}, {start}, {end} and {date}
+      --overwrite <POLICY>   What to do if the output exists: ask, skip, number or overwrite
+                             [default: ask, which fails when not run from a terminal]
+  -s, --start <TIME>         Where the loop starts
+  -e, --end <TIME>           Where the loop ends
+  -c, --crossfade <TIME>     How long the crossfade at each seam is [default: 0]
+  -p, --placement <WHERE>    Where the crossfade is taken from: before-start, after-end or centred
+                             [default: before-start]
+      --curve <CURVE>        The shape of the crossfade: linear or equal-power [default: linear]
+  -n, --loops <COUNT>        How many times the section repeats, 0 to 255 [default: 1]
+  -f, --format <FORMAT>      Output format, wav or mp3 [default: from the output extension]
+  -q, --mp3-quality <0-9>    VBR quality of MP3 loops, 0 the best and 9 the smallest [default: 2]
+  -t, --test                 Render a test file with a single seam instead of the full loop
+      --ffmpeg <PATH>        The ffmpeg executable to use [default: ffmpeg]
+      --scratch-dir <DIR>    Where intermediate files are written [default: system temp folder]
+      --keep-intermediates   Leave the intermediate files behind for debugging
+      --script <FILE>        Write the ffmpeg commands to a shell script, or a PowerShell script if
+                             it ends in .ps1, instead of rendering
+  -v, --verbose              Print ffmpeg's output as well as EchoBlend's progress
+  -h, --help                 Print this help
+
+Times accept 1:23.456, 01:02:03.5, plain seconds, 12345ms or s:12345 for a sample position.
+
+Exit codes: 0 on success, 1 if rendering failed, 2 for invalid arguments or times,
+3 if ffmpeg cannot be run or is missing something a loop needs.";
+
+struct Args {
+    project: Option<PathBuf>,
+    input: Option<String>,
+    output: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    crossfade: Option<String>,
+    placement: Option<CrossfadePlacement>,
+    curve: Option<CrossfadeCurve>,
+    loop_count: Option<u8>,
+    format: Option<String>,
+    mp3_quality: Option<u8>,
+    test: bool,
+    ffmpeg_path: String,
+    scratch_dir: Option<PathBuf>,
+    keep_intermediates: bool,
+    script: Option<PathBuf>,
+    overwrite: OverwritePolicy,
+    verbose: bool,
+}
+
+// Returns None if help was asked for
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut parsed = Args {
+        project: None,
+        input: None,
+        output: None,
+        start: None,
+        end: None,
+        crossfade: None,
+        placement: None,
+        curve: None,
+        loop_count: None,
+        format: None,
+        mp3_quality: None,
+        test: false,
+        ffmpeg_path: "ffmpeg".to_string(),
+        scratch_dir: None,
+        keep_intermediates: false,
+        script: None,
+        overwrite: OverwritePolicy::Ask,
+        verbose: false,
+    };
+
+    while let Some(arg) = args.next() {
+        // Accept both "--start 1:00" and "--start=1:00"
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or(format!("{} needs a value", flag))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-P" | "--project" => parsed.project = Some(PathBuf::from(value()?)),
+            "-i" | "--input" => parsed.input = Some(value()?),
+            "-o" | "--output" => parsed.output = Some(value()?),
+            "-s" | "--start" => parsed.start = Some(value()?),
+            "-e" | "--end" => parsed.end = Some(value()?),
+            "-c" | "--crossfade" => parsed.crossfade = Some(value()?),
+            "-p" | "--placement" => {
+                parsed.placement = Some(match value()?.to_lowercase().as_str() {
+                    "before-start" | "pre-roll" => CrossfadePlacement::PreRoll,
+                    "after-end" | "post-roll" => CrossfadePlacement::PostRoll,
+                    "centred" | "centered" => CrossfadePlacement::Centred,
+                    other => {
+                        return Err(format!(
+                            "Unknown placement \"{}\", use before-start, after-end or centred",
+                            other
+                        ))
+                    }
+                })
+            }
+            "--curve" => {
+                parsed.curve = Some(match value()?.to_lowercase().as_str() {
+                    "linear" => CrossfadeCurve::Linear,
+                    "equal-power" => CrossfadeCurve::EqualPower,
+                    other => {
+                        return Err(format!(
+                            "Unknown curve \"{}\", use linear or equal-power",
+                            other
+                        ))
+                    }
+                })
+            }
+            "-n" | "--loops" => {
+                let count = value()?;
+                parsed.loop_count = Some(
+                    count
+                        .parse()
+                        .map_err(|_| format!("\"{}\" is not a loop count from 0 to 255", count))?,
+                );
+            }
+            "-f" | "--format" => {
+                let format = value()?.to_lowercase();
+                if format != "wav" && format != "mp3" {
+                    return Err(format!("Unknown format \"{}\", use wav or mp3", format));
+                }
+                parsed.format = Some(format);
+            }
+            "-q" | "--mp3-quality" => {
+                let quality = value()?;
+                parsed.mp3_quality = Some(
+                    quality
+                        .parse()
+                        .ok()
+                        .filter(|quality| *quality <= 9)
+                        .ok_or(format!("\"{}\" is not an MP3 quality from 0 to 9", quality))?,
+                );
+            }
+            "-t" | "--test" => parsed.test = true,
+            "--ffmpeg" => parsed.ffmpeg_path = value()?,
+            "--scratch-dir" => parsed.scratch_dir = Some(PathBuf::from(value()?)),
+            "--keep-intermediates" => parsed.keep_intermediates = true,
+            "--script" => parsed.script = Some(PathBuf::from(value()?)),
+            "--overwrite" => {
+                parsed.overwrite = match value()?.to_lowercase().as_str() {
+                    "ask" => OverwritePolicy::Ask,
+                    "skip" => OverwritePolicy::Skip,
+                    "number" => OverwritePolicy::AutoNumber,
+                    "overwrite" => OverwritePolicy::Overwrite,
+                    other => {
+                        return Err(format!(
+                            "Unknown overwrite policy \"{}\", use ask, skip, number or overwrite",
+                            other
+                        ))
+                    }
+                }
+            }
+            "-v" | "--verbose" => parsed.verbose = true,
+            _ => return Err(format!("Unknown argument \"{}\"", arg)),
+        }
+    }
+
+    Ok(Some(parsed))
+}
+
+// Fills in whatever the arguments left out from the project
+fn apply_project(args: &mut Args, project: &Project) {
+    let seconds = |s: f64| Some(s.to_string());
+    args.input = args
+        .input
+        .take()
+        .or_else(|| Some(project.source.path.display().to_string()));
+    args.output = args.output.take().or_else(|| {
+        project
+            .output
+            .path
+            .as_ref()
+            .map(|path| path.display().to_string())
+    });
+    args.start = args.start.take().or_else(|| seconds(project.times.start_s));
+    args.end = args.end.take().or_else(|| seconds(project.times.end_s));
+    args.crossfade = args
+        .crossfade
+        .take()
+        .or_else(|| seconds(project.times.crossfade_s));
+    args.placement = args.placement.or(Some(project.times.placement));
+    args.curve = args.curve.or(Some(project.times.curve));
+    args.loop_count = args.loop_count.or(Some(project.times.loop_count));
+    // The project's format only fills in an output given without an extension
+    let has_format = args.output.as_deref().is_some_and(|output| {
+        has_extension(output, OutputFormat::Wav.extension())
+            || has_extension(output, OutputFormat::Mp3.extension())
+    });
+    if args.format.is_none() && !has_format {
+        args.format = project
+            .output
+            .format
+            .map(|format| format.extension().to_string());
+    }
+    args.mp3_quality = args.mp3_quality.or(project.output.mp3_quality);
+}
+
+fn has_extension(path: &str, extension: &str) -> bool {
+    path.to_lowercase().ends_with(&format!(".{}", extension))
+}
+
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("error: {}\n\nRun with --help for usage.", message);
+    ExitCode::from(EXIT_USAGE)
+}
+
+/// Renders a loop from command line arguments (without the program name), printing progress to stderr.
+pub fn run_cli(args: impl Iterator<Item = String>) -> ExitCode {
+    let mut args = match parse_args(args) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => return usage_error(&e),
+    };
+
+    if let Some(path) = args.project.clone() {
+        let project = match Project::load(&path) {
+            Ok(project) => project,
+            Err(e) => return usage_error(&e),
+        };
+        // The project's song only matters if --input does not replace it
+        let status = match args.input {
+            Some(_) => SourceStatus::Unchanged,
+            None => project.source_status(),
+        };
+        match status {
+            SourceStatus::Unchanged => {}
+            SourceStatus::Changed => eprintln!(
+                "warning: {} has changed since the project was saved, check the loop points still line up",
+                project.source.path.display()
+            ),
+            SourceStatus::Missing => {
+                return usage_error(&format!(
+                    "The project's song {} could not be found",
+                    project.source.path.display()
+                ))
+            }
+        }
+        apply_project(&mut args, &project);
+    }
+    let required = |value: &Option<String>, flag: &str| {
+        value.clone().ok_or(format!(
+            "{} is required unless a --project provides it",
+            flag
+        ))
+    };
+    let (input, mut output, start, end) = match required(&args.input, "--input").and_then(|input| {
+        Ok((
+            input,
+            required(&args.output, "--output")?,
+            required(&args.start, "--start")?,
+            required(&args.end, "--end")?,
+        ))
+    }) {
+        Ok(values) => values,
+        Err(e) => return usage_error(&e),
+    };
+    let crossfade = args.crossfade.clone().unwrap_or_else(|| "0".to_string());
+    let placement = args.placement.unwrap_or_default();
+    let curve = args.curve.unwrap_or_default();
+    let loop_count = args.loop_count.unwrap_or(1);
+
+    // The output format follows the extension, which --format adds if it is missing
+    match &args.format {
+        Some(format) if !has_extension(&output, format) => {
+            if has_extension(&output, "wav") || has_extension(&output, "mp3") {
+                return usage_error(&format!(
+                    "The output {} does not match the format {}",
+                    output, format
+                ));
+            }
+            output = format!("{}.{}", output, format);
+        }
+        Some(_) => {}
+        None => {
+            if !has_extension(&output, "wav") && !has_extension(&output, "mp3") {
+                return usage_error("The output must end in .wav or .mp3, or pass --format");
+            }
+        }
+    }
+
+    // A script may be run on a machine with a different ffmpeg, so this one is only checked before rendering
+    if args.script.is_none() {
+        let capabilities = match ffmpeg::detect_capabilities(&args.ffmpeg_path) {
+            Ok(capabilities) => capabilities,
+            Err(e) => {
+                eprintln!("error: FFMPEG could not be checked: {}", e);
+                return ExitCode::from(EXIT_FFMPEG_UNAVAILABLE);
+            }
+        };
+        if let Some(missing) = capabilities.missing_summary() {
+            eprintln!(
+                "error: The selected FFMPEG cannot create loops. {}.",
+                missing
+            );
+            return ExitCode::from(EXIT_FFMPEG_UNAVAILABLE);
+        }
+        if has_extension(&output, "mp3") && !capabilities.mp3_output {
+            eprintln!("error: The selected FFMPEG has no MP3 encoder (libmp3lame), save the loop as .wav instead.");
+            return ExitCode::from(EXIT_FFMPEG_UNAVAILABLE);
+        }
+    }
+
+    let backend = FfmpegBackend::new(args.ffmpeg_path.clone());
+    let info = backend.probe(&input).unwrap_or_default();
+    let parse_time = |name: &str, text: &str| {
+        timecode::parse(text, info.sample_rate).map_err(|e| format!("Invalid {}: {}", name, e))
+    };
+    let times = parse_time("start", &start).and_then(|start| {
+        Ok((
+            start,
+            parse_time("end", &end)?,
+            parse_time("crossfade", &crossfade)?,
+        ))
+    });
+    let (start_s, end_s, crossfade_s) = match times {
+        Ok(times) => times,
+        Err(e) => return usage_error(&e),
+    };
+
+    let mut builder = LoopSpec::builder(&input, &output)
+        .start(start_s)
+        .end(end_s)
+        .crossfade(crossfade_s)
+        .placement(placement)
+        .curve(curve)
+        .loop_count(loop_count)
+        .mp3_quality(args.mp3_quality.unwrap_or(DEFAULT_MP3_QUALITY))
+        .test(args.test)
+        .keep_intermediates(args.keep_intermediates);
+    if let Some(dir) = &args.scratch_dir {
+        builder = builder.scratch_dir(dir);
+    }
+    let mut spec = match builder.clone().build() {
+        Ok(spec) => spec,
+        Err(e) => return usage_error(&e),
+    };
+
+    // Tokens in the output's file name are filled in from the spec
+    if spec
+        .output()
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().contains('{'))
+    {
+        let values = NameValues {
+            input: spec.input(),
+            title: info.title.as_deref(),
+            loop_count: spec.loop_count(),
+            duration_s: f64::from(looper::loop_duration(&spec, info.duration_s.unwrap_or(0.0))),
+            start_s: spec.start_s(),
+            end_s: spec.end_s(),
+        };
+        let template = spec
+            .output()
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let name = match naming::expand(&template, &values) {
+            Ok(name) => name,
+            Err(e) => return usage_error(&e),
+        };
+        let extension = spec
+            .output()
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let output = spec
+            .output()
+            .with_file_name(format!("{}.{}", name, extension));
+        builder = builder.output(output);
+        spec = match builder.clone().build() {
+            Ok(spec) => spec,
+            Err(e) => return usage_error(&e),
+        };
+    }
+
+    if let Some(path) = &args.script {
+        // Without a render to check them, the times are checked against the input's duration here
+        if let Err(e) = looper::validate_times(
+            (start_s * 1000.0).round() as u32,
+            (end_s * 1000.0).round() as u32,
+            (crossfade_s * 1000.0).round() as u32,
+            placement,
+            info.duration_s,
+        ) {
+            return usage_error(&e);
+        }
+        let written = RenderScript::new(&spec, &args.ffmpeg_path).and_then(|script| {
+            std::fs::write(path, script.to_script(Shell::for_path(path)))
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+        });
+        return match written {
+            Ok(_) => {
+                println!("{}", path.display());
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("error: {}", e);
+                ExitCode::from(EXIT_RENDER_FAILED)
+            }
+        };
+    }
+
+    if spec.output().exists() {
+        let output = spec.output().display().to_string();
+        let policy = match args.overwrite {
+            OverwritePolicy::Ask if std::io::stdin().is_terminal() => {
+                eprint!("{} already exists. Overwrite it? [y/N] ", output);
+                let mut answer = String::new();
+                let _ = std::io::stdin().read_line(&mut answer);
+                if answer.trim().eq_ignore_ascii_case("y") {
+                    OverwritePolicy::Overwrite
+                } else {
+                    OverwritePolicy::Skip
+                }
+            }
+            OverwritePolicy::Ask => {
+                return usage_error(&format!(
+                    "{} already exists, pass --overwrite with skip, number or overwrite",
+                    output
+                ))
+            }
+            policy => policy,
+        };
+        match naming::resolve_output(spec.output(), policy) {
+            Some(path) if path == spec.output() => {}
+            Some(path) => {
+                spec = match builder.output(path).build() {
+                    Ok(spec) => spec,
+                    Err(e) => return usage_error(&e),
+                }
+            }
+            None => {
+                eprintln!("Skipped, {} already exists", output);
+                return ExitCode::SUCCESS;
+            }
+        }
+    }
+
+    let mut progress = (0, 0);
+    let mut print_event = |event| match event {
+        JobEvent::Progress { done, total } => progress = (done, total),
+        JobEvent::StepStarted(step) => {
+            if progress.1 > 0 && progress.0 < progress.1 {
+                eprintln!("[{}/{}] {}", progress.0 + 1, progress.1, step);
+            } else {
+                eprintln!("{}", step);
+            }
+        }
+        JobEvent::Log { text, level } => match level {
+            LogLevel::Info => eprintln!("{}", text),
+            LogLevel::Output if args.verbose => eprintln!("{}", text),
+            LogLevel::Output => {}
+            LogLevel::Error => eprintln!("{}", text),
+        },
+        JobEvent::Warning(text) => eprintln!("warning: {}", text),
+        // Only a spawned job reports how it ended as an event
+        JobEvent::Failed(_) | JobEvent::Finished { .. } => {}
+    };
+
+    // Nothing cancels a CLI render short of stopping the process
+    match render(&spec, &backend, &mut print_event, &CancelToken::default()) {
+        Ok(rendered) => {
+            eprintln!("{}", rendered.summary);
+            // The written files go to stdout so scripts can pick them up
+            for file in rendered.files() {
+                println!("{}", file.display());
+            }
+            ExitCode::SUCCESS
+        }
+        // The times only turn out not to fit once the input's duration is known
+        Err(JobError::Invalid(e)) => usage_error(&e),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(EXIT_RENDER_FAILED)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::ProjectOutput;
+    use std::path::Path;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("echo_blend_cli_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    // ExitCode cannot be compared, but its debug form holds the code
+    fn run(args: &[&str]) -> String {
+        format!("{:?}", run_cli(args.iter().map(|arg| arg.to_string())))
+    }
+
+    fn exit_code(code: u8) -> String {
+        format!("{:?}", ExitCode::from(code))
+    }
+
+    // A project looping 1 s to 2 s of a song that is deleted once the project is saved
+    fn project_with_missing_song(dir: &Path) -> PathBuf {
+        let song = dir.join("gone.wav");
+        std::fs::write(&song, b"RIFF").unwrap();
+        let mut project = Project::new(&song).unwrap();
+        project.times.start_s = 1.0;
+        project.times.end_s = 2.0;
+        project.times.loop_count = 1;
+        let path = dir.join("song.echoblend");
+        project.save(&path).unwrap();
+        std::fs::remove_file(&song).unwrap();
+        path
+    }
+
+    fn project() -> Project {
+        let mut project = Project::new(file!()).unwrap();
+        project.times.start_s = 1.0;
+        project.times.end_s = 2.5;
+        project.times.crossfade_s = 0.5;
+        project.times.placement = CrossfadePlacement::Centred;
+        project.times.loop_count = 3;
+        project.output = ProjectOutput {
+            path: Some(PathBuf::from("loops/song_loop")),
+            format: Some(OutputFormat::Mp3),
+            mp3_quality: Some(4),
+        };
+        project
+    }
+
+    #[test]
+    fn refuses_an_unknown_flag() {
+        assert_eq!(
+            parse(&["--input", "song.wav", "--loud"]).err(),
+            Some("Unknown argument \"--loud\"".to_string())
+        );
+        assert_eq!(run(&["--loud"]), exit_code(EXIT_USAGE));
+    }
+
+    #[test]
+    fn refuses_a_flag_missing_its_value() {
+        assert_eq!(
+            parse(&["--input", "song.wav", "--start"]).err(),
+            Some("--start needs a value".to_string())
+        );
+        assert_eq!(run(&["--end"]), exit_code(EXIT_USAGE));
+    }
+
+    #[test]
+    fn takes_values_inline_or_after_the_flag() {
+        let args = parse(&["--start=1:00", "-e", "2:00", "-n", "4", "-q", "7"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.start.as_deref(), Some("1:00"));
+        assert_eq!(args.end.as_deref(), Some("2:00"));
+        assert_eq!(args.loop_count, Some(4));
+        assert_eq!(args.mp3_quality, Some(7));
+        assert!(parse(&["-q", "10"]).is_err());
+        assert!(parse(&["--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_a_bad_timecode() {
+        let dir = temp_dir("timecode");
+        let script = dir.join("loop.sh").display().to_string();
+        // Writing a script skips the ffmpeg check, so the times are the first thing to fail
+        let code = run(&[
+            "--input",
+            "song.wav",
+            "--output",
+            "out.wav",
+            "--start",
+            "1:xx",
+            "--end",
+            "2",
+            "--ffmpeg",
+            "echo_blend_missing_ffmpeg",
+            "--script",
+            &script,
+        ]);
+        assert_eq!(code, exit_code(EXIT_USAGE));
+        assert!(!Path::new(&script).exists());
+    }
+
+    #[test]
+    fn refuses_a_project_whose_song_is_missing() {
+        let dir = temp_dir("missing");
+        let project = project_with_missing_song(&dir).display().to_string();
+        let script = dir.join("loop.sh").display().to_string();
+        let code = run(&[
+            "--project",
+            &project,
+            "--output",
+            "out.wav",
+            "--ffmpeg",
+            "echo_blend_missing_ffmpeg",
+            "--script",
+            &script,
+        ]);
+        assert_eq!(code, exit_code(EXIT_USAGE));
+    }
+
+    #[test]
+    fn an_input_replaces_a_project_song_that_is_missing() {
+        let dir = temp_dir("replaced");
+        let project = project_with_missing_song(&dir).display().to_string();
+        let input = dir.join("song.wav");
+        std::fs::write(&input, b"RIFF").unwrap();
+        let script = dir.join("loop.sh");
+        let code = run(&[
+            "--project",
+            &project,
+            "--input",
+            &input.display().to_string(),
+            "--output",
+            &dir.join("out.wav").display().to_string(),
+            "--ffmpeg",
+            "echo_blend_missing_ffmpeg",
+            "--script",
+            &script.display().to_string(),
+        ]);
+        assert_eq!(code, exit_code(0));
+        assert!(std::fs::read_to_string(&script)
+            .unwrap()
+            .contains("song.wav"));
+    }
+
+    #[test]
+    fn the_project_fills_in_what_the_flags_leave_out() {
+        let mut args = parse(&["--start", "1:30", "--loops", "2"])
+            .unwrap()
+            .unwrap();
+        let project = project();
+        apply_project(&mut args, &project);
+        assert_eq!(args.input, Some(project.source.path.display().to_string()));
+        assert_eq!(args.output.as_deref(), Some("loops/song_loop"));
+        assert_eq!(args.start.as_deref(), Some("1:30"));
+        assert_eq!(args.end.as_deref(), Some("2.5"));
+        assert_eq!(args.crossfade.as_deref(), Some("0.5"));
+        assert_eq!(args.placement, Some(CrossfadePlacement::Centred));
+        assert_eq!(args.loop_count, Some(2));
+        // An output without an extension takes the project's format
+        assert_eq!(args.format.as_deref(), Some("mp3"));
+        assert_eq!(args.mp3_quality, Some(4));
+    }
+
+    #[test]
+    fn the_flags_override_the_project_output() {
+        let mut args = parse(&["--input", "other.wav", "--output", "other.wav", "-q", "1"])
+            .unwrap()
+            .unwrap();
+        apply_project(&mut args, &project());
+        assert_eq!(args.input.as_deref(), Some("other.wav"));
+        assert_eq!(args.output.as_deref(), Some("other.wav"));
+        // The extension of the output wins over the project's format
+        assert_eq!(args.format, None);
+        assert_eq!(args.mp3_quality, Some(1));
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+
+// Number of stderr lines kept from a failed ffmpeg run to look for a known failure in
+#[cfg(feature = "native-engine")]
+pub const STDERR_HISTORY: usize = 100;
+
+/// A failed ffmpeg step explained in plain language
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// The step that failed, e.g. "Rendering intro"
+    pub step: String,
+    pub explanation: String,
+    pub fix: String,
+    /// The ffmpeg output the diagnosis is based on
+    pub detail: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed: {}\nSuggested fix: {}",
+            self.step, self.explanation, self.fix
+        )?;
+        if !self.detail.is_empty() {
+            write!(f, "\nffmpeg said: {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "native-engine")]
+struct Pattern {
+    // Lowercase fragments, any of which identifies the failure
+    needles: &'static [&'static str],
+    explanation: &'static str,
+    fix: &'static str,
+}
+
+// Checked in order, so more specific patterns come first
+#[cfg(feature = "native-engine")]
+const PATTERNS: &[Pattern] = &[
+    Pattern {
+        needles: &["no space left on device"],
+        explanation: "The disk ran out of space while writing audio.",
+        fix: "Free up some disk space or save the loop to another drive.",
+    },
+    Pattern {
+        needles: &["permission denied", "access is denied", "operation not permitted"],
+        explanation: "A file could not be read or written because access was denied.",
+        fix: "Save the loop to a folder you can write to, and close any program that has the file open.",
+    },
+    Pattern {
+        needles: &["no such file or directory"],
+        explanation: "A file could not be found.",
+        fix: "Check the source file still exists and drop it into EchoBlend again.",
+    },
+    Pattern {
+        needles: &["invalid data found when processing input", "header missing"],
+        explanation: "The source file is damaged or is not audio ffmpeg can read.",
+        fix: "Open the file in an audio editor and export it again as .wav or .mp3.",
+    },
+    Pattern {
+        needles: &["option 'normalize' not found", "option normalize not found"],
+        explanation: "This ffmpeg is too old to mix crossfades without changing their volume.",
+        fix: "Use ffmpeg 4.4 or newer, e.g. with the Download FFMPEG button.",
+    },
+    Pattern {
+        needles: &["no such filter", "filter not found"],
+        explanation: "This ffmpeg build does not include a filter EchoBlend needs.",
+        fix: "Use a full ffmpeg build, e.g. with the Download FFMPEG button.",
+    },
+    Pattern {
+        needles: &[
+            "unknown encoder",
+            "encoder not found",
+            "decoder (codec",
+            "unsupported codec",
+            "codec not currently supported",
+            "could not find codec parameters",
+        ],
+        explanation: "This ffmpeg build cannot read or write the audio format that was used.",
+        fix: "Use a full ffmpeg build, or convert the file to .wav first.",
+    },
+    Pattern {
+        needles: &["does not contain any stream", "output file is empty"],
+        explanation: "There was no audio to write, usually because a time is past the end of the song.",
+        fix: "Check that the start, end and crossfade times are within the song.",
+    },
+];
+
+#[cfg(feature = "native-engine")]
+/// Explains why an ffmpeg step failed from the last lines it wrote to stderr.
+///
+/// Falls back to the last thing ffmpeg printed when the failure is not recognised.
+pub fn diagnose(step: &str, stderr: &[String], status: &str) -> Diagnostic {
+    let step = step.trim_end_matches('.').to_string();
+    let lower: Vec<String> = stderr.iter().map(|line| line.to_lowercase()).collect();
+    for pattern in PATTERNS {
+        if let Some(i) = lower
+            .iter()
+            .position(|line| pattern.needles.iter().any(|needle| line.contains(needle)))
+        {
+            return Diagnostic {
+                step,
+                explanation: pattern.explanation.to_string(),
+                fix: pattern.fix.to_string(),
+                detail: stderr[i].trim().to_string(),
+            };
+        }
+    }
+
+    Diagnostic {
+        step,
+        explanation: format!("ffmpeg stopped unexpectedly ({}).", status),
+        fix: "Check the console output above for details.".to_string(),
+        detail: stderr
+            .iter()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .map(|line| line.trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(feature = "native-engine")]
+/// Whether a line of ffmpeg output reports a problem
+pub fn is_error_line(line: &str) -> bool {
+    let lower = line.to_lowercase();
+    lower.contains("error")
+        || PATTERNS
+            .iter()
+            .any(|p| p.needles.iter().any(|needle| lower.contains(needle)))
+}
+
+#[cfg(all(test, feature = "native-engine"))]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn the_more_specific_pattern_wins_whatever_the_line_order() {
+        // "No such file or directory" is printed first, but the disk running out of space is the cause
+        let stderr = lines(&[
+            "[in#0] Error opening input file cache.tmp: No such file or directory",
+            "[out#0/wav] Error writing trailer: No space left on device",
+        ]);
+        let diagnostic = diagnose("Rendering intro...", &stderr, "exit status: 1");
+        assert_eq!(diagnostic.step, "Rendering intro");
+        assert_eq!(diagnostic.explanation, PATTERNS[0].explanation);
+        assert_eq!(
+            diagnostic.detail,
+            "[out#0/wav] Error writing trailer: No space left on device"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_last_line() {
+        let stderr = lines(&["Something new went wrong", "Conversion failed!", "  "]);
+        let diagnostic = diagnose("Mixing", &stderr, "exit status: 1");
+        assert!(diagnostic.explanation.contains("exit status: 1"));
+        assert_eq!(diagnostic.detail, "Conversion failed!");
+    }
+}
@@ -1,13 +1,20 @@
-use crate::app;
+use crate::{
+    backend::MediaInfo,
+    diagnostics,
+    job::{self, CancelToken, JobEvent, JobSender, LogLevel},
+};
 use std::io::BufRead;
 
-const FFMPEG_URL: &str = "https://www.gyan.dev/ffmpeg/builds/ffmpeg-git-essentials.7z";
+// How often a running step checks whether its job was cancelled
+const CANCEL_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
 
 pub fn run_ffmpeg(
     ffmpeg_path: &str,
+    step: &str,
     args: &[&str],
-    tx: &std::sync::mpsc::Sender<Result<app::ConsoleText, String>>,
-) -> Result<(), String> {
+    tx: &JobSender,
+    cancel: &CancelToken,
+) -> Result<(), diagnostics::Diagnostic> {
     let mut cmd = match std::process::Command::new(ffmpeg_path)
         .args(args)
         .stdout(std::process::Stdio::piped())
@@ -16,125 +23,470 @@ pub fn run_ffmpeg(
     {
         Ok(cmd) => cmd,
         Err(e) => {
-            return Err(e.to_string());
+            return Err(diagnostics::Diagnostic {
+                step: step.trim_end_matches('.').to_string(),
+                explanation: "FFMPEG could not be started.".to_string(),
+                fix: "Check the FFMPEG path, or download FFMPEG again.".to_string(),
+                detail: e.to_string(),
+            });
         }
     };
 
     // Spawn a thread to handle stdout
-    let stdout = cmd.stdout.take().unwrap();
+    // Both pipes were requested above, so they are always there
+    let stdout = cmd.stdout.take().expect("stdout is piped");
     let tx_stdout = tx.clone();
-    std::thread::spawn(move || {
+    let stdout_thread = std::thread::spawn(move || {
         let reader = std::io::BufReader::new(stdout);
         for line in reader.lines() {
             match line {
                 Ok(line) => {
-                    let _ = tx_stdout.send(Ok(app::ConsoleText::Stdout(line))); // Handle send error gracefully
+                    let _ = tx_stdout.send(JobEvent::Log {
+                        text: line,
+                        level: LogLevel::Output,
+                    }); // Handle send error gracefully
                 }
                 Err(e) => {
-                    let err_msg = e.to_string();
-                    let _ = tx_stdout.send(Err(err_msg.clone())); // Handle send error gracefully
+                    let _ = tx_stdout.send(JobEvent::Warning(format!(
+                        "Failed to read ffmpeg output: {}",
+                        e
+                    ))); // Handle send error gracefully
                     return;
                 }
             }
         }
     });
 
-    // Spawn another thread to handle stderr
-    let stderr = cmd.stderr.take().unwrap();
+    // Spawn another thread to handle stderr, keeping the last lines to diagnose a failure with
+    let stderr = cmd.stderr.take().expect("stderr is piped");
     let tx_stderr = tx.clone();
-    std::thread::spawn(move || {
+    let stderr_thread = std::thread::spawn(move || {
         let reader = std::io::BufReader::new(stderr);
-        let mut on_error = false;
+        let mut history = std::collections::VecDeque::new();
         for line in reader.lines() {
             match line {
                 Ok(line) => {
-                    if line.to_lowercase().contains("error") || on_error {
-                        on_error = true;
-                        let _ = tx_stderr.send(Ok(app::ConsoleText::Stderr(line)));
-                    // Adjust as needed
-                    } else {
-                        let _ = tx_stderr.send(Ok(app::ConsoleText::Stdout(line)));
-                        // Handle send error gracefully
+                    if history.len() == diagnostics::STDERR_HISTORY {
+                        history.pop_front();
                     }
+                    history.push_back(line.clone());
+                    let level = if diagnostics::is_error_line(&line) {
+                        LogLevel::Error
+                    } else {
+                        LogLevel::Output
+                    };
+                    let _ = tx_stderr.send(JobEvent::Log { text: line, level });
+                    // Handle send error gracefully
                 }
                 Err(e) => {
-                    let err_msg = e.to_string();
-                    let _ = tx_stderr.send(Err(err_msg.clone())); // Handle send error gracefully
-                    return;
+                    let _ = tx_stderr.send(JobEvent::Warning(format!(
+                        "Failed to read ffmpeg output: {}",
+                        e
+                    ))); // Handle send error gracefully
+                    break;
                 }
             }
         }
+        Vec::from(history)
     });
 
-    // Check the process's exit status
-    match cmd.wait() {
-        Ok(status) if status.success() => Ok(()),
-        Ok(status) => Err(format!("ffmpeg exited with error code: {}", status)),
-        Err(e) => Err(e.to_string()),
-    }
-}
-
-pub fn get_ffmpeg(tx: std::sync::mpsc::Sender<Result<std::path::PathBuf, String>>) {
-    std::thread::spawn(move || {
-        let result = (|| -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
-            let ffmpeg_path = std::env::current_dir()?
-                .join("ffmpeg")
-                .join("bin")
-                .join("ffmpeg.exe");
-            if ffmpeg_path.exists() {
-                Ok(ffmpeg_path)
-            } else {
-                download_ffmpeg()?;
-                let ffmpeg_path = extract_ffmpeg()?;
-                std::process::Command::new(&ffmpeg_path).output()?;
-                Ok(std::path::PathBuf::from(ffmpeg_path))
+    // Wait for the process to exit, killing it if the job is cancelled in the meantime
+    let status = loop {
+        match cmd.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) if cancel.is_cancelled() => {
+                let _ = cmd.kill();
+                break cmd.wait();
             }
-        })();
-
-        // Send the result back to the UI thread
-        let _ = tx.send(result.map_err(|e| e.to_string()));
-    });
+            Ok(None) => std::thread::sleep(CANCEL_POLL_INTERVAL),
+            Err(e) => break Err(e),
+        }
+    };
+    // Wait for the readers so no output arrives after the step has finished
+    if let Err(panic) = stdout_thread.join() {
+        let _ = tx.send(JobEvent::Warning(format!(
+            "The ffmpeg output reader crashed: {}",
+            job::panic_message(panic.as_ref())
+        )));
+    }
+    let stderr_lines = match stderr_thread.join() {
+        Ok(lines) => lines,
+        Err(panic) => {
+            let _ = tx.send(JobEvent::Warning(format!(
+                "The ffmpeg error reader crashed: {}",
+                job::panic_message(panic.as_ref())
+            )));
+            Vec::new()
+        }
+    };
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(diagnostics::diagnose(
+            step,
+            &stderr_lines,
+            &format!("exit code {}", status.code().unwrap_or(-1)),
+        )),
+        Err(e) => Err(diagnostics::diagnose(step, &stderr_lines, &e.to_string())),
+    }
 }
 
-fn download_ffmpeg() -> Result<(), Box<dyn std::error::Error>> {
-    let mut response = reqwest::blocking::get(FFMPEG_URL)?;
-    let mut file = std::fs::File::create("ffmpeg.7z")?;
-    std::io::copy(&mut response, &mut file)?;
-    Ok(())
-}
-
-// Extract ffmpeg and provide the path to the binary
-fn extract_ffmpeg() -> Result<String, Box<dyn std::error::Error>> {
-    std::process::Command::new("7z")
-        .arg("x")
-        .arg("ffmpeg.7z")
-        .arg("-o./ffmpeg")
-        .arg("-y")
-        .output()?;
-    std::fs::remove_file("ffmpeg.7z")?;
-    let dir = std::env::current_dir()?.join("ffmpeg");
-    let ffmpeg_path = find_ffmpeg(&dir)?;
-    Ok(ffmpeg_path)
-}
-
-fn find_ffmpeg(dir: &std::path::PathBuf) -> Result<String, Box<dyn std::error::Error>> {
-    let mut ffmpeg_path = String::new();
-    for entry in std::fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                if ext == "exe" {
-                    ffmpeg_path = path.to_str().unwrap().to_string();
-                    break;
+// Read the stream information ffmpeg prints when it is given an input without an output
+pub fn probe(ffmpeg_path: &str, input_path: &str) -> Result<MediaInfo, String> {
+    let output = std::process::Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-i", input_path])
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    let mut info = MediaInfo::default();
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        if let Some(rest) = line.trim().strip_prefix("Duration: ") {
+            info.duration_s = rest.split(',').next().and_then(parse_timestamp);
+        } else if line.contains("Audio: ") && info.sample_rate.is_none() {
+            // e.g. "Stream #0:0: Audio: mp3, 44100 Hz, stereo, fltp, 320 kb/s"
+            info.sample_rate = line
+                .split(',')
+                .find_map(|field| field.trim().strip_suffix(" Hz")?.parse().ok());
+            info.channels = line
+                .split(',')
+                .find_map(|field| parse_channels(field.trim()));
+        } else if let Some((key, value)) = line.split_once(':') {
+            // e.g. "    comment         : Recorded live", the first being the file's own tag
+            match key.trim() {
+                "comment" if info.comment.is_none() => {
+                    info.comment = Some(value.trim().to_string())
                 }
+                "title" if info.title.is_none() => info.title = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+    Ok(info)
+}
+
+// Parse an ffmpeg channel layout such as stereo, 5.1(side) or 3 channels
+fn parse_channels(layout: &str) -> Option<u32> {
+    match layout {
+        "mono" => Some(1),
+        "stereo" => Some(2),
+        _ => {
+            if let Some(count) = layout.strip_suffix(" channels") {
+                return count.parse().ok();
             }
-        } else if path.is_dir() {
-            ffmpeg_path = find_ffmpeg(&path)?;
-            if !ffmpeg_path.is_empty() {
-                break;
+            // Surround layouts count the main channels before the dot and the LFE after it
+            let (main, lfe) = layout.split('(').next()?.split_once('.')?;
+            Some(main.parse::<u32>().ok()? + lfe.parse::<u32>().ok()?)
+        }
+    }
+}
+
+// Parse an ffmpeg timestamp such as 00:03:25.47
+fn parse_timestamp(timestamp: &str) -> Option<f32> {
+    let mut seconds = 0.0;
+    for part in timestamp.trim().split(':') {
+        seconds = seconds * 60.0 + part.parse::<f32>().ok()?;
+    }
+    Some(seconds)
+}
+
+/// A 32-bit float WAV stream decoded by ffmpeg, which fails if ffmpeg does once the stream ends
+pub struct PcmStream {
+    child: std::process::Child,
+    stdout: std::process::ChildStdout,
+}
+
+impl std::io::Read for PcmStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("ffmpeg exited with error code: {}", status),
+                ));
             }
         }
+        Ok(read)
+    }
+}
+
+impl Drop for PcmStream {
+    fn drop(&mut self) {
+        // Stop ffmpeg if the stream was not read to the end
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+// Spawn ffmpeg decoding the input to a 32-bit float WAV stream on stdout
+pub fn decode_pcm(ffmpeg_path: &str, input_path: &str) -> Result<PcmStream, String> {
+    let mut child = std::process::Command::new(ffmpeg_path)
+        .args([
+            "-v",
+            "error",
+            "-i",
+            input_path,
+            "-map",
+            "0:a:0",
+            "-c:a",
+            "pcm_f32le",
+            "-f",
+            "wav",
+            "-",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let stdout = child.stdout.take().ok_or("Failed to read decoded audio")?;
+    Ok(PcmStream { child, stdout })
+}
+
+// Filters and encoders every loop needs
+const REQUIRED_FILTERS: [&str; 3] = ["afade", "amix", "concat"];
+const REQUIRED_ENCODERS: [&str; 2] = ["pcm_s16le", "pcm_f32le"];
+// Only needed to write MP3 files
+const MP3_ENCODER: &str = "libmp3lame";
+
+/// What the selected ffmpeg binary can do
+#[derive(Debug, Default, Clone)]
+pub struct Capabilities {
+    pub version: String,
+    pub missing_filters: Vec<String>,
+    pub missing_encoders: Vec<String>,
+    pub mp3_output: bool,
+}
+
+impl Capabilities {
+    /// Whether everything needed to create a loop is available
+    pub fn can_loop(&self) -> bool {
+        self.missing_filters.is_empty() && self.missing_encoders.is_empty()
+    }
+
+    /// Describes what is missing, or `None` if everything is available
+    pub fn missing_summary(&self) -> Option<String> {
+        let mut missing: Vec<String> = Vec::new();
+        if !self.missing_filters.is_empty() {
+            missing.push(format!("filters: {}", self.missing_filters.join(", ")));
+        }
+        if !self.missing_encoders.is_empty() {
+            missing.push(format!("encoders: {}", self.missing_encoders.join(", ")));
+        }
+        (!missing.is_empty()).then(|| format!("Missing {}", missing.join("; ")))
+    }
+}
+
+// Run ffmpeg with the given arguments and return its stdout
+fn query(ffmpeg_path: &str, args: &[&str]) -> Result<String, String> {
+    let output = std::process::Command::new(ffmpeg_path)
+        .args(args)
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffmpeg {} failed: {}",
+            args.join(" "),
+            output.status
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// Collect the names from a -filters or -encoders listing, e.g. " ... afade  A->A  Fade in/out input audio."
+fn listed_names(listing: &str) -> std::collections::HashSet<&str> {
+    listing
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .collect()
+}
+
+// Check the version, filters and encoders of an ffmpeg binary
+pub fn detect_capabilities(ffmpeg_path: &str) -> Result<Capabilities, String> {
+    let version = query(ffmpeg_path, &["-hide_banner", "-version"])?;
+    let filters = query(ffmpeg_path, &["-hide_banner", "-filters"])?;
+    // amix only gained the normalize option in ffmpeg 4.4, older builds fail midway through a loop
+    let amix_help = if listed_names(&filters).contains("amix") {
+        Some(query(ffmpeg_path, &["-hide_banner", "-h", "filter=amix"])?)
+    } else {
+        None
+    };
+    let encoders = query(ffmpeg_path, &["-hide_banner", "-encoders"])?;
+    parse_capabilities(&version, &filters, amix_help.as_deref(), &encoders)
+}
+
+// Find the version in the first line of -version, e.g. "ffmpeg version 6.1.1 Copyright (c) ..."
+fn parse_version(output: &str) -> Option<&str> {
+    output
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("ffmpeg version "))
+        .and_then(|rest| rest.split_whitespace().next())
+}
+
+// Work out the capabilities from what ffmpeg printed, `amix_help` being None if amix is not listed
+fn parse_capabilities(
+    version: &str,
+    filters: &str,
+    amix_help: Option<&str>,
+    encoders: &str,
+) -> Result<Capabilities, String> {
+    let version = parse_version(version)
+        .ok_or("The selected file does not look like FFMPEG")?
+        .to_string();
+
+    let filters = listed_names(filters);
+    let mut missing_filters: Vec<String> = REQUIRED_FILTERS
+        .iter()
+        .filter(|f| !filters.contains(*f))
+        .map(|f| f.to_string())
+        .collect();
+    if amix_help.is_some_and(|help| !help.contains("normalize")) {
+        missing_filters.push("amix normalize option (ffmpeg 4.4 or newer)".to_string());
+    }
+
+    let encoders = listed_names(encoders);
+    let missing_encoders = REQUIRED_ENCODERS
+        .iter()
+        .filter(|e| !encoders.contains(*e))
+        .map(|e| e.to_string())
+        .collect();
+
+    Ok(Capabilities {
+        version,
+        missing_filters,
+        missing_encoders,
+        mp3_output: encoders.contains(MP3_ENCODER),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Captured from ffmpeg 6.1.1, trimmed to the lines that matter
+    const VERSION: &str =
+        "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers
+built with gcc 13 (Ubuntu 13.2.0-23ubuntu3)
+libavutil      58. 29.100 / 58. 29.100
+";
+    const FILTERS: &str = "Filters:
+  T.. = Timeline support
+  .S. = Slice threading
+  ..C = Command support
+  A = Audio input/output
+  V = Video input/output
+  N = Dynamic number and/or type of input/output
+  | = Source or sink filter
+ ... abench            A->A       Benchmark part of a filtergraph.
+ TSC acrossfade        AA->A      Cross fade two input audio streams.
+ T.. afade             A->A       Fade in/out input audio.
+ ..C amix              N->A       Audio mixing.
+ ... concat            N->N       Concatenate audio and video streams.
+";
+    const AMIX_HELP: &str = "Filter amix
+  Audio mixing.
+    Inputs:
+        dynamic (depending on the options)
+    Outputs:
+       #0: default (audio)
+amix AVOptions:
+   inputs            <int>        ..F.A...... Number of inputs. (from 1 to 32767) (default 2)
+   duration          <int>        ..F.A...... How to determine the end-of-stream. (from 0 to 2) (default longest)
+   normalize         <boolean>    ..F.A....T. Scale inputs (default true)
+";
+    const ENCODERS: &str = "Encoders:
+ V..... = Video
+ A..... = Audio
+ S..... = Subtitle
+ ------
+ A....D pcm_f32le            PCM 32-bit floating point little-endian
+ A....D pcm_s16le            PCM signed 16-bit little-endian
+ A....D libmp3lame           libmp3lame MP3 (MPEG audio layer 3) (codec mp3)
+";
+
+    #[test]
+    fn reads_the_version() {
+        assert_eq!(parse_version(VERSION), Some("6.1.1-3ubuntu5"));
+        assert_eq!(parse_version("ffprobe version 6.1.1 Copyright"), None);
+        assert_eq!(parse_version(""), None);
+    }
+
+    #[test]
+    fn lists_the_names_without_the_legend() {
+        let filters = listed_names(FILTERS);
+        for name in ["abench", "acrossfade", "afade", "amix", "concat"] {
+            assert!(filters.contains(name), "{} is not listed", name);
+        }
+        assert!(!filters.contains("Timeline"));
+        let encoders = listed_names(ENCODERS);
+        assert!(encoders.contains("pcm_s16le"));
+        assert!(encoders.contains("libmp3lame"));
+        assert!(!encoders.contains("Video"));
+    }
+
+    #[test]
+    fn a_full_build_can_loop() {
+        let capabilities = parse_capabilities(VERSION, FILTERS, Some(AMIX_HELP), ENCODERS).unwrap();
+        assert_eq!(capabilities.version, "6.1.1-3ubuntu5");
+        assert!(capabilities.can_loop());
+        assert!(capabilities.mp3_output);
+        assert_eq!(capabilities.missing_summary(), None);
+    }
+
+    #[test]
+    fn reports_what_a_build_is_missing() {
+        let filters = FILTERS.replace("concat", "aresample");
+        let encoders = ENCODERS
+            .replace("libmp3lame", "aac")
+            .replace("pcm_f32le", "pcm_u8");
+        let capabilities = parse_capabilities(VERSION, &filters, None, &encoders).unwrap();
+        assert_eq!(capabilities.missing_filters, vec!["concat".to_string()]);
+        assert_eq!(capabilities.missing_encoders, vec!["pcm_f32le".to_string()]);
+        assert!(!capabilities.mp3_output);
+        assert_eq!(
+            capabilities.missing_summary().as_deref(),
+            Some("Missing filters: concat; encoders: pcm_f32le")
+        );
+    }
+
+    #[test]
+    fn an_amix_without_normalize_is_too_old() {
+        let amix_help = AMIX_HELP.replace("normalize", "weights");
+        let capabilities =
+            parse_capabilities(VERSION, FILTERS, Some(&amix_help), ENCODERS).unwrap();
+        assert_eq!(
+            capabilities.missing_filters,
+            vec!["amix normalize option (ffmpeg 4.4 or newer)".to_string()]
+        );
+        assert!(!capabilities.can_loop());
+    }
+
+    #[test]
+    fn refuses_something_other_than_ffmpeg() {
+        assert!(parse_capabilities("Python 3.12.3", FILTERS, None, ENCODERS).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cancelling_kills_the_running_step() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let cancel = CancelToken::default();
+        let canceller = cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            canceller.cancel();
+        });
+        let started = std::time::Instant::now();
+        let result = run_ffmpeg(
+            "sh",
+            "Rendering intro...",
+            &["-c", "exec sleep 30"],
+            &tx,
+            &cancel,
+        );
+        assert!(result.is_err());
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
     }
-    Ok(ffmpeg_path)
 }
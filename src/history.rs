@@ -0,0 +1,145 @@
+// Entries kept before the oldest are dropped
+const MAX_ENTRIES: usize = 100;
+
+/// Snapshots of an edited state, each labelled with the edit that produced it
+pub struct History<T> {
+    entries: Vec<(String, T)>,
+    // Index of the entry matching the current state
+    current: usize,
+}
+
+impl<T: Clone + PartialEq> History<T> {
+    pub fn new(initial: T) -> Self {
+        Self {
+            entries: vec![("Start".to_string(), initial)],
+            current: 0,
+        }
+    }
+
+    pub fn current(&self) -> &T {
+        &self.entries[self.current].1
+    }
+
+    /// Records `state` as a new edit, dropping anything that was undone.
+    pub fn record(&mut self, label: String, state: T) {
+        if *self.current() == state {
+            return;
+        }
+        self.entries.truncate(self.current + 1);
+        self.entries.push((label, state));
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.current = self.entries.len() - 1;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.current > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.current + 1 < self.entries.len()
+    }
+
+    pub fn undo(&mut self) -> Option<&T> {
+        self.jump(self.current.checked_sub(1)?)
+    }
+
+    pub fn redo(&mut self) -> Option<&T> {
+        self.jump(self.current + 1)
+    }
+
+    /// Moves to an earlier or later entry, keeping the ones after it for redo
+    pub fn jump(&mut self, index: usize) -> Option<&T> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        self.current = index;
+        Some(self.current())
+    }
+
+    /// The labels of every entry, oldest first, and the index of the current one
+    pub fn labels(&self) -> (Vec<&str>, usize) {
+        (
+            self.entries
+                .iter()
+                .map(|(label, _)| label.as_str())
+                .collect(),
+            self.current,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A history of 0 followed by an edit to each of `states`
+    fn history(states: &[u32]) -> History<u32> {
+        let mut history = History::new(0);
+        for state in states {
+            history.record(format!("Set {}", state), *state);
+        }
+        history
+    }
+
+    #[test]
+    fn undoes_and_redoes_each_edit() {
+        let mut history = history(&[1, 2]);
+        assert!(!history.can_redo());
+        assert_eq!(history.undo(), Some(&1));
+        assert_eq!(history.undo(), Some(&0));
+        assert!(history.can_redo());
+        assert_eq!(history.redo(), Some(&1));
+        assert_eq!(history.redo(), Some(&2));
+        assert_eq!(history.redo(), None);
+        assert_eq!(history.current(), &2);
+    }
+
+    #[test]
+    fn cannot_undo_past_the_oldest_entry() {
+        let mut history = history(&[]);
+        assert!(!history.can_undo());
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.current(), &0);
+    }
+
+    #[test]
+    fn an_unchanged_state_is_not_recorded() {
+        let history = history(&[1, 1]);
+        assert_eq!(history.labels(), (vec!["Start", "Set 1"], 1));
+    }
+
+    #[test]
+    fn a_new_edit_drops_what_was_undone() {
+        let mut history = history(&[1, 2, 3]);
+        history.undo();
+        history.undo();
+        history.record("Set 4".to_string(), 4);
+        assert_eq!(history.labels(), (vec!["Start", "Set 1", "Set 4"], 2));
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn jumps_to_any_entry() {
+        let mut history = history(&[1, 2, 3]);
+        assert_eq!(history.jump(1), Some(&1));
+        assert_eq!(history.labels().1, 1);
+        // The entries after it stay for redo
+        assert_eq!(history.jump(3), Some(&3));
+        assert_eq!(history.jump(4), None);
+        assert_eq!(history.current(), &3);
+    }
+
+    #[test]
+    fn keeps_only_the_newest_entries() {
+        let states: Vec<u32> = (1..=150).collect();
+        let mut history = history(&states);
+        let (labels, current) = history.labels();
+        assert_eq!(labels.len(), MAX_ENTRIES);
+        assert_eq!(labels[0], "Set 51");
+        assert_eq!(current, MAX_ENTRIES - 1);
+        assert_eq!(history.jump(0), Some(&51));
+        assert_eq!(history.undo(), None);
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::diagnostics::Diagnostic;
+
+pub type JobSender = std::sync::mpsc::Sender<JobEvent>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogLevel {
+    /// Messages from EchoBlend itself
+    Info,
+    /// Output from ffmpeg
+    Output,
+    /// Output that reports a problem
+    Error,
+}
+
+/// Everything a running job reports back to the UI, in the order it happens
+#[derive(Debug)]
+pub enum JobEvent {
+    StepStarted(String),
+    Log {
+        text: String,
+        level: LogLevel,
+    },
+    Progress {
+        done: usize,
+        total: usize,
+    },
+    /// Something went wrong that does not stop the job
+    Warning(String),
+    Failed(JobError),
+    Finished {
+        outputs: Vec<PathBuf>,
+        summary: String,
+    },
+}
+
+impl JobEvent {
+    pub fn info(text: impl Into<String>) -> Self {
+        JobEvent::Log {
+            text: text.into(),
+            level: LogLevel::Info,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    /// An ffmpeg step failed
+    Step(Diagnostic),
+    /// A file could not be read or written
+    Io { context: String, message: String },
+    /// The job panicked, during `step` if one had started
+    Panicked {
+        step: Option<String>,
+        message: String,
+    },
+    /// The job was asked to do something that cannot work
+    Invalid(String),
+    /// The job stopped without reporting a result
+    Stopped,
+    /// The job was cancelled before it finished
+    Cancelled,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Step(diagnostic) => diagnostic.fmt(f),
+            JobError::Io { context, message } => write!(f, "{}: {}", context, message),
+            JobError::Panicked {
+                step: Some(step),
+                message,
+            } => write!(f, "{} crashed: {}", step.trim_end_matches('.'), message),
+            JobError::Panicked {
+                step: None,
+                message,
+            } => write!(f, "The job crashed: {}", message),
+            JobError::Invalid(message) => write!(f, "{}", message),
+            JobError::Stopped => write!(f, "The job stopped without reporting a result."),
+            JobError::Cancelled => write!(f, "The job was cancelled."),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+impl From<Diagnostic> for JobError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        JobError::Step(diagnostic)
+    }
+}
+
+/// Asks a running job to stop, shared between the job and whoever started it.
+///
+/// The job checks it between steps, and the ffmpeg backend kills the step that is running.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fails with [`JobError::Cancelled`] once the job has been cancelled
+    pub fn check(&self) -> Result<(), JobError> {
+        if self.is_cancelled() {
+            Err(JobError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Receives the events of a job as they happen
+pub trait Observer {
+    fn on_event(&mut self, event: JobEvent);
+}
+
+impl Observer for JobSender {
+    fn on_event(&mut self, event: JobEvent) {
+        // Nobody is listening any more if this fails, which does not stop the job
+        let _ = self.send(event);
+    }
+}
+
+impl<F: FnMut(JobEvent)> Observer for F {
+    fn on_event(&mut self, event: JobEvent) {
+        self(event)
+    }
+}
+
+/// Runs `job` on a worker thread, passing its events to `observer` until it ends.
+///
+/// Returns once the job and every thread holding one of its senders has stopped.
+/// A panic in the job is returned as [`JobError::Panicked`] naming the step it happened in.
+pub fn run<T, J>(observer: &mut dyn Observer, job: J) -> Result<T, JobError>
+where
+    T: Send,
+    J: FnOnce(&JobSender) -> Result<T, JobError> + Send,
+{
+    let (job_tx, job_rx) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+        let worker = scope.spawn(move || job(&job_tx));
+
+        // Ends once the worker and any reader threads it started have dropped their senders
+        let mut step = None;
+        for event in job_rx {
+            if let JobEvent::StepStarted(started) = &event {
+                step = Some(started.clone());
+            }
+            observer.on_event(event);
+        }
+
+        worker.join().unwrap_or_else(|panic| {
+            Err(JobError::Panicked {
+                step,
+                message: panic_message(panic.as_ref()),
+            })
+        })
+    })
+}
+
+/// The message a panic was raised with
+pub fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::FailingBackend,
+        looper::{render_loop, TempFiles},
+        render::LoopSpec,
+    };
+    use std::{path::Path, time::Duration};
+
+    #[test]
+    fn a_backend_panicking_mid_step_fails_the_job() {
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        // Run on its own thread so a hang fails the test instead of stalling it
+        std::thread::spawn(move || {
+            let spec = LoopSpec::builder("song.wav", "out.wav")
+                .start(10.0)
+                .end(20.0)
+                .build()
+                .unwrap();
+            let backend = FailingBackend { panics: true };
+            let dir = Path::new("work");
+            let files = TempFiles::in_dir(dir);
+            let mut steps = Vec::new();
+            let result = run(
+                &mut |event| {
+                    if let JobEvent::StepStarted(step) = event {
+                        steps.push(step);
+                    }
+                },
+                |tx| {
+                    render_loop(
+                        &backend,
+                        &spec,
+                        &files,
+                        dir,
+                        &[],
+                        tx,
+                        &CancelToken::default(),
+                    )
+                },
+            );
+            let _ = done_tx.send((result, steps));
+        });
+
+        let (result, steps) = done_rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the job did not finish");
+        assert_eq!(steps, vec!["Rendering intro...".to_string()]);
+        match result {
+            Err(JobError::Panicked { step, message }) => {
+                assert_eq!(step.as_deref(), Some("Rendering intro..."));
+                assert_eq!(message, "The test backend panicked in Rendering intro...");
+            }
+            result => panic!("expected a panic, got {:?}", result.err()),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::path::{Path, PathBuf};
+
+use crate::{app::ConsoleText, project::ProjectTimes};
+
+// Jobs kept before the oldest are deleted
+const MAX_JOBS: usize = 100;
+
+/// How a job ended
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub enum JobOutcome {
+    Succeeded { summary: String },
+    Failed { error: String },
+}
+
+/// A render that was run, with everything it printed
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct JobRecord {
+    /// Milliseconds since the Unix epoch when the job started, which also names its file
+    pub id: u64,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub test: bool,
+    pub times: ProjectTimes,
+    /// Every file the job wrote
+    pub outputs: Vec<PathBuf>,
+    pub duration_s: f64,
+    pub outcome: JobOutcome,
+    pub log: Vec<ConsoleText>,
+}
+
+impl JobRecord {
+    pub fn succeeded(&self) -> bool {
+        matches!(self.outcome, JobOutcome::Succeeded { .. })
+    }
+}
+
+/// Past jobs, each stored as a JSON file in one directory
+pub struct JobHistory {
+    dir: PathBuf,
+    // Newest first
+    records: Vec<JobRecord>,
+}
+
+impl JobHistory {
+    /// Reads every job in `dir`, skipping any file that cannot be read
+    pub fn load(dir: &Path) -> Self {
+        let mut records: Vec<JobRecord> = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|entry| {
+                let json = std::fs::read_to_string(entry.path()).ok()?;
+                serde_json::from_str(&json).ok()
+            })
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.id));
+        Self {
+            dir: dir.to_path_buf(),
+            records,
+        }
+    }
+
+    pub fn records(&self) -> &[JobRecord] {
+        &self.records
+    }
+
+    /// Stores a finished job, deleting the oldest ones past the limit
+    pub fn add(&mut self, record: JobRecord) -> Result<(), String> {
+        std::fs::create_dir_all(&self.dir).map_err(|e| {
+            format!(
+                "Failed to create history folder {}: {}",
+                self.dir.display(),
+                e
+            )
+        })?;
+        let json = serde_json::to_string(&record).map_err(|e| e.to_string())?;
+        let path = self.path(record.id);
+        std::fs::write(&path, json)
+            .map_err(|e| format!("Failed to save job {}: {}", path.display(), e))?;
+        self.records.insert(0, record);
+        while self.records.len() > MAX_JOBS {
+            if let Some(oldest) = self.records.pop() {
+                let _ = std::fs::remove_file(self.path(oldest.id));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        for record in std::mem::take(&mut self.records) {
+            let _ = std::fs::remove_file(self.path(record.id));
+        }
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "echo_blend_job_history_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn record(id: u64) -> JobRecord {
+        JobRecord {
+            id,
+            input: PathBuf::from("song.wav"),
+            output: PathBuf::from("song_loop.wav"),
+            test: false,
+            times: ProjectTimes::default(),
+            outputs: vec![PathBuf::from("song_loop.wav")],
+            duration_s: 1.5,
+            outcome: JobOutcome::Succeeded {
+                summary: "Done!".to_string(),
+            },
+            log: Vec::new(),
+        }
+    }
+
+    fn ids(history: &JobHistory) -> Vec<u64> {
+        history.records().iter().map(|record| record.id).collect()
+    }
+
+    #[test]
+    fn loads_the_jobs_it_added_newest_first() {
+        let dir = temp_dir("added");
+        let mut history = JobHistory::load(&dir);
+        history.add(record(2)).unwrap();
+        history.add(record(1)).unwrap();
+        history.add(record(3)).unwrap();
+        assert_eq!(ids(&history), vec![3, 1, 2]);
+
+        let loaded = JobHistory::load(&dir);
+        assert_eq!(ids(&loaded), vec![3, 2, 1]);
+        assert_eq!(loaded.records()[0].outcome, record(3).outcome);
+    }
+
+    #[test]
+    fn clearing_deletes_every_job() {
+        let dir = temp_dir("cleared");
+        let mut history = JobHistory::load(&dir);
+        history.add(record(1)).unwrap();
+        history.add(record(2)).unwrap();
+        history.clear();
+        assert!(history.records().is_empty());
+        assert!(JobHistory::load(&dir).records().is_empty());
+    }
+
+    #[test]
+    fn deletes_the_oldest_jobs_past_the_limit() {
+        let dir = temp_dir("limit");
+        let mut history = JobHistory::load(&dir);
+        for id in 1..=MAX_JOBS as u64 + 5 {
+            history.add(record(id)).unwrap();
+        }
+        assert_eq!(history.records().len(), MAX_JOBS);
+        assert_eq!(history.records().last().map(|record| record.id), Some(6));
+        assert!(!dir.join("5.json").exists());
+        assert_eq!(JobHistory::load(&dir).records().len(), MAX_JOBS);
+    }
+
+    #[test]
+    fn skips_files_that_are_not_jobs() {
+        let dir = temp_dir("corrupt");
+        let mut history = JobHistory::load(&dir);
+        history.add(record(1)).unwrap();
+        std::fs::write(dir.join("2.json"), "{ \"id\": 2, ").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a job").unwrap();
+        assert_eq!(ids(&JobHistory::load(&dir)), vec![1]);
+    }
+
+    #[test]
+    fn a_missing_folder_is_an_empty_history() {
+        let dir = temp_dir("missing").join("history");
+        let mut history = JobHistory::load(&dir);
+        assert!(history.records().is_empty());
+        // The folder is made once the first job is added
+        history.add(record(1)).unwrap();
+        assert!(dir.join("1.json").is_file());
+    }
+}
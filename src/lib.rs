@@ -1,7 +1,93 @@
+//! Renders seamless loops of songs with ffmpeg.
+//!
+//! Describe a loop with [`LoopSpec::builder`] and render it with [`render`]:
+//!
+//! ```no_run
+//! # #[cfg(feature = "native-engine")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use echo_blend::{render, CancelToken, FfmpegBackend, JobEvent, LoopSpec};
+//!
+//! let spec = LoopSpec::builder("song.wav", "song_loop.wav")
+//!     .start(12.5)
+//!     .end(95.0)
+//!     .crossfade(0.5)
+//!     .loop_count(3)
+//!     .build()?;
+//! let backend = FfmpegBackend::new("ffmpeg");
+//! let print = &mut |event: JobEvent| println!("{:?}", event);
+//! let rendered = render(&spec, &backend, print, &CancelToken::default())?;
+//! println!("{}", rendered.summary);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "native-engine"))]
+//! # fn main() {}
+//! ```
+//!
+//! Only the loop engine is built with `default-features = false`. The `native-engine` feature adds
+//! [`FfmpegBackend`], `cli` the `echo_blend_cli` binary, `ffmpeg-download` fetching a build of
+//! ffmpeg, and `gui` the EchoBlend window. All of them are enabled by default.
 #![warn(clippy::all, rust_2018_idioms)]
 
+#[cfg(feature = "gui")]
 mod app;
+#[cfg(feature = "ffmpeg-download")]
+mod archive;
+mod backend;
+#[cfg(feature = "cli")]
+mod cli;
+mod diagnostics;
+#[cfg(feature = "native-engine")]
 mod ffmpeg;
+mod hash;
+#[cfg(feature = "gui")]
+mod history;
+mod job;
+#[cfg(feature = "gui")]
+mod job_history;
 mod looper;
+mod naming;
+mod project;
+#[cfg(feature = "ffmpeg-download")]
+mod provision;
+mod recipe;
+mod render;
+#[cfg(feature = "native-engine")]
+mod script;
+mod seams;
+#[cfg(feature = "gui")]
+mod settings;
+#[cfg(any(feature = "gui", feature = "cli"))]
+mod timecode;
+#[cfg(feature = "gui")]
 mod ui;
+mod workspace;
+#[cfg(feature = "gui")]
 pub use app::App;
+#[cfg(feature = "native-engine")]
+pub use backend::FfmpegBackend;
+pub use backend::{
+    Call, Codec, Fade, MediaBackend, MediaInfo, RecordingBackend, Segment, StepContext,
+};
+#[cfg(feature = "cli")]
+pub use cli::run_cli;
+pub use diagnostics::Diagnostic;
+#[cfg(feature = "native-engine")]
+pub use ffmpeg::{detect_capabilities, Capabilities};
+pub use job::{CancelToken, JobError, JobEvent, JobSender, LogLevel, Observer};
+pub use looper::{loop_duration, plan, CrossfadeCurve, CrossfadePlacement, Section, SectionKind};
+pub use naming::{
+    expand, numbered, resolve_output, NameValues, OverwritePolicy, DEFAULT_TEMPLATE, TOKENS,
+};
+pub use project::{
+    Project, ProjectOutput, ProjectSource, ProjectTimes, Region, SourceStatus, PROJECT_EXTENSION,
+};
+#[cfg(feature = "ffmpeg-download")]
+pub use provision::{is_supported_platform, provision_ffmpeg, ProvisionConfig, ProvisionEvent};
+pub use recipe::Recipe;
+pub use render::{
+    estimated_size, render, LoopSpec, LoopSpecBuilder, OutputFormat, RenderOutput,
+    DEFAULT_MP3_QUALITY,
+};
+#[cfg(feature = "native-engine")]
+pub use script::{RenderScript, Shell};
+pub use seams::{Rating, Seam, SeamReport};
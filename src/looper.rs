@@ -1,297 +1,729 @@
-use std::io::Write;
-use std::vec;
+use std::path::Path;
 
-use crate::{app, ffmpeg};
+use crate::{
+    backend::{Codec, Fade, MediaBackend, Segment, StepContext},
+    job::{CancelToken, JobError, JobEvent, JobSender},
+    render::LoopSpec,
+    workspace::Workspace,
+};
 
-macro_rules! ffmpeg_command {
-    ($tx:expr, $msg:expr, $ffmpeg_path:expr, $args:expr, $thread_finished:expr) => {
-        $tx.send(Ok(app::ConsoleText::Program($msg.to_string())))
-            .unwrap();
-        if execute_ffmpeg_command($ffmpeg_path, $args, $tx).is_err() {
-            $thread_finished.send(Ok(true)).unwrap();
-            return;
+/// Where the crossfade sits relative to the loop seam
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, Default, Debug)]
+pub enum CrossfadePlacement {
+    /// Fade in the audio leading up to the loop start (needs audio before the start)
+    #[default]
+    PreRoll,
+    /// Fade out the audio following the loop end (needs audio after the end)
+    PostRoll,
+    /// Split the crossfade evenly around the seam
+    Centred,
+}
+
+impl CrossfadePlacement {
+    /// How much of the crossfade lies before the seam
+    pub fn lead_in(self, crossfade: f32) -> f32 {
+        match self {
+            CrossfadePlacement::PreRoll => crossfade,
+            CrossfadePlacement::PostRoll => 0.0,
+            CrossfadePlacement::Centred => crossfade / 2.0,
         }
-    };
+    }
+
+    /// How much of the crossfade lies after the seam
+    pub fn lead_out(self, crossfade: f32) -> f32 {
+        crossfade - self.lead_in(crossfade)
+    }
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn create_loop(
-    start_s: f32,
-    end_s: f32,
-    crossfade_s: f32,
-    loop_count: u8,
-    ffmpeg_path: String,
-    file_path: String,
-    output_path: String,
-    tx: std::sync::mpsc::Sender<Result<app::ConsoleText, String>>,
-    tx_finished: std::sync::mpsc::Sender<Result<bool, String>>,
-    is_test: bool,
-) {
-    let thread_finished = tx_finished.clone();
-    std::thread::spawn(move || {
-        // Generate unique file names to avoid conflicts
-        let intro_file_name = format!("intro_echo_blend_{}.wav", std::process::id());
-        let outro_file_name = format!("outro_echo_blend_{}.wav", std::process::id());
-        let loop_file_name = format!("loop_echo_blend_{}.wav", std::process::id());
-        let crossfade_1_file_name = format!("crossfade_echo_blend_1_{}.wav", std::process::id());
-        let crossfade_2_file_name = format!("crossfade_echo_blend_2_{}.wav", std::process::id());
-        let crossfade_file_name = format!("crossfade_echo_blend_{}.wav", std::process::id());
-        let concat_list_file_name = format!("concat_list_echo_blend_{}.txt", std::process::id());
-
-        let temp_files = vec![
-            intro_file_name.clone(),
-            outro_file_name.clone(),
-            loop_file_name.clone(),
-            crossfade_1_file_name.clone(),
-            crossfade_2_file_name.clone(),
-            crossfade_file_name.clone(),
-            concat_list_file_name.clone(),
-        ];
-
-        if crossfade_s == 0.0 {
-            tx.send(Ok(app::ConsoleText::Program(
-                "Crossfade duration is 0, skipping crossfade...".to_string(),
-            )))
-            .unwrap();
+/// The shape of the two fades a crossfade is mixed from
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, Default, Debug)]
+pub enum CrossfadeCurve {
+    /// Straight fades, which dip in loudness halfway through unless both sides are alike
+    #[default]
+    Linear,
+    /// Quarter sine fades, which keep the loudness even when the two sides differ
+    EqualPower,
+}
+
+/// Checks the loop times, in milliseconds, fit together and within the song if its duration is known
+pub fn validate_times(
+    start: u32,
+    end: u32,
+    crossfade: u32,
+    placement: CrossfadePlacement,
+    duration_s: Option<f32>,
+) -> Result<(), String> {
+    if start >= end {
+        return Err(format!(
+            "The start time must be less than the end time. Start: {}, End: {}",
+            start, end
+        ));
+    }
+    if crossfade >= end - start {
+        return Err(format!("The crossfade duration must be less than the loop duration. Crossfade: {}, Loop Duration: {}", crossfade, end - start));
+    }
+
+    // The crossfade samples are taken from around the loop start and end, so that audio has to exist.
+    // Half of an odd crossfade is a fraction of a millisecond, so this is compared without rounding.
+    let lead_in = placement.lead_in(crossfade as f32);
+    let lead_out = placement.lead_out(crossfade as f32);
+    if lead_in > start as f32 {
+        return Err(format!(
+            "The part of the crossfade before the seam must fit before the start time. Crossfade lead-in: {}, Start: {}",
+            lead_in, start
+        ));
+    }
+    if let Some(duration) = duration_s {
+        let duration = duration * 1000.0;
+        if end as f32 + lead_out > duration {
+            return Err(format!(
+                "The part of the crossfade after the seam must fit between the end time and the end of the song. Crossfade lead-out: {}, End: {}, Song Duration: {}",
+                lead_out, end, duration
+            ));
         }
+    }
+    Ok(())
+}
 
-        if is_test {
-            tx.send(Ok(app::ConsoleText::Program(
-                "Test run, skipping loop segment...".to_string(),
-            )))
-            .unwrap();
+/// Intermediate files a loop is rendered through, all inside the job's workspace
+#[derive(Clone)]
+pub struct TempFiles {
+    pub source: String,
+    pub intro: String,
+    pub crossfade_1: String,
+    pub crossfade_2: String,
+    pub crossfade: String,
+    pub loop_segment: String,
+    pub outro: String,
+    pub merged: String,
+}
+
+impl TempFiles {
+    pub fn new(workspace: &Workspace) -> Self {
+        Self::in_dir(workspace.dir())
+    }
+
+    /// The files inside `dir`, which is not created or removed
+    pub fn in_dir(dir: &Path) -> Self {
+        let file = |name: &str| dir.join(name).display().to_string();
+        Self {
+            source: file("source.wav"),
+            intro: file("intro.wav"),
+            crossfade_1: file("crossfade_1.wav"),
+            crossfade_2: file("crossfade_2.wav"),
+            crossfade: file("crossfade.wav"),
+            loop_segment: file("loop.wav"),
+            outro: file("outro.wav"),
+            merged: file("merged.wav"),
+        }
+    }
+}
+
+/// Renders the loop through `backend`, announcing each step and the progress through them on `tx`.
+///
+/// Intermediate audio goes to `files` and any other scratch files to `scratch_dir`,
+/// which the caller removes afterwards. `metadata` is written into the output file.
+/// No further step starts once `cancel` is cancelled.
+pub fn render_loop(
+    backend: &dyn MediaBackend,
+    spec: &LoopSpec,
+    files: &TempFiles,
+    scratch_dir: &Path,
+    metadata: &[(&str, &str)],
+    tx: &JobSender,
+    cancel: &CancelToken,
+) -> Result<(), JobError> {
+    let start_s = spec.start_s() as f32;
+    let end_s = spec.end_s() as f32;
+    let crossfade_s = spec.crossfade_s() as f32;
+    let lead_in_s = spec.placement().lead_in(crossfade_s);
+    let lead_out_s = spec.placement().lead_out(crossfade_s);
+    let is_test = spec.is_test();
+    let file_path = spec.input().display().to_string();
+    let output_path = spec.output().display().to_string();
+    let output_path = output_path.as_str();
+
+    let is_mp3_input = file_path.to_lowercase().ends_with(".mp3");
+    let is_mp3_output = output_path.to_lowercase().ends_with(".mp3");
+
+    // Intro, outro and merge, plus the optional steps
+    let total = 3
+        + usize::from(is_mp3_input)
+        + usize::from(is_mp3_output)
+        + if crossfade_s > 0.0 { 3 } else { 0 }
+        + usize::from(!is_test);
+    let done = std::cell::Cell::new(0);
+    let step = |name: &'static str| {
+        cancel.check()?;
+        let _ = tx.send(JobEvent::Progress {
+            done: done.get(),
+            total,
+        });
+        let _ = tx.send(JobEvent::StepStarted(name.to_string()));
+        done.set(done.get() + 1);
+        Ok::<_, JobError>(StepContext {
+            name,
+            scratch_dir,
+            tx,
+            cancel,
+        })
+    };
+
+    if crossfade_s == 0.0 {
+        let _ = tx.send(JobEvent::info(
+            "Crossfade duration is 0, skipping crossfade...",
+        ));
+    }
+
+    if is_test {
+        let _ = tx.send(JobEvent::info("Test run, skipping loop segment..."));
+    }
+
+    // MP3 frames carry encoder delay and padding which the cuts below would not account for.
+    // Decoding the whole file once from the start lets ffmpeg apply the LAME/Xing gapless info,
+    // and every segment is then cut from sample accurate PCM.
+    let file_path = if is_mp3_input {
+        backend.transcode(
+            &step("Decoding MP3 source...")?,
+            &file_path,
+            Codec::PcmFloat,
+            &files.source,
+            &[],
+        )?;
+        files.source.as_str()
+    } else {
+        file_path.as_str()
+    };
+
+    backend.render_segment(
+        &step("Rendering intro...")?,
+        file_path,
+        &Segment {
+            start_s: None,
+            duration_s: Some(end_s - lead_in_s),
+            fade: None,
+        },
+        &files.intro,
+    )?;
+
+    if crossfade_s > 0.0 {
+        backend.render_segment(
+            &step("Rendering crossfade sample 1...")?,
+            file_path,
+            &Segment {
+                start_s: Some(end_s - lead_in_s),
+                duration_s: Some(crossfade_s),
+                fade: Some(Fade::Out {
+                    start_s: end_s - lead_in_s,
+                    duration_s: crossfade_s,
+                    curve: spec.curve(),
+                }),
+            },
+            &files.crossfade_1,
+        )?;
+        backend.render_segment(
+            &step("Rendering crossfade sample 2...")?,
+            file_path,
+            &Segment {
+                start_s: Some(start_s - lead_in_s),
+                duration_s: Some(crossfade_s),
+                fade: Some(Fade::In {
+                    start_s: start_s - lead_in_s,
+                    duration_s: crossfade_s,
+                    curve: spec.curve(),
+                }),
+            },
+            &files.crossfade_2,
+        )?;
+        backend.mix(
+            &step("Rendering crossfade...")?,
+            &[&files.crossfade_1, &files.crossfade_2],
+            &files.crossfade,
+        )?;
+    }
+
+    if !is_test {
+        backend.render_segment(
+            &step("Rendering loop segment...")?,
+            file_path,
+            &Segment {
+                start_s: Some(start_s + lead_out_s),
+                duration_s: Some(end_s - start_s - crossfade_s),
+                fade: None,
+            },
+            &files.loop_segment,
+        )?;
+    }
+
+    backend.render_segment(
+        &step("Rendering outro...")?,
+        file_path,
+        &Segment {
+            start_s: Some(start_s + lead_out_s),
+            duration_s: None,
+            fade: None,
+        },
+        &files.outro,
+    )?;
+
+    let segments: Vec<&str> = plan(spec, 0.0)
+        .iter()
+        .map(|section| match section.kind {
+            SectionKind::Intro => files.intro.as_str(),
+            SectionKind::Crossfade => &files.crossfade,
+            SectionKind::Loop => &files.loop_segment,
+            SectionKind::Outro => &files.outro,
+        })
+        .collect();
+
+    backend.concat(
+        &step("Merging segments...")?,
+        &segments,
+        if is_mp3_output {
+            &files.merged
+        } else {
+            output_path
+        },
+        if is_mp3_output { &[] } else { metadata },
+    )?;
+
+    // Encode the MP3 once from the merged PCM so there is a single encoder delay and padding,
+    // which the Xing/LAME header records for gapless playback
+    if is_mp3_output {
+        backend.transcode(
+            &step("Encoding MP3...")?,
+            &files.merged,
+            Codec::Mp3 {
+                quality: spec.mp3_quality(),
+            },
+            output_path,
+            metadata,
+        )?;
+    }
+
+    let _ = tx.send(JobEvent::Progress { done: total, total });
+    Ok(())
+}
+
+/// What a part of the rendered loop is made of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SectionKind {
+    /// The song up to the first seam
+    Intro,
+    Crossfade,
+    /// One repeat of the section between the start and end
+    Loop,
+    /// The rest of the song after the last seam
+    Outro,
+}
+
+/// A part of the rendered loop, with times in the output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub start_s: f32,
+    pub duration_s: f32,
+}
+
+/// The parts [`render_loop`] joins into the output, in order, for a song lasting `source_duration_s`
+pub fn plan(spec: &LoopSpec, source_duration_s: f32) -> Vec<Section> {
+    let start_s = spec.start_s() as f32;
+    let end_s = spec.end_s() as f32;
+    let crossfade_s = spec.crossfade_s() as f32;
+    let lead_out_s = spec.placement().lead_out(crossfade_s);
+
+    let mut kinds = Vec::new();
+    if !spec.is_test() {
+        for _ in 0..spec.loop_count() {
+            if crossfade_s > 0.0 {
+                kinds.push(SectionKind::Crossfade);
+            }
+            kinds.push(SectionKind::Loop);
         }
+    }
+    if crossfade_s > 0.0 {
+        kinds.push(SectionKind::Crossfade);
+    }
+    kinds.push(SectionKind::Outro);
+
+    let mut sections = vec![Section {
+        kind: SectionKind::Intro,
+        start_s: 0.0,
+        duration_s: end_s - spec.placement().lead_in(crossfade_s),
+    }];
+    for kind in kinds {
+        let duration_s = match kind {
+            SectionKind::Intro => 0.0,
+            SectionKind::Crossfade => crossfade_s,
+            SectionKind::Loop => end_s - start_s - crossfade_s,
+            SectionKind::Outro => (source_duration_s - start_s - lead_out_s).max(0.0),
+        };
+        let last = sections[sections.len() - 1];
+        sections.push(Section {
+            kind,
+            start_s: last.start_s + last.duration_s,
+            duration_s,
+        });
+    }
+    sections
+}
 
-        ffmpeg_command!(
+/// How long the loop rendered for a song lasting `source_duration_s` is
+pub fn loop_duration(spec: &LoopSpec, source_duration_s: f32) -> f32 {
+    plan(spec, source_duration_s)
+        .last()
+        .map_or(0.0, |last| last.start_s + last.duration_s)
+}
+
+/// Times in the rendered output where two segments are joined
+pub fn seam_times(spec: &LoopSpec) -> Vec<f32> {
+    // The outro's length does not move any seam
+    plan(spec, 0.0)
+        .iter()
+        .skip(1)
+        .map(|section| section.start_s)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Call, MediaInfo, RecordingBackend};
+
+    const METADATA: &[(&str, &str)] = &[("comment", "recipe")];
+
+    fn spec(input: &str, output: &str, placement: CrossfadePlacement) -> LoopSpec {
+        LoopSpec::builder(input, output)
+            .start(10.0)
+            .end(20.0)
+            .crossfade(1.0)
+            .placement(placement)
+            .loop_count(2)
+            .build()
+            .unwrap()
+    }
+
+    fn record(spec: &LoopSpec) -> (Vec<Call>, TempFiles) {
+        let backend = RecordingBackend::new(MediaInfo::default());
+        let dir = Path::new("work");
+        let files = TempFiles::in_dir(dir);
+        let (tx, _rx) = std::sync::mpsc::channel();
+        render_loop(
+            &backend,
+            spec,
+            &files,
+            dir,
+            METADATA,
             &tx,
-            "Rendering intro...",
-            &ffmpeg_path,
-            &[
-                "-y",
-                "-i",
-                &file_path,
-                "-t",
-                &(end_s - crossfade_s).to_string(),
-                &intro_file_name,
-            ],
-            thread_finished
-        );
+            &CancelToken::default(),
+        )
+        .unwrap();
+        (backend.calls(), files)
+    }
 
-        if crossfade_s > 0.0 {
-            ffmpeg_command!(
-                &tx,
-                "Rendering crossfade sample 1...",
-                &ffmpeg_path,
-                &[
-                    "-y",
-                    "-i",
-                    &file_path,
-                    "-ss",
-                    &format!("{}", end_s - crossfade_s),
-                    "-t",
-                    &crossfade_s.to_string(),
-                    "-af",
-                    &format!("afade=t=out:st={}:d={}", end_s - crossfade_s, crossfade_s),
-                    &crossfade_1_file_name,
-                ],
-                thread_finished
-            );
-            ffmpeg_command!(
-                &tx,
-                "Rendering crossfade sample 2...",
-                &ffmpeg_path,
-                &[
-                    "-y",
-                    "-i",
-                    &file_path,
-                    "-ss",
-                    &format!("{}", start_s - crossfade_s),
-                    "-t",
-                    &crossfade_s.to_string(),
-                    "-af",
-                    &format!("afade=t=in:st={}:d={}", start_s - crossfade_s, crossfade_s),
-                    &crossfade_2_file_name,
-                ],
-                thread_finished
-            );
-            ffmpeg_command!(
-                &tx,
-                "Rendering crossfade...",
-                &ffmpeg_path,
-                &[
-                    "-y",
-                    "-i",
-                    &crossfade_1_file_name,
-                    "-i",
-                    &crossfade_2_file_name,
-                    "-filter_complex",
-                    "amix=inputs=2:duration=first:dropout_transition=0:normalize=0",
-                    &crossfade_file_name,
-                ],
-                thread_finished
-            );
+    fn segment(input: &str, start_s: Option<f32>, duration_s: Option<f32>, output: &str) -> Call {
+        Call::RenderSegment {
+            input: input.to_string(),
+            segment: Segment {
+                start_s,
+                duration_s,
+                fade: None,
+            },
+            output: output.to_string(),
         }
+    }
 
-        if !is_test {
-            ffmpeg_command!(
-                &tx,
-                "Rendering loop segment...",
-                &ffmpeg_path,
-                &[
-                    "-y",
-                    "-i",
-                    &file_path,
-                    "-ss",
-                    &start_s.to_string(),
-                    "-t",
-                    (end_s - start_s - crossfade_s).to_string().as_str(),
-                    &loop_file_name,
-                ],
-                thread_finished
-            );
+    fn fade(input: &str, fade: Fade, output: &str) -> Call {
+        let (start_s, duration_s) = match fade {
+            Fade::In {
+                start_s,
+                duration_s,
+                ..
+            }
+            | Fade::Out {
+                start_s,
+                duration_s,
+                ..
+            } => (start_s, duration_s),
+        };
+        Call::RenderSegment {
+            input: input.to_string(),
+            segment: Segment {
+                start_s: Some(start_s),
+                duration_s: Some(duration_s),
+                fade: Some(fade),
+            },
+            output: output.to_string(),
         }
+    }
 
-        ffmpeg_command!(
-            &tx,
-            "Rendering outro...",
-            &ffmpeg_path,
-            &[
-                "-y",
-                "-i",
-                &file_path,
-                "-ss",
-                &start_s.to_string(),
-                &outro_file_name,
-            ],
-            thread_finished
+    fn strings(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    // Every call a WAV loop from 10 s to 20 s with a 1 s crossfade makes, given where the crossfade sits
+    fn expected_wav_calls(files: &TempFiles, lead_in_s: f32) -> Vec<Call> {
+        let lead_out_s = 1.0 - lead_in_s;
+        let curve = CrossfadeCurve::Linear;
+        vec![
+            segment("song.wav", None, Some(20.0 - lead_in_s), &files.intro),
+            fade(
+                "song.wav",
+                Fade::Out {
+                    start_s: 20.0 - lead_in_s,
+                    duration_s: 1.0,
+                    curve,
+                },
+                &files.crossfade_1,
+            ),
+            fade(
+                "song.wav",
+                Fade::In {
+                    start_s: 10.0 - lead_in_s,
+                    duration_s: 1.0,
+                    curve,
+                },
+                &files.crossfade_2,
+            ),
+            Call::Mix {
+                inputs: strings(&[&files.crossfade_1, &files.crossfade_2]),
+                output: files.crossfade.clone(),
+            },
+            segment(
+                "song.wav",
+                Some(10.0 + lead_out_s),
+                Some(9.0),
+                &files.loop_segment,
+            ),
+            segment("song.wav", Some(10.0 + lead_out_s), None, &files.outro),
+            Call::Concat {
+                inputs: strings(&[
+                    &files.intro,
+                    &files.crossfade,
+                    &files.loop_segment,
+                    &files.crossfade,
+                    &files.loop_segment,
+                    &files.crossfade,
+                    &files.outro,
+                ]),
+                output: "out.wav".to_string(),
+                metadata: vec![("comment".to_string(), "recipe".to_string())],
+            },
+        ]
+    }
+
+    #[test]
+    fn pre_roll_takes_the_crossfade_from_before_each_seam() {
+        let (calls, files) = record(&spec("song.wav", "out.wav", CrossfadePlacement::PreRoll));
+        assert_eq!(calls, expected_wav_calls(&files, 1.0));
+    }
+
+    #[test]
+    fn post_roll_takes_the_crossfade_from_after_each_seam() {
+        let (calls, files) = record(&spec("song.wav", "out.wav", CrossfadePlacement::PostRoll));
+        assert_eq!(calls, expected_wav_calls(&files, 0.0));
+    }
+
+    #[test]
+    fn centred_splits_the_crossfade_around_each_seam() {
+        let (calls, files) = record(&spec("song.wav", "out.wav", CrossfadePlacement::Centred));
+        assert_eq!(calls, expected_wav_calls(&files, 0.5));
+    }
+
+    #[test]
+    fn test_mode_renders_a_single_seam() {
+        let spec = LoopSpec::builder("song.wav", "out.wav")
+            .start(10.0)
+            .end(20.0)
+            .crossfade(1.0)
+            .loop_count(2)
+            .test(true)
+            .build()
+            .unwrap();
+        let (calls, files) = record(&spec);
+        assert!(!calls.iter().any(|call| matches!(
+            call,
+            Call::RenderSegment { output, .. } if *output == files.loop_segment
+        )));
+        assert_eq!(
+            calls.last(),
+            Some(&Call::Concat {
+                inputs: strings(&[&files.intro, &files.crossfade, &files.outro]),
+                output: "out.wav".to_string(),
+                metadata: vec![("comment".to_string(), "recipe".to_string())],
+            })
         );
+    }
+
+    #[test]
+    fn no_crossfade_skips_the_crossfade_steps() {
+        let spec = LoopSpec::builder("song.wav", "out.wav")
+            .start(10.0)
+            .end(20.0)
+            .loop_count(2)
+            .build()
+            .unwrap();
+        let (calls, files) = record(&spec);
+        assert_eq!(
+            calls,
+            vec![
+                segment("song.wav", None, Some(20.0), &files.intro),
+                segment("song.wav", Some(10.0), Some(10.0), &files.loop_segment),
+                segment("song.wav", Some(10.0), None, &files.outro),
+                Call::Concat {
+                    inputs: strings(&[
+                        &files.intro,
+                        &files.loop_segment,
+                        &files.loop_segment,
+                        &files.outro
+                    ]),
+                    output: "out.wav".to_string(),
+                    metadata: vec![("comment".to_string(), "recipe".to_string())],
+                },
+            ]
+        );
+    }
 
-        let cmd = final_cmd_builder(
-            &intro_file_name,
-            &loop_file_name,
-            loop_count,
-            crossfade_s,
-            &crossfade_file_name,
-            &outro_file_name,
-            &concat_list_file_name,
-            &output_path,
-            is_test,
+    #[test]
+    fn mp3_is_decoded_once_and_encoded_once() {
+        let (calls, files) = record(&spec("song.mp3", "out.mp3", CrossfadePlacement::PreRoll));
+        assert_eq!(
+            calls.first(),
+            Some(&Call::Transcode {
+                input: "song.mp3".to_string(),
+                codec: Codec::PcmFloat,
+                output: files.source.clone(),
+                metadata: Vec::new(),
+            })
         );
-        ffmpeg_command!(
+        // Every segment is cut from the decoded PCM
+        for call in &calls {
+            if let Call::RenderSegment { input, .. } = call {
+                assert_eq!(*input, files.source);
+            }
+        }
+        let n = calls.len();
+        assert!(matches!(
+            &calls[n - 2],
+            Call::Concat { output, metadata, .. } if *output == files.merged && metadata.is_empty()
+        ));
+        assert_eq!(
+            calls[n - 1],
+            Call::Transcode {
+                input: files.merged.clone(),
+                codec: Codec::Mp3 { quality: 2 },
+                output: "out.mp3".to_string(),
+                metadata: vec![("comment".to_string(), "recipe".to_string())],
+            }
+        );
+    }
+
+    #[test]
+    fn a_cancelled_job_starts_no_more_steps() {
+        let spec = spec("song.wav", "out.wav", CrossfadePlacement::PreRoll);
+        let backend = RecordingBackend::new(MediaInfo::default());
+        let dir = Path::new("work");
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let cancel = CancelToken::default();
+        cancel.cancel();
+        let result = render_loop(
+            &backend,
+            &spec,
+            &TempFiles::in_dir(dir),
+            dir,
+            METADATA,
             &tx,
-            "Merging segments...",
-            &ffmpeg_path,
-            &cmd.iter().map(String::as_str).collect::<Vec<&str>>(),
-            &thread_finished
+            &cancel,
         );
+        assert!(matches!(result, Err(JobError::Cancelled)));
+        assert!(backend.calls().is_empty());
+    }
 
-        tx.send(Ok(app::ConsoleText::Program(
-            "Deleting files...".to_string(),
-        )))
-        .unwrap();
-        for f in temp_files {
-            std::fs::remove_file(f).unwrap_or_default();
-        }
+    #[test]
+    fn plan_matches_the_rendered_sections() {
+        let spec = spec("song.wav", "out.wav", CrossfadePlacement::PreRoll);
+        let sections = plan(&spec, 60.0);
+        let kinds: Vec<SectionKind> = sections.iter().map(|section| section.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                SectionKind::Intro,
+                SectionKind::Crossfade,
+                SectionKind::Loop,
+                SectionKind::Crossfade,
+                SectionKind::Loop,
+                SectionKind::Crossfade,
+                SectionKind::Outro,
+            ]
+        );
+        let durations: Vec<f32> = sections.iter().map(|section| section.duration_s).collect();
+        assert_eq!(durations, [19.0, 1.0, 9.0, 1.0, 9.0, 1.0, 50.0]);
+        assert_eq!(seam_times(&spec), [19.0, 20.0, 29.0, 30.0, 39.0, 40.0]);
+        assert_eq!(loop_duration(&spec, 60.0), 90.0);
+    }
 
-        tx.send(Ok(app::ConsoleText::Success("Done!".to_string())))
-            .unwrap();
-        tx_finished.send(Ok(true)).unwrap();
-    });
-}
+    fn durations(spec: &LoopSpec) -> Vec<f32> {
+        plan(spec, 60.0)
+            .iter()
+            .map(|section| section.duration_s)
+            .collect()
+    }
 
-fn execute_ffmpeg_command(
-    ffmpeg_path: &str,
-    args: &[&str],
-    tx: &std::sync::mpsc::Sender<Result<app::ConsoleText, String>>,
-) -> Result<(), ()> {
-    match ffmpeg::run_ffmpeg(ffmpeg_path, args, tx) {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            tx.send(Err(e)).unwrap();
-            Err(())
-        }
+    #[test]
+    fn plan_moves_the_seams_with_the_placement() {
+        let post_roll = spec("song.wav", "out.wav", CrossfadePlacement::PostRoll);
+        assert_eq!(durations(&post_roll), [20.0, 1.0, 9.0, 1.0, 9.0, 1.0, 49.0]);
+        let centred = spec("song.wav", "out.wav", CrossfadePlacement::Centred);
+        assert_eq!(durations(&centred), [19.5, 1.0, 9.0, 1.0, 9.0, 1.0, 49.5]);
+        // Wherever the crossfade sits, the loop is as long
+        assert_eq!(loop_duration(&post_roll, 60.0), 90.0);
+        assert_eq!(loop_duration(&centred, 60.0), 90.0);
     }
-}
 
-#[allow(clippy::too_many_arguments)]
-fn final_cmd_builder(
-    intro_file_name: &str,
-    loop_file_name: &str,
-    loop_count: u8,
-    crossfade_s: f32,
-    crossfade_file_name: &str,
-    outro_file_name: &str,
-    concat_list_file_name: &str,
-    output_path: &str,
-    is_test: bool,
-) -> Vec<String> {
-    let mut cmd: Vec<String> = vec!["-y".to_owned()];
-    if is_test {
-        if crossfade_s > 0.0 {
-            cmd.extend([
-                "-i".to_owned(),
-                intro_file_name.to_owned(),
-                "-i".to_owned(),
-                crossfade_file_name.to_owned(),
-                "-i".to_owned(),
-                outro_file_name.to_owned(),
-                "-filter_complex".to_owned(),
-                "concat=n=3:v=0:a=1".to_owned(),
-                output_path.to_owned(),
-            ]);
-        } else {
-            cmd.extend([
-                "-i".to_owned(),
-                intro_file_name.to_owned(),
-                "-i".to_owned(),
-                outro_file_name.to_owned(),
-                "-filter_complex".to_owned(),
-                "concat=n=2:v=0:a=1".to_owned(),
-                output_path.to_owned(),
-            ]);
-        }
-    } else {
-        // Create an ffmpeg concat list txt file
-        let mut concat_list = std::fs::File::create(concat_list_file_name).unwrap();
-        concat_list
-            .write_all(format!("file '{}'\n", &intro_file_name).as_bytes())
+    #[test]
+    fn plan_without_a_crossfade_or_repeats() {
+        let no_crossfade = LoopSpec::builder("song.wav", "out.wav")
+            .start(10.0)
+            .end(20.0)
+            .loop_count(2)
+            .build()
             .unwrap();
-        for _ in 0..loop_count {
-            if crossfade_s > 0.0 {
-                concat_list
-                    .write_all(format!("file '{}'\n", &crossfade_file_name).as_bytes())
-                    .unwrap();
-            }
-            concat_list
-                .write_all(format!("file '{}'\n", &loop_file_name).as_bytes())
-                .unwrap();
-        }
-        if crossfade_s > 0.0 {
-            concat_list
-                .write_all(format!("file '{}'\n", &crossfade_file_name).as_bytes())
-                .unwrap();
-        }
-        concat_list
-            .write_all(format!("file '{}'\n", &outro_file_name).as_bytes())
+        assert_eq!(durations(&no_crossfade), [20.0, 10.0, 10.0, 50.0]);
+        let test = LoopSpec::builder("song.wav", "out.wav")
+            .start(10.0)
+            .end(20.0)
+            .crossfade(1.0)
+            .test(true)
+            .build()
             .unwrap();
+        assert_eq!(durations(&test), [19.0, 1.0, 50.0]);
+        // The outro is cut short rather than negative if the song is shorter than it was said to be
+        assert_eq!(
+            plan(&test, 5.0).last().map(|outro| outro.duration_s),
+            Some(0.0)
+        );
+    }
 
-        cmd.extend([
-            "-f".to_owned(),
-            "concat".to_owned(),
-            "-safe".to_owned(),
-            "0".to_owned(),
-            "-i".to_owned(),
-            concat_list_file_name.to_owned(),
-        ]);
-        if output_path.ends_with(".mp3") {
-            cmd.push("-q:a".to_owned());
-            cmd.push("2".to_owned());
-        } else {
-            cmd.push("-c".to_owned());
-            cmd.push("copy".to_owned());
-        }
-        cmd.push(output_path.to_owned());
+    #[test]
+    fn centred_odd_crossfade_must_fit_before_the_start() {
+        // Half of 1001 ms is 500.5 ms, which would seek to before the start of the song
+        assert!(validate_times(500, 5000, 1001, CrossfadePlacement::Centred, None).is_err());
+        assert!(validate_times(501, 5000, 1001, CrossfadePlacement::Centred, None).is_ok());
+        assert!(validate_times(500, 5000, 1000, CrossfadePlacement::Centred, None).is_ok());
     }
 
-    cmd
+    #[test]
+    fn centred_odd_crossfade_must_fit_before_the_end_of_the_song() {
+        let placement = CrossfadePlacement::Centred;
+        assert!(validate_times(1000, 9500, 1001, placement, Some(10.0)).is_err());
+        assert!(validate_times(1000, 9499, 1001, placement, Some(10.0)).is_ok());
+    }
+
+    #[test]
+    fn pre_and_post_roll_need_audio_on_their_side() {
+        assert!(validate_times(999, 5000, 1000, CrossfadePlacement::PreRoll, None).is_err());
+        assert!(validate_times(1000, 5000, 1000, CrossfadePlacement::PreRoll, None).is_ok());
+        assert!(validate_times(0, 5000, 1000, CrossfadePlacement::PostRoll, Some(5.5)).is_err());
+        assert!(validate_times(0, 5000, 1000, CrossfadePlacement::PostRoll, Some(6.0)).is_ok());
+    }
 }
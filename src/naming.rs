@@ -0,0 +1,242 @@
+use std::path::{Path, PathBuf};
+
+/// The output name used until another template is set
+pub const DEFAULT_TEMPLATE: &str = "{stem}_loop";
+
+/// Tokens an output name template can hold
+pub const TOKENS: &[(&str, &str)] = &[
+    (
+        "{title}",
+        "The song's title tag, or its file name if it has none",
+    ),
+    ("{stem}", "The song's file name without its extension"),
+    ("{loops}", "How many times the section repeats"),
+    ("{duration}", "How long the rendered loop is"),
+    ("{start}", "Where the loop starts"),
+    ("{end}", "Where the loop ends"),
+    ("{date}", "Today's date, as 2024-01-31"),
+];
+
+/// What to do when the output file already exists
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverwritePolicy {
+    /// Ask before replacing it
+    #[default]
+    Ask,
+    /// Leave it alone and render nothing
+    Skip,
+    /// Write to the first free name with a number added, e.g. `song_loop (2).wav`
+    AutoNumber,
+    Overwrite,
+}
+
+/// What an output name template is filled in with
+pub struct NameValues<'a> {
+    pub input: &'a Path,
+    /// The song's title tag, if it has one
+    pub title: Option<&'a str>,
+    pub loop_count: u8,
+    /// Length of the rendered loop, in seconds
+    pub duration_s: f64,
+    pub start_s: f64,
+    pub end_s: f64,
+}
+
+/// Fills in the tokens of `template`, giving a file name without an extension.
+///
+/// Values are cleaned of characters file names cannot hold, and unknown tokens are an error.
+pub fn expand(template: &str, values: &NameValues<'_>) -> Result<String, String> {
+    let stem = values
+        .input
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        name.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .ok_or(format!("The template \"{}\" has an unclosed {{", template))?
+            + open;
+        let value = match &rest[open..=close] {
+            "{title}" => values
+                .title
+                .filter(|title| !title.trim().is_empty())
+                .map_or_else(|| stem.clone(), |title| title.trim().to_string()),
+            "{stem}" => stem.clone(),
+            "{loops}" => values.loop_count.to_string(),
+            "{duration}" => name_time(values.duration_s),
+            "{start}" => name_time(values.start_s),
+            "{end}" => name_time(values.end_s),
+            "{date}" => today(),
+            token => {
+                return Err(format!(
+                    "Unknown token {} in the template \"{}\"",
+                    token, template
+                ))
+            }
+        };
+        name.push_str(&sanitise(&value));
+        rest = &rest[close + 1..];
+    }
+    name.push_str(rest);
+
+    let name = sanitise(&name).trim_end_matches(['.', ' ']).to_string();
+    if name.is_empty() {
+        return Err(format!("The template \"{}\" gives an empty name", template));
+    }
+    Ok(name)
+}
+
+/// Where to write `output` under `policy`, or `None` if the render should be skipped.
+///
+/// [`OverwritePolicy::Ask`] is the caller's to ask about and is treated as overwriting here.
+pub fn resolve_output(output: &Path, policy: OverwritePolicy) -> Option<PathBuf> {
+    if !output.exists() {
+        return Some(output.to_path_buf());
+    }
+    match policy {
+        OverwritePolicy::Ask | OverwritePolicy::Overwrite => Some(output.to_path_buf()),
+        OverwritePolicy::Skip => None,
+        OverwritePolicy::AutoNumber => Some(numbered(output)),
+    }
+}
+
+/// The first name not taken of `song_loop (2).wav`, `song_loop (3).wav` and so on
+pub fn numbered(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = output
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|n| output.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap_or_else(|| output.to_path_buf())
+}
+
+// Times in names avoid the colons file names cannot hold, e.g. 1m02.500s
+fn name_time(seconds: f64) -> String {
+    let ms = (seconds * 1000.0).round().max(0.0) as u64;
+    let (minutes, secs, millis) = (ms / 60_000, ms / 1000 % 60, ms % 1000);
+    if minutes > 0 {
+        format!("{}m{:02}.{:03}s", minutes, secs, millis)
+    } else {
+        format!("{}.{:03}s", secs, millis)
+    }
+}
+
+// Today's date in UTC as YYYY-MM-DD
+fn today() -> String {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86_400) as i64;
+    // Converts days since 1970-01-01 to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Replaces characters that are not allowed in file names on some systems
+fn sanitise(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(input: &Path) -> NameValues<'_> {
+        NameValues {
+            input,
+            title: None,
+            loop_count: 3,
+            duration_s: 62.5,
+            start_s: 1.0,
+            end_s: 61.25,
+        }
+    }
+
+    #[test]
+    fn fills_in_the_tokens() {
+        let input = Path::new("music/song.wav");
+        assert_eq!(
+            expand("{stem}_{loops}x_{start}-{end}_{duration}", &values(input)).unwrap(),
+            "song_3x_1.000s-1m01.250s_1m02.500s"
+        );
+        let titled = NameValues {
+            title: Some(" Theme "),
+            ..values(input)
+        };
+        assert_eq!(expand("{title} loop", &titled).unwrap(), "Theme loop");
+        assert_eq!(expand("{title}", &values(input)).unwrap(), "song");
+    }
+
+    #[test]
+    fn rejects_unknown_tokens() {
+        let input = Path::new("song.wav");
+        let error = expand("{stem}_{bpm}", &values(input)).unwrap_err();
+        assert!(error.contains("{bpm}"), "{}", error);
+        assert!(expand("{stem", &values(input)).is_err());
+        assert!(expand("{title}", &values(Path::new(""))).is_err());
+    }
+
+    #[test]
+    fn replaces_characters_file_names_cannot_hold() {
+        let input = Path::new("song.wav");
+        let titled = NameValues {
+            title: Some("AC/DC: Who? \"Live\" <1|2>*"),
+            ..values(input)
+        };
+        assert_eq!(
+            expand("{title}", &titled).unwrap(),
+            "AC_DC_ Who_ _Live_ _1_2__"
+        );
+        // Trailing dots and spaces are dropped, as Windows would
+        assert_eq!(expand("{stem}. ", &values(input)).unwrap(), "song");
+        assert_eq!(expand("a/b\tc", &values(input)).unwrap(), "a_b_c");
+    }
+
+    #[test]
+    fn numbers_past_every_name_taken() {
+        let dir = std::env::temp_dir().join(format!("echo_blend_naming_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("song_loop.wav");
+        assert_eq!(
+            resolve_output(&output, OverwritePolicy::AutoNumber),
+            Some(output.clone())
+        );
+
+        std::fs::write(&output, b"").unwrap();
+        assert_eq!(numbered(&output), dir.join("song_loop (2).wav"));
+        std::fs::write(dir.join("song_loop (2).wav"), b"").unwrap();
+        std::fs::write(dir.join("song_loop (3).wav"), b"").unwrap();
+        assert_eq!(
+            resolve_output(&output, OverwritePolicy::AutoNumber),
+            Some(dir.join("song_loop (4).wav"))
+        );
+        assert_eq!(resolve_output(&output, OverwritePolicy::Skip), None);
+        assert_eq!(
+            resolve_output(&output, OverwritePolicy::Overwrite),
+            Some(output.clone())
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
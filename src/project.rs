@@ -0,0 +1,256 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    hash::sha256_file,
+    looper::{CrossfadeCurve, CrossfadePlacement},
+    render::{LoopSpec, LoopSpecBuilder, OutputFormat},
+};
+
+/// Extension of EchoBlend project files, without the dot
+pub const PROJECT_EXTENSION: &str = "echoblend";
+
+// Bumped whenever older versions would misread a project
+const FORMAT_VERSION: u32 = 1;
+
+/// The song a project loops
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct ProjectSource {
+    pub path: PathBuf,
+    /// SHA-256 of the song when the project was saved, to notice if it has changed since
+    pub sha256: String,
+}
+
+/// A named section of the song worth coming back to
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Region {
+    pub name: String,
+    pub start_s: f64,
+    pub end_s: f64,
+}
+
+/// Loop and crossfade settings, in seconds
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Default)]
+pub struct ProjectTimes {
+    pub start_s: f64,
+    pub end_s: f64,
+    pub crossfade_s: f64,
+    pub placement: CrossfadePlacement,
+    #[serde(default)]
+    pub curve: CrossfadeCurve,
+    pub loop_count: u8,
+}
+
+/// How the loop is written, each part falling back to the settings if it is missing
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Default)]
+pub struct ProjectOutput {
+    /// Where the loop was last written
+    pub path: Option<PathBuf>,
+    /// The format of the loop, which the extension of `path` takes precedence over
+    #[serde(default)]
+    pub format: Option<OutputFormat>,
+    /// VBR quality of the loop if it is an MP3, from 0, the best, to 9, the smallest
+    #[serde(default)]
+    pub mp3_quality: Option<u8>,
+}
+
+/// Whether a project's song is still the one it was saved with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceStatus {
+    Unchanged,
+    Changed,
+    Missing,
+}
+
+/// A saved loop session, stored as JSON in a `.echoblend` file.
+///
+/// Paths inside the project's own folder are stored relative to it, so the folder can be moved as a whole.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Project {
+    version: u32,
+    pub source: ProjectSource,
+    pub times: ProjectTimes,
+    #[serde(default)]
+    pub regions: Vec<Region>,
+    #[serde(default)]
+    pub output: ProjectOutput,
+    #[serde(default)]
+    pub notes: String,
+}
+
+impl Project {
+    /// Starts a project for the song at `source`, hashing it.
+    pub fn new(source: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = source.into();
+        let sha256 =
+            sha256_file(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Ok(Self {
+            version: FORMAT_VERSION,
+            source: ProjectSource { path, sha256 },
+            times: ProjectTimes::default(),
+            regions: Vec::new(),
+            output: ProjectOutput::default(),
+            notes: String::new(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read project {}: {}", path.display(), e))?;
+        let mut project: Project = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid project {}: {}", path.display(), e))?;
+        if project.version > FORMAT_VERSION {
+            return Err(format!(
+                "The project {} was saved by a newer version of EchoBlend, update to open it",
+                path.display()
+            ));
+        }
+        if project
+            .output
+            .mp3_quality
+            .is_some_and(|quality| quality > 9)
+        {
+            return Err(format!(
+                "Invalid project {}: the MP3 quality is out of range",
+                path.display()
+            ));
+        }
+
+        let dir = project_dir(path);
+        project.source.path = dir.join(&project.source.path);
+        project.output.path = project.output.path.map(|output| dir.join(output));
+        // A song that was moved along with the project is found next to it
+        if !project.source.path.exists() {
+            if let Some(moved) = project.source.path.file_name().map(|name| dir.join(name)) {
+                if moved.exists() {
+                    project.source.path = moved;
+                }
+            }
+        }
+        Ok(project)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let dir = project_dir(path);
+        let mut project = self.clone();
+        project.version = FORMAT_VERSION;
+        project.source.path = relative_to(&dir, &project.source.path);
+        project.output.path = project.output.path.map(|output| relative_to(&dir, &output));
+        let json = serde_json::to_string_pretty(&project).map_err(|e| e.to_string())?;
+        std::fs::write(path, json)
+            .map_err(|e| format!("Failed to save project {}: {}", path.display(), e))
+    }
+
+    /// Checks the song against the hash it was saved with
+    pub fn source_status(&self) -> SourceStatus {
+        match sha256_file(&self.source.path) {
+            Ok(sha256) if sha256 == self.source.sha256 => SourceStatus::Unchanged,
+            Ok(_) => SourceStatus::Changed,
+            Err(_) => SourceStatus::Missing,
+        }
+    }
+
+    /// A loop of the project's song with its settings, written to `output`
+    pub fn spec_builder(&self, output: impl Into<PathBuf>) -> LoopSpecBuilder {
+        let builder = LoopSpec::builder(self.source.path.clone(), output)
+            .start(self.times.start_s)
+            .end(self.times.end_s)
+            .crossfade(self.times.crossfade_s)
+            .placement(self.times.placement)
+            .curve(self.times.curve)
+            .loop_count(self.times.loop_count);
+        match self.output.mp3_quality {
+            Some(quality) => builder.mp3_quality(quality),
+            None => builder,
+        }
+    }
+}
+
+// Relative paths in a project are relative to the folder it is in
+fn project_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn relative_to(dir: &Path, path: &Path) -> PathBuf {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    path.strip_prefix(&dir)
+        .map_or_else(|_| path.clone(), Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "echo_blend_project_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn keeps_the_output_settings() {
+        let dir = temp_dir("output");
+        let song = dir.join("song.wav");
+        std::fs::write(&song, b"RIFF").unwrap();
+        let mut project = Project::new(&song).unwrap();
+        project.times.end_s = 1.0;
+        project.output = ProjectOutput {
+            path: Some(dir.join("song_loop.mp3")),
+            format: Some(OutputFormat::Mp3),
+            mp3_quality: Some(5),
+        };
+        let path = dir.join("song.echoblend");
+        project.save(&path).unwrap();
+
+        let loaded = Project::load(&path).unwrap();
+        assert_eq!(loaded.output.format, Some(OutputFormat::Mp3));
+        assert_eq!(loaded.output.mp3_quality, Some(5));
+        let spec = loaded.spec_builder(dir.join("out.mp3")).build().unwrap();
+        assert_eq!(spec.mp3_quality(), 5);
+    }
+
+    #[test]
+    fn older_projects_leave_the_output_settings_to_the_app() {
+        let dir = temp_dir("older");
+        let path = dir.join("song.echoblend");
+        std::fs::write(
+            &path,
+            r#"{
+                "version": 1,
+                "source": { "path": "song.wav", "sha256": "" },
+                "times": { "start_s": 1.0, "end_s": 2.0, "crossfade_s": 0.0, "placement": "PreRoll", "loop_count": 1 },
+                "output": { "path": "song_loop.wav" }
+            }"#,
+        )
+        .unwrap();
+        let loaded = Project::load(&path).unwrap();
+        assert_eq!(loaded.output.path, Some(dir.join("song_loop.wav")));
+        assert_eq!(loaded.output.format, None);
+        assert_eq!(loaded.output.mp3_quality, None);
+    }
+
+    #[test]
+    fn rejects_an_mp3_quality_out_of_range() {
+        let dir = temp_dir("quality");
+        let path = dir.join("song.echoblend");
+        std::fs::write(
+            &path,
+            r#"{
+                "version": 1,
+                "source": { "path": "song.wav", "sha256": "" },
+                "times": { "start_s": 1.0, "end_s": 2.0, "crossfade_s": 0.0, "placement": "PreRoll", "loop_count": 1 },
+                "output": { "mp3_quality": 10 }
+            }"#,
+        )
+        .unwrap();
+        assert!(Project::load(&path).is_err());
+    }
+}
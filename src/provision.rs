@@ -0,0 +1,477 @@
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc::Sender, OnceLock},
+};
+
+use crate::{
+    archive::{self, ArchiveKind},
+    hash::sha256_file,
+    job,
+};
+
+// Static ffmpeg builds pinned by version and SHA-256.
+// Entries without a hash are refused, so a build has to be hashed before it can be installed.
+const MANIFEST: &str = include_str!("../assets/ffmpeg-manifest.json");
+
+// Environment variables that override the download source and manifest, e.g. to test against a local server
+const SOURCE_ENV: &str = "ECHOBLEND_FFMPEG_SOURCE";
+const MANIFEST_ENV: &str = "ECHOBLEND_FFMPEG_MANIFEST";
+
+// Marker written once a build is fully installed, containing the path to the binary
+const INSTALLED_MARKER: &str = ".installed";
+
+// How long a mirror may take to accept the connection, and then to send each chunk of the archive
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(serde::Deserialize)]
+struct Manifest {
+    version: String,
+    builds: Vec<Build>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+struct Build {
+    os: String,
+    arch: String,
+    url: String,
+    sha256: String,
+    archive: ArchiveKind,
+}
+
+impl Build {
+    fn file_name(&self) -> &str {
+        self.url.rsplit('/').next().unwrap_or(&self.url)
+    }
+}
+
+#[derive(Debug)]
+pub enum ProvisionEvent {
+    Status(String),
+    Progress { downloaded: u64, total: Option<u64> },
+    Finished(Result<PathBuf, String>),
+}
+
+pub struct ProvisionConfig {
+    /// Base URL the archive is fetched from instead of the one in the manifest
+    pub source: Option<String>,
+    /// Directory ffmpeg is installed into
+    pub install_root: PathBuf,
+}
+
+impl ProvisionConfig {
+    /// Uses the given source, falling back to the source environment variable,
+    /// and installs into the per-user data directory.
+    pub fn new(source: &str) -> Result<Self, String> {
+        let source = Some(source.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .or_else(|| std::env::var(SOURCE_ENV).ok().filter(|s| !s.is_empty()));
+        let install_root = directories_next::ProjectDirs::from("", "", "Echo Blend")
+            .ok_or("Could not find a data directory for the current user")?
+            .data_dir()
+            .join("ffmpeg");
+        Ok(Self {
+            source,
+            install_root,
+        })
+    }
+}
+
+/// Whether a build with a pinned checksum exists for the current OS and architecture
+pub fn is_supported_platform() -> bool {
+    manifest()
+        .ok()
+        .and_then(find_build)
+        .is_some_and(|build| !build.sha256.is_empty())
+}
+
+/// Downloads, verifies and installs ffmpeg on a background thread, reporting back through `tx`.
+pub fn provision_ffmpeg(config: ProvisionConfig, tx: Sender<ProvisionEvent>) {
+    std::thread::spawn(move || {
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| provision(&config, &tx)))
+                .unwrap_or_else(|panic| {
+                    Err(format!(
+                        "The download crashed: {}",
+                        job::panic_message(panic.as_ref())
+                    ))
+                });
+        let _ = tx.send(ProvisionEvent::Finished(result));
+    });
+}
+
+fn provision(config: &ProvisionConfig, tx: &Sender<ProvisionEvent>) -> Result<PathBuf, String> {
+    install(manifest()?, config, tx)
+}
+
+fn install(
+    manifest: &Manifest,
+    config: &ProvisionConfig,
+    tx: &Sender<ProvisionEvent>,
+) -> Result<PathBuf, String> {
+    let build = find_build(manifest).ok_or(format!(
+        "No ffmpeg build is available for {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    ))?;
+
+    let install_dir = config.install_root.join(&manifest.version);
+    if let Some(path) = installed_binary(&install_dir) {
+        return Ok(path);
+    }
+
+    if build.sha256.is_empty() {
+        return Err(format!(
+            "The ffmpeg {} build for {} {} has no pinned checksum, so it cannot be verified",
+            manifest.version, build.os, build.arch
+        ));
+    }
+
+    let url = match &config.source {
+        Some(source) => format!("{}/{}", source.trim_end_matches('/'), build.file_name()),
+        None => build.url.clone(),
+    };
+    let downloads = config.install_root.join("downloads");
+    std::fs::create_dir_all(&downloads).map_err(|e| e.to_string())?;
+    let archive_path = downloads.join(build.file_name());
+
+    let _ = tx.send(ProvisionEvent::Status(format!("Downloading {}...", url)));
+    download(&url, &archive_path, tx)?;
+
+    let _ = tx.send(ProvisionEvent::Status("Verifying checksum...".to_string()));
+    let hash = sha256_file(&archive_path)?;
+    if !hash.eq_ignore_ascii_case(&build.sha256) {
+        std::fs::remove_file(&archive_path).unwrap_or_default();
+        return Err(format!(
+            "Checksum mismatch for {}. Expected {}, got {}",
+            build.file_name(),
+            build.sha256,
+            hash
+        ));
+    }
+
+    let _ = tx.send(ProvisionEvent::Status("Extracting...".to_string()));
+    archive::extract(&archive_path, build.archive, &install_dir)?;
+    std::fs::remove_file(&archive_path).unwrap_or_default();
+
+    let binary = find_binary(&install_dir).ok_or("The archive does not contain ffmpeg")?;
+    std::process::Command::new(&binary)
+        .arg("-version")
+        .output()
+        .map_err(|e| format!("The installed ffmpeg does not run: {}", e))?;
+    std::fs::write(
+        install_dir.join(INSTALLED_MARKER),
+        binary.display().to_string(),
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(binary)
+}
+
+// The manifest is read once, as the UI asks whether the platform is supported on every frame
+fn manifest() -> Result<&'static Manifest, String> {
+    static LOADED: OnceLock<Result<Manifest, String>> = OnceLock::new();
+    LOADED
+        .get_or_init(load_manifest)
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+fn load_manifest() -> Result<Manifest, String> {
+    let manifest = match std::env::var(MANIFEST_ENV) {
+        Ok(path) => std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read manifest {}: {}", path, e))?,
+        Err(_) => MANIFEST.to_string(),
+    };
+    serde_json::from_str(&manifest).map_err(|e| format!("Invalid ffmpeg manifest: {}", e))
+}
+
+fn find_build(manifest: &Manifest) -> Option<Build> {
+    manifest
+        .builds
+        .iter()
+        .find(|b| b.os == std::env::consts::OS && b.arch == std::env::consts::ARCH)
+        .cloned()
+}
+
+fn installed_binary(install_dir: &Path) -> Option<PathBuf> {
+    let path = PathBuf::from(std::fs::read_to_string(install_dir.join(INSTALLED_MARKER)).ok()?);
+    path.is_file().then_some(path)
+}
+
+// Downloads into a .part file next to the target, resuming a previous partial download if there is one
+fn download(url: &str, target: &Path, tx: &Sender<ProvisionEvent>) -> Result<(), String> {
+    let part = target.with_extension("part");
+    let existing = std::fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+
+    // The blocking client applies the timeout to each read, so a slow download still finishes
+    // while a stalled one fails and can be resumed
+    let client = reqwest::blocking::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(READ_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut request = client.get(url);
+    if existing > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing));
+    }
+    let mut response = request.send().map_err(|e| e.to_string())?;
+
+    let status = response.status();
+    let (mut file, mut downloaded) = if status == reqwest::StatusCode::PARTIAL_CONTENT {
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&part)
+            .map_err(|e| e.to_string())?;
+        (file, existing)
+    } else if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE && existing > 0 {
+        // The partial file already holds the whole archive
+        std::fs::rename(&part, target).map_err(|e| e.to_string())?;
+        return Ok(());
+    } else if status.is_success() {
+        (std::fs::File::create(&part).map_err(|e| e.to_string())?, 0)
+    } else {
+        return Err(format!("Download failed with status {}", status));
+    };
+
+    let total = response.content_length().map(|len| len + downloaded);
+    let _ = tx.send(ProvisionEvent::Progress { downloaded, total });
+
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut last_report = downloaded;
+    loop {
+        let read = response.read(&mut buf).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buf[..read]).map_err(|e| e.to_string())?;
+        downloaded += read as u64;
+        if downloaded - last_report >= 512 * 1024 {
+            last_report = downloaded;
+            let _ = tx.send(ProvisionEvent::Progress { downloaded, total });
+        }
+    }
+    let _ = tx.send(ProvisionEvent::Progress { downloaded, total });
+
+    if let Some(total) = total {
+        if downloaded < total {
+            return Err(format!(
+                "Download ended early ({} of {} bytes), try again to resume",
+                downloaded, total
+            ));
+        }
+    }
+
+    drop(file);
+    std::fs::rename(&part, target).map_err(|e| e.to_string())
+}
+
+fn find_binary(dir: &Path) -> Option<PathBuf> {
+    let name = format!("ffmpeg{}", std::env::consts::EXE_SUFFIX);
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find_binary(&path) {
+                return Some(found);
+            }
+        } else if path.file_name().is_some_and(|n| n == name.as_str()) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    // Serves `body` to every request on a local port, returning the base URL
+    fn serve(body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+                    line.clear();
+                }
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(header.as_bytes());
+                let _ = stream.write_all(&body);
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    // A zip holding an ffmpeg that only exits successfully, as the installer runs it once
+    fn fixture_archive() -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().unix_permissions(0o755);
+        zip.start_file(
+            format!("ffmpeg-test/bin/ffmpeg{}", std::env::consts::EXE_SUFFIX),
+            options,
+        )
+        .unwrap();
+        zip.write_all(b"#!/bin/sh\nexit 0\n").unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn test_manifest(url: String, sha256: String) -> Manifest {
+        Manifest {
+            version: "test".to_string(),
+            builds: vec![Build {
+                os: std::env::consts::OS.to_string(),
+                arch: std::env::consts::ARCH.to_string(),
+                url: format!("{}/ffmpeg-test.zip", url),
+                sha256,
+                archive: ArchiveKind::Zip,
+            }],
+        }
+    }
+
+    fn install_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "echo_blend_provision_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    fn sha256(bytes: &[u8]) -> String {
+        let path = install_root("hash");
+        std::fs::create_dir_all(&path).unwrap();
+        let file = path.join("archive");
+        std::fs::write(&file, bytes).unwrap();
+        let hash = sha256_file(&file).unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+        hash
+    }
+
+    #[test]
+    fn the_bundled_manifest_parses() {
+        let manifest: Manifest = serde_json::from_str(MANIFEST).unwrap();
+        assert!(!manifest.version.is_empty());
+        for (os, arch) in [
+            ("windows", "x86_64"),
+            ("linux", "x86_64"),
+            ("linux", "aarch64"),
+            ("macos", "x86_64"),
+            ("macos", "aarch64"),
+        ] {
+            let build = manifest
+                .builds
+                .iter()
+                .find(|build| build.os == os && build.arch == arch)
+                .unwrap_or_else(|| panic!("no build for {} {}", os, arch));
+            assert!(build.url.starts_with("https://"), "{}", build.url);
+            // A checksum that is there must be a whole SHA-256
+            assert!(
+                build.sha256.is_empty()
+                    || (build.sha256.len() == 64
+                        && build.sha256.chars().all(|c| c.is_ascii_hexdigit())),
+                "{} {}: {}",
+                os,
+                arch,
+                build.sha256
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "the checksums of the bundled builds are not pinned yet"]
+    fn the_bundled_manifest_pins_every_build() {
+        let manifest: Manifest = serde_json::from_str(MANIFEST).unwrap();
+        for build in &manifest.builds {
+            assert!(
+                !build.sha256.is_empty(),
+                "{} {} has no checksum",
+                build.os,
+                build.arch
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_a_checksum_mismatch() {
+        let url = serve(fixture_archive());
+        let root = install_root("mismatch");
+        let config = ProvisionConfig {
+            source: None,
+            install_root: root.clone(),
+        };
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let manifest = test_manifest(url, "0".repeat(64));
+        let error = install(&manifest, &config, &tx).unwrap_err();
+        assert!(error.starts_with("Checksum mismatch"), "{}", error);
+        // Neither the download nor anything extracted from it is left behind
+        assert!(!root.join("downloads").join("ffmpeg-test.zip").exists());
+        assert!(!root.join("test").exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_build_without_a_checksum() {
+        let root = install_root("unpinned");
+        let config = ProvisionConfig {
+            source: None,
+            install_root: root.clone(),
+        };
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let manifest = test_manifest("http://127.0.0.1:9".to_string(), String::new());
+        let error = install(&manifest, &config, &tx).unwrap_err();
+        assert!(error.contains("no pinned checksum"), "{}", error);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    // The fixture ffmpeg is a shell script, which only runs on Unix
+    #[cfg(unix)]
+    #[test]
+    fn installs_a_verified_build_from_a_mirror() {
+        let archive = fixture_archive();
+        let hash = sha256(&archive);
+        let url = serve(archive);
+        let root = install_root("success");
+        // The mirror replaces the manifest's own host, which does not exist
+        let config = ProvisionConfig {
+            source: Some(url),
+            install_root: root.clone(),
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        let manifest = test_manifest("http://unreachable.invalid".to_string(), hash);
+        let binary = install(&manifest, &config, &tx).unwrap();
+        assert_eq!(
+            binary,
+            root.join("test")
+                .join("ffmpeg-test")
+                .join("bin")
+                .join("ffmpeg")
+        );
+        assert!(rx
+            .try_iter()
+            .any(|event| matches!(event, ProvisionEvent::Progress { .. })));
+        assert!(!root.join("downloads").join("ffmpeg-test.zip").exists());
+        // A second run finds the installed build without downloading it again
+        let offline = ProvisionConfig {
+            source: Some("http://unreachable.invalid".to_string()),
+            install_root: root.clone(),
+        };
+        assert_eq!(install(&manifest, &offline, &tx).unwrap(), binary);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
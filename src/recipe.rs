@@ -0,0 +1,177 @@
+use std::path::{Path, PathBuf};
+
+use crate::{hash::sha256_file, project::ProjectTimes, render::LoopSpec};
+
+// Starts the comment tag of every rendered loop, so a recipe is never read from someone else's comment
+const COMMENT_PREFIX: &str = "EchoBlend recipe: ";
+
+// How many other songs next to a rendered loop are hashed when looking for its source
+const MAX_CANDIDATES: usize = 50;
+
+/// The settings a loop was rendered with, embedded in the rendered file's comment tag
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Recipe {
+    pub echoblend_version: String,
+    /// File name of the song the loop was rendered from
+    pub source_name: String,
+    pub source_sha256: String,
+    #[serde(flatten)]
+    pub times: ProjectTimes,
+}
+
+impl Recipe {
+    /// The recipe of `spec`, whose input has the given SHA-256
+    pub fn new(spec: &LoopSpec, source_sha256: String) -> Self {
+        Self {
+            echoblend_version: env!("CARGO_PKG_VERSION").to_string(),
+            source_name: spec
+                .input()
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            source_sha256,
+            times: ProjectTimes {
+                start_s: spec.start_s(),
+                end_s: spec.end_s(),
+                crossfade_s: spec.crossfade_s(),
+                placement: spec.placement(),
+                curve: spec.curve(),
+                loop_count: spec.loop_count(),
+            },
+        }
+    }
+
+    pub fn to_comment(&self) -> String {
+        // Kept on one line, as ffmpeg prints each line of a tag separately
+        format!(
+            "{}{}",
+            COMMENT_PREFIX,
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+
+    /// Reads a recipe back from a comment tag, `None` if the comment is not one
+    pub fn from_comment(comment: &str) -> Option<Self> {
+        serde_json::from_str(comment.trim().strip_prefix(COMMENT_PREFIX)?).ok()
+    }
+
+    /// Looks for the source song in `dir` by its hash, trying a file with its original name first
+    pub fn find_source(&self, dir: &Path) -> Option<PathBuf> {
+        let named = dir.join(&self.source_name);
+        if self.is_source(&named) {
+            return Some(named);
+        }
+        candidates(dir, &named)
+            .into_iter()
+            .find(|path| self.is_source(path))
+    }
+
+    /// Whether the file at `path` is the song the loop was rendered from
+    pub fn is_source(&self, path: &Path) -> bool {
+        path.is_file() && sha256_file(path).is_ok_and(|sha256| sha256 == self.source_sha256)
+    }
+}
+
+// The songs in `dir` other than `named` that could be the source, at most MAX_CANDIDATES of them
+fn candidates(dir: &Path, named: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path != named
+                && path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| {
+                        ext.eq_ignore_ascii_case("wav") || ext.eq_ignore_ascii_case("mp3")
+                    })
+        })
+        .take(MAX_CANDIDATES)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::looper::CrossfadePlacement;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("echo_blend_recipe_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn recipe(sha256: &str) -> Recipe {
+        let spec = LoopSpec::builder("music/song.wav", "song_loop.wav")
+            .start(1.5)
+            .end(12.25)
+            .crossfade(0.5)
+            .placement(CrossfadePlacement::Centred)
+            .loop_count(4)
+            .build()
+            .unwrap();
+        Recipe::new(&spec, sha256.to_string())
+    }
+
+    #[test]
+    fn reads_back_the_comment_it_writes() {
+        let recipe = recipe("abc123");
+        let comment = recipe.to_comment();
+        assert!(comment.starts_with(COMMENT_PREFIX));
+        assert!(!comment.contains('\n'));
+        assert_eq!(Recipe::from_comment(&comment), Some(recipe.clone()));
+        assert_eq!(recipe.source_name, "song.wav");
+        assert_eq!(recipe.times.loop_count, 4);
+    }
+
+    #[test]
+    fn ignores_a_comment_it_did_not_write() {
+        assert_eq!(Recipe::from_comment("Ripped with EAC"), None);
+        // Even JSON that would parse is not read without the prefix
+        let json = recipe("abc123").to_comment().replace(COMMENT_PREFIX, "");
+        assert_eq!(Recipe::from_comment(&json), None);
+    }
+
+    #[test]
+    fn ignores_a_malformed_recipe() {
+        assert_eq!(
+            Recipe::from_comment(&format!("{}{{\"source_name\": ", COMMENT_PREFIX)),
+            None
+        );
+    }
+
+    #[test]
+    fn finds_a_renamed_source_by_its_hash() {
+        let dir = temp_dir("renamed");
+        let song = dir.join("renamed.wav");
+        std::fs::write(&song, b"the song").unwrap();
+        std::fs::write(dir.join("other.mp3"), b"another song").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"the song").unwrap();
+        let recipe = recipe(&sha256_file(&song).unwrap());
+        assert_eq!(recipe.find_source(&dir), Some(song));
+        assert_eq!(recipe.find_source(&dir.join("missing")), None);
+    }
+
+    #[test]
+    fn hashes_a_bounded_number_of_songs() {
+        let dir = temp_dir("bounded");
+        for i in 0..MAX_CANDIDATES + 10 {
+            std::fs::write(dir.join(format!("{}.wav", i)), b"").unwrap();
+        }
+        std::fs::write(dir.join("cover.jpg"), b"").unwrap();
+        let named = dir.join("0.wav");
+        let candidates = candidates(&dir, &named);
+        assert_eq!(candidates.len(), MAX_CANDIDATES);
+        assert!(!candidates.contains(&named));
+        assert!(candidates
+            .iter()
+            .all(|path| path.extension().is_some_and(|ext| ext == "wav")));
+    }
+}
@@ -0,0 +1,603 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    backend::{MediaBackend, MediaInfo},
+    hash,
+    job::{self, CancelToken, JobError, JobEvent, JobSender, LogLevel, Observer},
+    looper::{self, CrossfadeCurve, CrossfadePlacement, TempFiles},
+    recipe::Recipe,
+    seams::{self, Rating, SeamReport},
+    workspace::Workspace,
+};
+
+/// Everything needed to render one loop, checked to fit together when it is built.
+///
+/// Times are in seconds from the start of the input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopSpec {
+    input: PathBuf,
+    output: PathBuf,
+    start_s: f64,
+    end_s: f64,
+    crossfade_s: f64,
+    placement: CrossfadePlacement,
+    curve: CrossfadeCurve,
+    loop_count: u8,
+    mp3_quality: u8,
+    test: bool,
+    scratch_dir: Option<PathBuf>,
+    keep_intermediates: bool,
+    analyse_seams: bool,
+}
+
+impl LoopSpec {
+    /// Starts a spec that loops `input` into `output`, both of which must be .wav or .mp3 files.
+    ///
+    /// The loop repeats once with no crossfade unless the builder is told otherwise.
+    pub fn builder(input: impl Into<PathBuf>, output: impl Into<PathBuf>) -> LoopSpecBuilder {
+        LoopSpecBuilder {
+            spec: LoopSpec {
+                input: input.into(),
+                output: output.into(),
+                start_s: 0.0,
+                end_s: 0.0,
+                crossfade_s: 0.0,
+                placement: CrossfadePlacement::default(),
+                curve: CrossfadeCurve::default(),
+                loop_count: 1,
+                mp3_quality: DEFAULT_MP3_QUALITY,
+                test: false,
+                scratch_dir: None,
+                keep_intermediates: false,
+                analyse_seams: true,
+            },
+        }
+    }
+
+    pub fn input(&self) -> &Path {
+        &self.input
+    }
+
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    pub fn start_s(&self) -> f64 {
+        self.start_s
+    }
+
+    pub fn end_s(&self) -> f64 {
+        self.end_s
+    }
+
+    pub fn crossfade_s(&self) -> f64 {
+        self.crossfade_s
+    }
+
+    pub fn placement(&self) -> CrossfadePlacement {
+        self.placement
+    }
+
+    pub fn curve(&self) -> CrossfadeCurve {
+        self.curve
+    }
+
+    pub fn loop_count(&self) -> u8 {
+        self.loop_count
+    }
+
+    /// The VBR quality MP3 loops are encoded at, from 0, the best, to 9, the smallest
+    pub fn mp3_quality(&self) -> u8 {
+        self.mp3_quality
+    }
+
+    /// Whether only a single seam is rendered, to check how the loop sounds
+    pub fn is_test(&self) -> bool {
+        self.test
+    }
+
+    pub fn scratch_dir(&self) -> Option<&Path> {
+        self.scratch_dir.as_deref()
+    }
+
+    pub fn keeps_intermediates(&self) -> bool {
+        self.keep_intermediates
+    }
+
+    pub fn analyses_seams(&self) -> bool {
+        self.analyse_seams
+    }
+
+    // Checks the times, and that they fit within the input if its duration is known
+    fn validate(&self, duration_s: Option<f32>) -> Result<(), String> {
+        let to_ms = |seconds: f64| (seconds * 1000.0).round() as u32;
+        looper::validate_times(
+            to_ms(self.start_s),
+            to_ms(self.end_s),
+            to_ms(self.crossfade_s),
+            self.placement,
+            duration_s,
+        )
+    }
+}
+
+/// Sets the optional parts of a [`LoopSpec`], see [`LoopSpec::builder`]
+#[derive(Debug, Clone)]
+pub struct LoopSpecBuilder {
+    spec: LoopSpec,
+}
+
+impl LoopSpecBuilder {
+    /// Where the loop is written, in place of the output the builder was started with
+    pub fn output(mut self, output: impl Into<PathBuf>) -> Self {
+        self.spec.output = output.into();
+        self
+    }
+
+    /// Where the loop starts
+    pub fn start(mut self, seconds: f64) -> Self {
+        self.spec.start_s = seconds;
+        self
+    }
+
+    /// Where the loop ends
+    pub fn end(mut self, seconds: f64) -> Self {
+        self.spec.end_s = seconds;
+        self
+    }
+
+    /// How long the crossfade at each seam is, 0 for a hard cut
+    pub fn crossfade(mut self, seconds: f64) -> Self {
+        self.spec.crossfade_s = seconds;
+        self
+    }
+
+    pub fn placement(mut self, placement: CrossfadePlacement) -> Self {
+        self.spec.placement = placement;
+        self
+    }
+
+    pub fn curve(mut self, curve: CrossfadeCurve) -> Self {
+        self.spec.curve = curve;
+        self
+    }
+
+    /// How many times the section between the start and end repeats
+    pub fn loop_count(mut self, loop_count: u8) -> Self {
+        self.spec.loop_count = loop_count;
+        self
+    }
+
+    /// Render a single seam instead of the full loop
+    pub fn test(mut self, test: bool) -> Self {
+        self.spec.test = test;
+        self
+    }
+
+    /// The VBR quality MP3 loops are encoded at, from 0, the best, to 9, the smallest
+    pub fn mp3_quality(mut self, quality: u8) -> Self {
+        self.spec.mp3_quality = quality;
+        self
+    }
+
+    /// Where intermediate files are written, instead of the system temp directory
+    pub fn scratch_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.spec.scratch_dir = Some(dir.into());
+        self
+    }
+
+    /// Leave the intermediate files behind for debugging
+    pub fn keep_intermediates(mut self, keep: bool) -> Self {
+        self.spec.keep_intermediates = keep;
+        self
+    }
+
+    /// Whether the seams of the rendered loop are analysed and a report written next to it
+    pub fn analyse_seams(mut self, analyse: bool) -> Self {
+        self.spec.analyse_seams = analyse;
+        self
+    }
+
+    /// Checks the files and times, without knowing how long the input is.
+    ///
+    /// [`render`] checks the times again against the input's duration.
+    pub fn build(self) -> Result<LoopSpec, String> {
+        let spec = self.spec;
+        for path in [&spec.input, &spec.output] {
+            if !is_audio_file(path) {
+                return Err(format!(
+                    "You can only use .wav or .mp3 files. Your file was: {}",
+                    path.display()
+                ));
+            }
+        }
+        for (name, seconds) in [
+            ("start time", spec.start_s),
+            ("end time", spec.end_s),
+            ("crossfade duration", spec.crossfade_s),
+        ] {
+            if !seconds.is_finite() || seconds < 0.0 {
+                return Err(format!("The {} must be a positive number of seconds", name));
+            }
+        }
+        if spec.mp3_quality > 9 {
+            return Err(format!(
+                "The MP3 quality must be from 0 to 9, not {}",
+                spec.mp3_quality
+            ));
+        }
+        spec.validate(None)?;
+        Ok(spec)
+    }
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("wav") || ext.eq_ignore_ascii_case("mp3"))
+}
+
+/// The audio format a loop is written in
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Wav,
+    Mp3,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "wav",
+            OutputFormat::Mp3 => "mp3",
+        }
+    }
+
+    /// The format a file is written in going by its extension, `None` for anything else
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        [OutputFormat::Wav, OutputFormat::Mp3]
+            .into_iter()
+            .find(|format| extension.eq_ignore_ascii_case(format.extension()))
+    }
+}
+
+/// The VBR quality MP3 loops are encoded at unless told otherwise
+pub const DEFAULT_MP3_QUALITY: u8 = 2;
+
+// Average bitrate of the MP3 encoder at each VBR quality, from LAME's documentation
+const MP3_BITS_PER_SECOND: [f32; 10] = [
+    245_000.0, 225_000.0, 190_000.0, 175_000.0, 165_000.0, 130_000.0, 115_000.0, 100_000.0,
+    85_000.0, 65_000.0,
+];
+
+/// Roughly how many bytes a loop lasting `duration_s` takes up in the format of `output`.
+///
+/// WAV loops are 16-bit PCM at the song's sample rate, assumed to be 44.1 kHz stereo if unknown,
+/// and MP3 loops are encoded at `mp3_quality`.
+pub fn estimated_size(
+    output: &Path,
+    duration_s: f32,
+    info: &MediaInfo,
+    mp3_quality: u8,
+) -> Option<u64> {
+    let extension = output.extension()?.to_str()?.to_lowercase();
+    let bytes_per_second = match extension.as_str() {
+        "wav" => (info.sample_rate.unwrap_or(44100) * info.channels.unwrap_or(2) * 2) as f32,
+        "mp3" => MP3_BITS_PER_SECOND[usize::from(mp3_quality.min(9))] / 8.0,
+        _ => return None,
+    };
+    Some((duration_s.max(0.0) * bytes_per_second) as u64)
+}
+
+/// What rendering a loop produced
+#[derive(Debug)]
+pub struct RenderOutput {
+    pub output: PathBuf,
+    /// The seam analysis, if it was asked for and succeeded
+    pub seam_report: Option<SeamReport>,
+    /// Where the seam report was written, if it was
+    pub report_path: Option<PathBuf>,
+    pub summary: String,
+}
+
+impl RenderOutput {
+    /// Every file that was written
+    pub fn files(&self) -> Vec<PathBuf> {
+        std::iter::once(self.output.clone())
+            .chain(self.report_path.clone())
+            .collect()
+    }
+}
+
+/// Renders the loop described by `spec` through `backend`, reporting each step to `observer`.
+///
+/// Blocks until the loop is written and its intermediate files are removed,
+/// or until the job stops early with [`JobError::Cancelled`] after `cancel` is cancelled.
+pub fn render(
+    spec: &LoopSpec,
+    backend: &dyn MediaBackend,
+    observer: &mut dyn Observer,
+    cancel: &CancelToken,
+) -> Result<RenderOutput, JobError> {
+    // An input that cannot be probed is still rendered, so ffmpeg can explain what is wrong with it
+    let info = backend
+        .probe(&spec.input.display().to_string())
+        .unwrap_or_default();
+    spec.validate(info.duration_s).map_err(JobError::Invalid)?;
+
+    let workspace =
+        Workspace::create(spec.scratch_dir(), spec.keep_intermediates).map_err(|message| {
+            JobError::Io {
+                context: "Failed to prepare the loop".to_string(),
+                message,
+            }
+        })?;
+    let files = TempFiles::new(&workspace);
+    let result = job::run(observer, |tx| {
+        // The settings go into the output so it can be traced back to them later
+        let comment = match recipe_comment(spec) {
+            Ok(comment) => Some(comment),
+            Err(e) => {
+                let _ = tx.send(JobEvent::Warning(format!(
+                    "The recipe was not embedded, {}",
+                    e
+                )));
+                None
+            }
+        };
+        let metadata: Vec<(&str, &str)> = comment.iter().map(|c| ("comment", c.as_str())).collect();
+        looper::render_loop(
+            backend,
+            spec,
+            &files,
+            workspace.dir(),
+            &metadata,
+            tx,
+            cancel,
+        )?;
+        cancel.check()?;
+
+        let mut rendered = RenderOutput {
+            output: spec.output.clone(),
+            seam_report: None,
+            report_path: None,
+            summary: "Done!".to_string(),
+        };
+        if spec.analyse_seams {
+            analyse_seams(backend, spec, &mut rendered, tx);
+        }
+        Ok(rendered)
+    });
+
+    // A step killed by the cancel fails in its own way, which is not what went wrong
+    let result = match result {
+        Err(_) if cancel.is_cancelled() => Err(JobError::Cancelled),
+        result => result,
+    };
+
+    // The workspace goes once ffmpeg has let go of every file in it
+    if workspace.keeps_intermediates() {
+        observer.on_event(JobEvent::info(format!(
+            "Keeping intermediate files in {}",
+            workspace.dir().display()
+        )));
+    } else {
+        observer.on_event(JobEvent::info("Deleting files..."));
+    }
+    drop(workspace);
+    result
+}
+
+// The comment tag holding the recipe of the loop, which needs the input to be readable to hash it
+pub(crate) fn recipe_comment(spec: &LoopSpec) -> Result<String, String> {
+    let sha256 = hash::sha256_file(spec.input())
+        .map_err(|e| format!("{} could not be read: {}", spec.input().display(), e))?;
+    Ok(Recipe::new(spec, sha256).to_comment())
+}
+
+// A failed analysis is only a warning, the loop itself was rendered
+fn analyse_seams(
+    backend: &dyn MediaBackend,
+    spec: &LoopSpec,
+    rendered: &mut RenderOutput,
+    tx: &JobSender,
+) {
+    let _ = tx.send(JobEvent::StepStarted("Analysing seams...".to_string()));
+    let output_path = spec.output.display().to_string();
+    let seam_times = looper::seam_times(spec);
+    let report = backend
+        .decode_pcm(&output_path)
+        .and_then(|wav| seams::analyse_seams(wav, &output_path, &seam_times));
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            let _ = tx.send(JobEvent::Warning(format!("Failed to analyse seams: {}", e)));
+            return;
+        }
+    };
+
+    for seam in &report.seams {
+        let line = format!(
+            "Seam {} at {:.3}s: {} (jump {:.4}, level change {:+.2} dB, spectral difference {:.2}, {} clipped samples)",
+            seam.index + 1,
+            seam.time_s,
+            seam.rating,
+            seam.discontinuity,
+            seam.rms_change_db,
+            seam.spectral_difference,
+            seam.clipped_samples
+        );
+        let _ = tx.send(match seam.rating {
+            Rating::Pass => JobEvent::info(line),
+            Rating::Warn => JobEvent::Warning(line),
+            Rating::Fail => JobEvent::Log {
+                text: line,
+                level: LogLevel::Error,
+            },
+        });
+    }
+    let report_path = seams::report_path(&output_path);
+    match report.write_json(&report_path) {
+        Ok(_) => rendered.report_path = Some(report_path),
+        Err(e) => {
+            let _ = tx.send(JobEvent::Warning(format!(
+                "Failed to write seam report {}: {}",
+                report_path.display(),
+                e
+            )));
+        }
+    }
+    rendered.summary = format!("Done! {}", report.summary());
+    rendered.seam_report = Some(report);
+}
+
+/// Renders the loop on a new thread, sending its events and then one `Finished` or `Failed` to `tx`.
+///
+/// Cancelling the returned token stops the render and removes its intermediate files.
+#[cfg(feature = "gui")]
+pub fn spawn(spec: LoopSpec, backend: impl MediaBackend + 'static, tx: JobSender) -> CancelToken {
+    let cancel = CancelToken::default();
+    let job_cancel = cancel.clone();
+    std::thread::spawn(move || {
+        let mut observer = tx.clone();
+        let _ = tx.send(match render(&spec, &backend, &mut observer, &job_cancel) {
+            Ok(rendered) => JobEvent::Finished {
+                outputs: rendered.files(),
+                summary: rendered.summary,
+            },
+            Err(e) => JobEvent::Failed(e),
+        });
+    });
+    cancel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(sample_rate: u32, channels: u32) -> MediaInfo {
+        MediaInfo {
+            sample_rate: Some(sample_rate),
+            channels: Some(channels),
+            ..MediaInfo::default()
+        }
+    }
+
+    fn times(start_s: f64, end_s: f64, crossfade_s: f64) -> LoopSpecBuilder {
+        LoopSpec::builder("song.wav", "loop.wav")
+            .start(start_s)
+            .end(end_s)
+            .crossfade(crossfade_s)
+    }
+
+    #[test]
+    fn the_end_must_come_after_the_start() {
+        for (start_s, end_s) in [(20.0, 10.0), (10.0, 10.0)] {
+            let error = times(start_s, end_s, 0.0).build().unwrap_err();
+            assert!(
+                error.contains("start time must be less than the end time"),
+                "{}",
+                error
+            );
+        }
+        assert!(times(10.0, 10.001, 0.0).build().is_ok());
+    }
+
+    #[test]
+    fn the_crossfade_must_be_shorter_than_the_loop() {
+        let error = times(10.0, 20.0, 10.0).build().unwrap_err();
+        assert!(
+            error.contains("crossfade duration must be less than the loop duration"),
+            "{}",
+            error
+        );
+        assert!(times(10.0, 20.0, 9.999).build().is_ok());
+    }
+
+    #[test]
+    fn times_must_be_positive_numbers() {
+        for (start_s, end_s, crossfade_s) in [
+            (-1.0, 20.0, 0.0),
+            (10.0, f64::NAN, 0.0),
+            (10.0, 20.0, f64::INFINITY),
+        ] {
+            let error = times(start_s, end_s, crossfade_s).build().unwrap_err();
+            assert!(
+                error.contains("must be a positive number of seconds"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn only_wav_and_mp3_files_are_looped() {
+        assert!(LoopSpec::builder("song.flac", "loop.wav")
+            .end(1.0)
+            .build()
+            .is_err());
+        assert!(LoopSpec::builder("song.wav", "loop.ogg")
+            .end(1.0)
+            .build()
+            .is_err());
+        assert!(LoopSpec::builder("SONG.MP3", "loop.Wav")
+            .end(1.0)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn the_mp3_quality_must_be_in_range() {
+        assert!(times(10.0, 20.0, 0.0).mp3_quality(9).build().is_ok());
+        let error = times(10.0, 20.0, 0.0).mp3_quality(10).build().unwrap_err();
+        assert!(error.contains("from 0 to 9"), "{}", error);
+    }
+
+    #[test]
+    fn a_zero_loop_count_plays_the_section_once_more() {
+        // No repeats still joins the end back to the start once, which the CLI and projects allow
+        let spec = times(10.0, 20.0, 1.0).loop_count(0).build().unwrap();
+        assert_eq!(looper::seam_times(&spec), [19.0, 20.0]);
+    }
+
+    #[test]
+    fn estimates_wav_from_the_song_format() {
+        let output = Path::new("loop.wav");
+        // 44.1 kHz stereo at 2 bytes a sample unless the song says otherwise
+        assert_eq!(
+            estimated_size(output, 10.0, &MediaInfo::default(), 2),
+            Some(1_764_000)
+        );
+        assert_eq!(
+            estimated_size(output, 10.0, &info(48000, 1), 2),
+            Some(960_000)
+        );
+        assert_eq!(
+            estimated_size(Path::new("LOOP.WAV"), 1.0, &info(8000, 2), 2),
+            Some(32_000)
+        );
+    }
+
+    #[test]
+    fn estimates_mp3_from_the_quality() {
+        let output = Path::new("loop.mp3");
+        let song = info(48000, 2);
+        assert_eq!(estimated_size(output, 10.0, &song, 0), Some(306_250));
+        assert_eq!(estimated_size(output, 10.0, &song, 2), Some(237_500));
+        assert_eq!(estimated_size(output, 10.0, &song, 9), Some(81_250));
+        // A quality out of range is taken as the smallest
+        assert_eq!(estimated_size(output, 10.0, &song, 12), Some(81_250));
+    }
+
+    #[test]
+    fn cannot_estimate_other_formats() {
+        let song = MediaInfo::default();
+        assert_eq!(estimated_size(Path::new("loop.flac"), 10.0, &song, 2), None);
+        assert_eq!(estimated_size(Path::new("loop"), 10.0, &song, 2), None);
+        assert_eq!(
+            estimated_size(Path::new("loop.wav"), -1.0, &song, 2),
+            Some(0)
+        );
+    }
+}
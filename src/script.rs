@@ -0,0 +1,460 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    backend::{self, Codec, MediaBackend, MediaInfo, Segment, StepContext},
+    diagnostics::Diagnostic,
+    job::CancelToken,
+    looper::{self, TempFiles},
+    render::{self, LoopSpec},
+};
+
+/// The shells a render script can be written for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Posix,
+    PowerShell,
+}
+
+impl Shell {
+    /// The shell a script saved as `path` is for, PowerShell for .ps1 files and POSIX otherwise
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("ps1") => Shell::PowerShell,
+            _ => Shell::Posix,
+        }
+    }
+
+    /// The shell scripts usually run with on this system
+    pub fn native() -> Self {
+        if cfg!(windows) {
+            Shell::PowerShell
+        } else {
+            Shell::Posix
+        }
+    }
+}
+
+enum ScriptStep {
+    Ffmpeg { name: String, args: Vec<String> },
+    WriteFile { path: String, contents: String },
+}
+
+/// Every ffmpeg command a loop is rendered with, to be run by hand or on another machine.
+///
+/// Intermediate files go to a folder next to the output, which the script creates and,
+/// unless the spec keeps intermediates, removes again.
+pub struct RenderScript {
+    ffmpeg_path: String,
+    work_dir: String,
+    keep_intermediates: bool,
+    steps: Vec<ScriptStep>,
+}
+
+impl RenderScript {
+    /// Plans the commands that render `spec` with the ffmpeg at `ffmpeg_path`, without running any.
+    ///
+    /// The recipe is embedded as when rendering if the input can be read.
+    pub fn new(spec: &LoopSpec, ffmpeg_path: &str) -> Result<Self, String> {
+        let work_dir = work_dir(spec.output());
+        let backend = ScriptBackend::default();
+        let files = TempFiles::in_dir(&work_dir);
+        let comment = render::recipe_comment(spec).ok();
+        let metadata: Vec<(&str, &str)> = comment.iter().map(|c| ("comment", c.as_str())).collect();
+        // Progress has nowhere to go as nothing runs
+        let (tx, _rx) = std::sync::mpsc::channel();
+        looper::render_loop(
+            &backend,
+            spec,
+            &files,
+            &work_dir,
+            &metadata,
+            &tx,
+            &CancelToken::default(),
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(Self {
+            ffmpeg_path: ffmpeg_path.to_string(),
+            work_dir: work_dir.display().to_string(),
+            keep_intermediates: spec.keeps_intermediates(),
+            steps: backend.steps.into_inner().unwrap_or_default(),
+        })
+    }
+
+    /// A complete script for `shell` that stops at the first failing command
+    pub fn to_script(&self, shell: Shell) -> String {
+        let mut lines = match shell {
+            Shell::Posix => vec![
+                "#!/bin/sh".to_string(),
+                "# Renders an EchoBlend loop".to_string(),
+                "set -e".to_string(),
+                format!("mkdir -p {}", quote(shell, &self.work_dir)),
+            ],
+            Shell::PowerShell => vec![
+                "# Renders an EchoBlend loop".to_string(),
+                "$ErrorActionPreference = 'Stop'".to_string(),
+                // Arguments are escaped for the classic Windows command line, whatever the PowerShell version
+                "$PSNativeCommandArgumentPassing = 'Legacy'".to_string(),
+                // .NET resolves relative paths against the process directory, not the script's location
+                "[System.Environment]::CurrentDirectory = (Get-Location).ProviderPath".to_string(),
+                format!(
+                    "[System.IO.Directory]::CreateDirectory({}) | Out-Null",
+                    quote(shell, &self.work_dir)
+                ),
+            ],
+        };
+
+        for step in &self.steps {
+            lines.push(String::new());
+            match step {
+                ScriptStep::Ffmpeg { name, args } => {
+                    lines.push(format!("# {}", name));
+                    lines.push(self.command(shell, args));
+                    if shell == Shell::PowerShell {
+                        lines.push("if ($LASTEXITCODE -ne 0) { exit $LASTEXITCODE }".to_string());
+                    }
+                }
+                ScriptStep::WriteFile { path, contents } => {
+                    let quoted: Vec<String> =
+                        contents.lines().map(|line| quote(shell, line)).collect();
+                    lines.push("# Writing the list of segments to join".to_string());
+                    lines.push(match shell {
+                        Shell::Posix => {
+                            format!(
+                                "printf '%s\\n' {} > {}",
+                                quoted.join(" "),
+                                quote(shell, path)
+                            )
+                        }
+                        Shell::PowerShell => format!(
+                            "[System.IO.File]::WriteAllText({}, (({}) -join \"`n\") + \"`n\")",
+                            quote(shell, path),
+                            quoted.join(", ")
+                        ),
+                    });
+                }
+            }
+        }
+
+        if !self.keep_intermediates {
+            lines.push(String::new());
+            lines.push(match shell {
+                Shell::Posix => format!("rm -rf {}", quote(shell, &self.work_dir)),
+                Shell::PowerShell => format!(
+                    "Remove-Item -LiteralPath {} -Recurse -Force",
+                    quote(shell, &self.work_dir)
+                ),
+            });
+        }
+        lines.join("\n") + "\n"
+    }
+
+    fn command(&self, shell: Shell, args: &[String]) -> String {
+        let mut command = match shell {
+            Shell::Posix => quote(shell, &self.ffmpeg_path),
+            // The call operator runs a quoted executable path instead of printing it
+            Shell::PowerShell => format!("& {}", quote(shell, &self.ffmpeg_path)),
+        };
+        for arg in args {
+            command.push(' ');
+            command.push_str(&match shell {
+                Shell::Posix => quote(shell, arg),
+                Shell::PowerShell => quote(shell, &windows_arg(arg)),
+            });
+        }
+        command
+    }
+}
+
+// Intermediates go next to the output so the script does not depend on this machine's temp folder
+fn work_dir(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{}_echoblend_work", stem))
+}
+
+// Quotes `text` as a single literal argument for `shell`
+fn quote(shell: Shell, text: &str) -> String {
+    match shell {
+        // Nothing is special inside single quotes, so a quote ends them, is escaped, and they start again
+        Shell::Posix => format!("'{}'", text.replace('\'', "'\\''")),
+        // PowerShell also treats typographic single quotes as quotes, all of which are doubled
+        Shell::PowerShell => {
+            let mut quoted = String::from("'");
+            for c in text.chars() {
+                if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+                    quoted.push(c);
+                }
+                quoted.push(c);
+            }
+            quoted.push('\'');
+            quoted
+        }
+    }
+}
+
+// Escapes an argument so the Windows command line splitting gives it back unchanged.
+// Legacy PowerShell wraps arguments with spaces in double quotes but escapes nothing inside them.
+fn windows_arg(arg: &str) -> String {
+    let mut escaped = String::new();
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes before a quote escape each other, then one more escapes the quote
+                escaped.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                escaped.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            escaped.push(c);
+        }
+    }
+    // Trailing backslashes would escape the closing quote PowerShell adds around arguments with spaces
+    let trailing = if arg.contains([' ', '\t']) { 2 } else { 1 };
+    escaped.push_str(&"\\".repeat(backslashes * trailing));
+    escaped
+}
+
+// Records the ffmpeg command of each step instead of running it
+#[derive(Default)]
+struct ScriptBackend {
+    steps: Mutex<Vec<ScriptStep>>,
+}
+
+impl ScriptBackend {
+    fn push(&self, step: ScriptStep) -> Result<(), Diagnostic> {
+        if let Ok(mut steps) = self.steps.lock() {
+            steps.push(step);
+        }
+        Ok(())
+    }
+
+    fn ffmpeg(&self, step: &StepContext<'_>, args: Vec<String>) -> Result<(), Diagnostic> {
+        self.push(ScriptStep::Ffmpeg {
+            name: step.name.to_string(),
+            args,
+        })
+    }
+}
+
+impl MediaBackend for ScriptBackend {
+    fn probe(&self, _input: &str) -> Result<MediaInfo, String> {
+        Ok(MediaInfo::default())
+    }
+
+    fn decode_pcm(&self, _input: &str) -> Result<Box<dyn std::io::Read + Send>, String> {
+        Err("A render script does not produce audio".to_string())
+    }
+
+    fn render_segment(
+        &self,
+        step: &StepContext<'_>,
+        input: &str,
+        segment: &Segment,
+        output: &str,
+    ) -> Result<(), Diagnostic> {
+        self.ffmpeg(step, backend::segment_args(input, segment, output))
+    }
+
+    fn mix(&self, step: &StepContext<'_>, inputs: &[&str], output: &str) -> Result<(), Diagnostic> {
+        self.ffmpeg(step, backend::mix_args(inputs, output))
+    }
+
+    fn concat(
+        &self,
+        step: &StepContext<'_>,
+        inputs: &[&str],
+        output: &str,
+        metadata: &[(&str, &str)],
+    ) -> Result<(), Diagnostic> {
+        let list = backend::concat_list_path(step.scratch_dir);
+        self.push(ScriptStep::WriteFile {
+            path: list.clone(),
+            contents: backend::concat_list(step.scratch_dir, inputs),
+        })?;
+        self.ffmpeg(step, backend::concat_args(&list, output, metadata))
+    }
+
+    fn transcode(
+        &self,
+        step: &StepContext<'_>,
+        input: &str,
+        codec: Codec,
+        output: &str,
+        metadata: &[(&str, &str)],
+    ) -> Result<(), Diagnostic> {
+        self.ffmpeg(
+            step,
+            backend::transcode_args(input, codec, output, metadata),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(output: &str) -> RenderScript {
+        let spec = LoopSpec::builder("my song.wav", output)
+            .start(10.0)
+            .end(20.0)
+            .build()
+            .unwrap();
+        RenderScript::new(&spec, "ffmpeg").unwrap()
+    }
+
+    #[test]
+    fn quotes_posix_arguments() {
+        assert_eq!(quote(Shell::Posix, "my song.wav"), "'my song.wav'");
+        assert_eq!(quote(Shell::Posix, "it's"), r"'it'\''s'");
+        assert_eq!(
+            quote(Shell::Posix, "$HOME `ls` \"x\""),
+            "'$HOME `ls` \"x\"'"
+        );
+    }
+
+    #[test]
+    fn doubles_every_kind_of_single_quote_for_powershell() {
+        assert_eq!(quote(Shell::PowerShell, "it's"), "'it''s'");
+        assert_eq!(
+            quote(Shell::PowerShell, "\u{2018}a\u{2019} \u{201A}b\u{201B}"),
+            "'\u{2018}\u{2018}a\u{2019}\u{2019} \u{201A}\u{201A}b\u{201B}\u{201B}'"
+        );
+        assert_eq!(quote(Shell::PowerShell, "$env:TEMP"), "'$env:TEMP'");
+    }
+
+    #[test]
+    fn escapes_windows_arguments() {
+        assert_eq!(windows_arg("plain"), "plain");
+        assert_eq!(windows_arg(r"C:\music\song.wav"), r"C:\music\song.wav");
+        assert_eq!(windows_arg(r#"say "hi""#), r#"say \"hi\""#);
+        // Backslashes before a quote are doubled, and the quote gets one of its own
+        assert_eq!(windows_arg(r#"a\"b"#), r#"a\\\"b"#);
+        assert_eq!(windows_arg(r#"a\\"b"#), r#"a\\\\\"b"#);
+        // Trailing backslashes only need doubling when the argument ends up in quotes
+        assert_eq!(windows_arg(r"C:\dir\"), r"C:\dir\");
+        assert_eq!(windows_arg(r"C:\my dir\"), r"C:\my dir\\");
+        assert_eq!(windows_arg(r"C:\my dir\\"), r"C:\my dir\\\\");
+    }
+
+    #[test]
+    fn writes_a_posix_script() {
+        assert_eq!(
+            script("out.wav").to_script(Shell::Posix),
+            r#"#!/bin/sh
+# Renders an EchoBlend loop
+set -e
+mkdir -p 'out_echoblend_work'
+
+# Rendering intro...
+'ffmpeg' '-y' '-i' 'my song.wav' '-t' '20' 'out_echoblend_work/intro.wav'
+
+# Rendering loop segment...
+'ffmpeg' '-y' '-i' 'my song.wav' '-ss' '10' '-t' '10' 'out_echoblend_work/loop.wav'
+
+# Rendering outro...
+'ffmpeg' '-y' '-i' 'my song.wav' '-ss' '10' 'out_echoblend_work/outro.wav'
+
+# Writing the list of segments to join
+printf '%s\n' 'file '\''intro.wav'\''' 'file '\''loop.wav'\''' 'file '\''outro.wav'\''' > 'out_echoblend_work/concat_list.txt'
+
+# Merging segments...
+'ffmpeg' '-y' '-f' 'concat' '-safe' '0' '-i' 'out_echoblend_work/concat_list.txt' '-c' 'copy' 'out.wav'
+
+rm -rf 'out_echoblend_work'
+"#
+        );
+    }
+
+    #[test]
+    fn writes_a_powershell_script() {
+        assert_eq!(
+            script("out.wav").to_script(Shell::PowerShell),
+            r#"# Renders an EchoBlend loop
+$ErrorActionPreference = 'Stop'
+$PSNativeCommandArgumentPassing = 'Legacy'
+[System.Environment]::CurrentDirectory = (Get-Location).ProviderPath
+[System.IO.Directory]::CreateDirectory('out_echoblend_work') | Out-Null
+
+# Rendering intro...
+& 'ffmpeg' '-y' '-i' 'my song.wav' '-t' '20' 'out_echoblend_work/intro.wav'
+if ($LASTEXITCODE -ne 0) { exit $LASTEXITCODE }
+
+# Rendering loop segment...
+& 'ffmpeg' '-y' '-i' 'my song.wav' '-ss' '10' '-t' '10' 'out_echoblend_work/loop.wav'
+if ($LASTEXITCODE -ne 0) { exit $LASTEXITCODE }
+
+# Rendering outro...
+& 'ffmpeg' '-y' '-i' 'my song.wav' '-ss' '10' 'out_echoblend_work/outro.wav'
+if ($LASTEXITCODE -ne 0) { exit $LASTEXITCODE }
+
+# Writing the list of segments to join
+[System.IO.File]::WriteAllText('out_echoblend_work/concat_list.txt', (('file ''intro.wav''', 'file ''loop.wav''', 'file ''outro.wav''') -join "`n") + "`n")
+
+# Merging segments...
+& 'ffmpeg' '-y' '-f' 'concat' '-safe' '0' '-i' 'out_echoblend_work/concat_list.txt' '-c' 'copy' 'out.wav'
+if ($LASTEXITCODE -ne 0) { exit $LASTEXITCODE }
+
+Remove-Item -LiteralPath 'out_echoblend_work' -Recurse -Force
+"#
+        );
+    }
+
+    // Runs the script for a relative output with an ffmpeg that reads the concat list as ffmpeg does,
+    // relative to the list's own folder
+    #[cfg(unix)]
+    #[test]
+    fn a_relative_output_joins_the_segments_it_rendered() {
+        let dir = std::env::temp_dir().join(format!("echo_blend_script_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let ffmpeg = dir.join("ffmpeg");
+        std::fs::write(
+            &ffmpeg,
+            r#"#!/bin/sh
+for last; do :; done
+if [ "$3" = concat ]; then
+    list="$7"
+    folder="$(dirname "$list")"
+    sed "s/^file '\(.*\)'$/\1/" "$list" | while read -r entry; do
+        [ -f "$folder/$entry" ] || { echo "missing $folder/$entry" >&2; exit 1; }
+    done || exit 1
+fi
+touch "$last"
+"#,
+        )
+        .unwrap();
+        std::process::Command::new("chmod")
+            .arg("+x")
+            .arg(&ffmpeg)
+            .status()
+            .unwrap();
+        let spec = LoopSpec::builder("my song.wav", "out.wav")
+            .start(10.0)
+            .end(20.0)
+            .build()
+            .unwrap();
+        let script = RenderScript::new(&spec, &ffmpeg.display().to_string()).unwrap();
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(script.to_script(Shell::Posix))
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(dir.join("out.wav").exists());
+        assert!(!dir.join("out_echoblend_work").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
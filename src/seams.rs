@@ -0,0 +1,525 @@
+use std::io::Read;
+
+// Number of frames on each side of a seam that are kept for analysis
+const WINDOW_FRAMES: usize = 2048;
+// Number of frames on each side of a seam used for the spectral comparison (must be a power of 2)
+const SPECTRUM_FRAMES: usize = 1024;
+const CLIP_LEVEL: f32 = 0.999;
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Rating {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Rating {
+    fn points(self) -> f32 {
+        match self {
+            Rating::Pass => 100.0,
+            Rating::Warn => 60.0,
+            Rating::Fail => 0.0,
+        }
+    }
+}
+
+impl std::fmt::Display for Rating {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rating::Pass => write!(f, "pass"),
+            Rating::Warn => write!(f, "warn"),
+            Rating::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Seam {
+    pub index: usize,
+    pub time_s: f32,
+    /// Largest sample jump across the join
+    pub discontinuity: f32,
+    /// Sample jump across the join relative to the average jump around it
+    pub discontinuity_ratio: f32,
+    pub rms_change_db: f32,
+    /// 0 means identical spectra on both sides of the join, 1 means nothing in common
+    pub spectral_difference: f32,
+    pub clipped_samples: usize,
+    pub rating: Rating,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct SeamReport {
+    pub file: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub total_clipped_samples: usize,
+    pub score: u8,
+    pub seams: Vec<Seam>,
+}
+
+impl SeamReport {
+    pub fn count(&self, rating: Rating) -> usize {
+        self.seams.iter().filter(|s| s.rating == rating).count()
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "Seam quality: {}/100 ({} passed, {} warnings, {} failed)",
+            self.score,
+            self.count(Rating::Pass),
+            self.count(Rating::Warn),
+            self.count(Rating::Fail)
+        )
+    }
+
+    pub fn write_json(&self, path: &std::path::Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| e.to_string())
+    }
+}
+
+/// Path of the JSON report written next to the given output file
+pub fn report_path(output_path: &str) -> std::path::PathBuf {
+    std::path::Path::new(output_path).with_extension("seams.json")
+}
+
+/// Analyses the audio around each of the given seam times in a 16-bit or 32-bit float WAV stream of `output_path`.
+pub fn analyse_seams(
+    mut wav: impl Read,
+    output_path: &str,
+    seam_times: &[f32],
+) -> Result<SeamReport, String> {
+    let format = read_wav_header(&mut wav)?;
+    let (sample_rate, channels) = (format.sample_rate, format.channels);
+    let seam_frames: Vec<usize> = seam_times
+        .iter()
+        .map(|t| (t * sample_rate as f32).round() as usize)
+        .collect();
+
+    // Interleaved samples around each seam, starting WINDOW_FRAMES before it
+    let mut windows: Vec<Vec<f32>> = vec![Vec::new(); seam_frames.len()];
+    let mut total_clipped_samples = 0;
+    let mut frame = 0usize;
+    let mut first_open = 0usize;
+    let mut sample_in_frame = 0usize;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut leftover: Vec<u8> = Vec::new();
+    loop {
+        let read = wav.read(&mut buf).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        leftover.extend_from_slice(&buf[..read]);
+        let usable = leftover.len() - leftover.len() % format.sample.bytes();
+        for bytes in leftover[..usable].chunks_exact(format.sample.bytes()) {
+            let sample = format.sample.decode(bytes);
+            if sample.abs() >= CLIP_LEVEL {
+                total_clipped_samples += 1;
+            }
+            // Seams are in order, so only the ones whose window has not passed yet are checked
+            while first_open < seam_frames.len() && seam_frames[first_open] + WINDOW_FRAMES <= frame
+            {
+                first_open += 1;
+            }
+            for (window, &seam) in windows.iter_mut().zip(&seam_frames).skip(first_open) {
+                if seam > frame + WINDOW_FRAMES {
+                    break;
+                }
+                window.push(sample);
+            }
+            sample_in_frame += 1;
+            if sample_in_frame == channels as usize {
+                sample_in_frame = 0;
+                frame += 1;
+            }
+        }
+        leftover.drain(..usable);
+    }
+
+    let seams: Vec<Seam> = windows
+        .iter()
+        .zip(&seam_frames)
+        .zip(seam_times)
+        .enumerate()
+        .map(|(index, ((window, &seam), &time_s))| {
+            // Seams close to the start of the file have a shorter lead-in
+            let before = seam.min(WINDOW_FRAMES);
+            analyse_window(index, time_s, window, channels as usize, before)
+        })
+        .collect();
+
+    let score = if seams.is_empty() {
+        100
+    } else {
+        (seams.iter().map(|s| s.rating.points()).sum::<f32>() / seams.len() as f32).round() as u8
+    };
+
+    Ok(SeamReport {
+        file: output_path.to_string(),
+        sample_rate,
+        channels,
+        total_clipped_samples,
+        score,
+        seams,
+    })
+}
+
+fn analyse_window(
+    index: usize,
+    time_s: f32,
+    window: &[f32],
+    channels: usize,
+    before: usize,
+) -> Seam {
+    let frames = window.len() / channels;
+    let mono: Vec<f32> = window
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    let (pre, post) = mono.split_at(before.min(frames));
+
+    // Sample discontinuity, compared against how much the signal usually moves between samples
+    let mut discontinuity = 0.0f32;
+    if before > 0 && before < frames {
+        for c in 0..channels {
+            let last = window[(before - 1) * channels + c];
+            let first = window[before * channels + c];
+            discontinuity = discontinuity.max((first - last).abs());
+        }
+    }
+    let average_step = mono.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f32>()
+        / mono.len().saturating_sub(1).max(1) as f32;
+    let discontinuity_ratio = discontinuity / average_step.max(1e-6);
+
+    let rms_change_db = 20.0 * (rms(post).max(1e-5) / rms(pre).max(1e-5)).log10();
+
+    let spectral_difference = {
+        let a = magnitude_spectrum(&pre[pre.len().saturating_sub(SPECTRUM_FRAMES)..]);
+        let b = magnitude_spectrum(&post[..post.len().min(SPECTRUM_FRAMES)]);
+        let diff: f32 = a.iter().zip(&b).map(|(x, y)| (x - y).abs()).sum();
+        let total: f32 = a.iter().zip(&b).map(|(x, y)| x + y).sum();
+        if total > 0.0 {
+            diff / total
+        } else {
+            0.0
+        }
+    };
+
+    let clipped_samples = window.iter().filter(|s| s.abs() >= CLIP_LEVEL).count();
+
+    let fail = (discontinuity > 0.1 && discontinuity_ratio > 8.0)
+        || rms_change_db.abs() > 6.0
+        || spectral_difference > 0.6
+        || clipped_samples > 8;
+    let warn = (discontinuity > 0.03 && discontinuity_ratio > 4.0)
+        || rms_change_db.abs() > 3.0
+        || spectral_difference > 0.4
+        || clipped_samples > 0;
+    let rating = if fail {
+        Rating::Fail
+    } else if warn {
+        Rating::Warn
+    } else {
+        Rating::Pass
+    };
+
+    Seam {
+        index,
+        time_s,
+        discontinuity,
+        discontinuity_ratio,
+        rms_change_db,
+        spectral_difference,
+        clipped_samples,
+        rating,
+    }
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+// Hann windowed magnitude spectrum, zero padded up to SPECTRUM_FRAMES
+fn magnitude_spectrum(samples: &[f32]) -> Vec<f32> {
+    let n = SPECTRUM_FRAMES;
+    let mut re: Vec<f32> = (0..n)
+        .map(|i| {
+            let hann = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / n as f32).cos();
+            samples.get(i).copied().unwrap_or(0.0) * hann
+        })
+        .collect();
+    let mut im = vec![0.0f32; n];
+    fft(&mut re, &mut im);
+    re.iter()
+        .zip(&im)
+        .take(n / 2)
+        .map(|(r, i)| (r * r + i * i).sqrt())
+        .collect()
+}
+
+// In-place iterative radix-2 FFT
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f32::consts::PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// How the samples of a WAV stream are stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SampleFormat {
+    Pcm16,
+    Float32,
+}
+
+impl SampleFormat {
+    fn bytes(self) -> usize {
+        match self {
+            SampleFormat::Pcm16 => 2,
+            SampleFormat::Float32 => 4,
+        }
+    }
+
+    // Decodes one little-endian sample of `self.bytes()` bytes to the range -1 to 1
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::Pcm16 => f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32768.0,
+            SampleFormat::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct WavFormat {
+    sample_rate: u32,
+    channels: u16,
+    sample: SampleFormat,
+}
+
+// Format tags of the fmt chunk, the extensible one keeping the real tag in its sub-format
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// Reads the header of a WAV stream up to the start of the sample data
+fn read_wav_header(reader: &mut impl Read) -> Result<WavFormat, String> {
+    let mut riff = [0u8; 12];
+    reader.read_exact(&mut riff).map_err(|e| e.to_string())?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err("Decoded audio is not a WAV stream".to_string());
+    }
+
+    let mut format = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        reader.read_exact(&mut chunk).map_err(|e| e.to_string())?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        match &chunk[0..4] {
+            b"data" => break,
+            b"fmt " => {
+                // Chunks are padded to an even size
+                let mut fmt = vec![0u8; size as usize + size as usize % 2];
+                reader.read_exact(&mut fmt).map_err(|e| e.to_string())?;
+                if fmt.len() < 16 {
+                    return Err("Malformed WAV format chunk".to_string());
+                }
+                let mut tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                if tag == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 26 {
+                    tag = u16::from_le_bytes([fmt[24], fmt[25]]);
+                }
+                let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+                let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                let sample = match (tag, bits) {
+                    (WAVE_FORMAT_PCM, 16) => SampleFormat::Pcm16,
+                    (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleFormat::Float32,
+                    _ => {
+                        return Err(format!(
+                            "Decoded audio has an unsupported sample format ({} bits, format {})",
+                            bits, tag
+                        ))
+                    }
+                };
+                format = Some(WavFormat {
+                    sample_rate,
+                    channels,
+                    sample,
+                });
+            }
+            _ => {
+                let skip = size as u64 + size as u64 % 2;
+                std::io::copy(&mut reader.take(skip), &mut std::io::sink())
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+
+    match format {
+        Some(format) if format.sample_rate > 0 && format.channels > 0 => Ok(format),
+        _ => Err("Decoded audio has no format information".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 44100;
+
+    // A mono WAV stream of `samples`, with an odd sized chunk before the format to skip
+    fn wav(samples: &[f32], sample: SampleFormat) -> Vec<u8> {
+        let (tag, bits) = match sample {
+            SampleFormat::Pcm16 => (WAVE_FORMAT_PCM, 16u16),
+            SampleFormat::Float32 => (WAVE_FORMAT_IEEE_FLOAT, 32u16),
+        };
+        let block_align = bits / 8;
+        let mut data = Vec::new();
+        for &s in samples {
+            match sample {
+                SampleFormat::Pcm16 => {
+                    data.extend_from_slice(&((s * 32767.0).round() as i16).to_le_bytes())
+                }
+                SampleFormat::Float32 => data.extend_from_slice(&s.to_le_bytes()),
+            }
+        }
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend_from_slice(b"LIST");
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(b"abc\0");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&tag.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&RATE.to_le_bytes());
+        bytes.extend_from_slice(&(RATE * u32::from(block_align)).to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&bits.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&data);
+        bytes
+    }
+
+    fn sine(frequency: f32, amplitude: f32, frames: std::ops::Range<usize>) -> Vec<f32> {
+        frames
+            .map(|i| {
+                amplitude * (2.0 * std::f32::consts::PI * frequency * i as f32 / RATE as f32).sin()
+            })
+            .collect()
+    }
+
+    fn rate_seam(samples: &[f32]) -> Seam {
+        let report =
+            analyse_seams(&wav(samples, SampleFormat::Float32)[..], "loop.wav", &[0.5]).unwrap();
+        assert_eq!(report.seams.len(), 1);
+        report.seams.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn reads_16_bit_and_float_headers() {
+        for sample in [SampleFormat::Pcm16, SampleFormat::Float32] {
+            let bytes = wav(&[0.5, -0.25], sample);
+            let mut reader = &bytes[..];
+            let format = read_wav_header(&mut reader).unwrap();
+            assert_eq!(
+                format,
+                WavFormat {
+                    sample_rate: RATE,
+                    channels: 1,
+                    sample
+                }
+            );
+            // The reader is left at the first sample
+            let decoded: Vec<f32> = reader
+                .chunks_exact(sample.bytes())
+                .map(|bytes| sample.decode(bytes))
+                .collect();
+            assert!((decoded[0] - 0.5).abs() < 1e-3);
+            assert!((decoded[1] + 0.25).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn rejects_other_streams() {
+        assert!(read_wav_header(&mut &b"RIFF\0\0\0\0AVI LIST"[..]).is_err());
+        let mut bytes = wav(&[0.0], SampleFormat::Pcm16);
+        // 24-bit PCM
+        let fmt = bytes.windows(4).position(|w| w == b"fmt ").unwrap() + 8;
+        bytes[fmt + 14] = 24;
+        assert!(read_wav_header(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn fft_finds_a_tone_in_its_bin() {
+        // A tone exactly on bin 32 of the spectrum
+        let frequency = 32.0 * RATE as f32 / SPECTRUM_FRAMES as f32;
+        let spectrum = magnitude_spectrum(&sine(frequency, 1.0, 0..SPECTRUM_FRAMES));
+        let peak = spectrum
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(bin, _)| bin);
+        assert_eq!(peak, Some(32));
+    }
+
+    #[test]
+    fn seamless_sine_passes() {
+        let seam = rate_seam(&sine(440.0, 0.5, 0..RATE as usize));
+        assert_eq!(seam.rating, Rating::Pass, "{:?}", seam);
+        assert!(seam.rms_change_db.abs() < 0.5);
+        assert!(seam.spectral_difference < 0.1);
+    }
+
+    #[test]
+    fn hard_discontinuity_fails() {
+        let half = RATE as usize / 2;
+        let mut samples = sine(440.0, 0.05, 0..half);
+        samples.extend(std::iter::repeat(0.9).take(half));
+        let seam = rate_seam(&samples);
+        assert_eq!(seam.rating, Rating::Fail, "{:?}", seam);
+        assert!(seam.discontinuity > 0.8);
+        assert!(seam.rms_change_db > 6.0);
+    }
+
+    #[test]
+    fn a_clipped_sample_warns() {
+        let mut samples = sine(440.0, 0.5, 0..RATE as usize);
+        // Inside the analysed window, but before the part whose spectrum is compared
+        samples[RATE as usize / 2 - 1500] = 1.0;
+        let seam = rate_seam(&samples);
+        assert_eq!(seam.clipped_samples, 1);
+        assert_eq!(seam.rating, Rating::Warn, "{:?}", seam);
+    }
+}
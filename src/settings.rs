@@ -0,0 +1,184 @@
+use std::path::Path;
+
+use crate::{
+    looper::{CrossfadeCurve, CrossfadePlacement},
+    naming::{self, OverwritePolicy},
+    render::{OutputFormat, DEFAULT_MP3_QUALITY},
+    timecode::Unit,
+};
+
+// Bumped whenever older versions would misread a settings file
+const FORMAT_VERSION: u32 = 1;
+
+/// Whether the window is dark, light or follows the system
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Theme {
+    #[default]
+    System,
+    Dark,
+    Light,
+}
+
+/// How much of a job's output the console shows until its filters are changed
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConsoleVerbosity {
+    /// EchoBlend's progress, results and errors, without ffmpeg's output
+    Quiet,
+    /// Everything, with runs of ffmpeg banner and progress lines collapsed
+    #[default]
+    Normal,
+    /// Every line as it was printed
+    Verbose,
+}
+
+/// Preferences that can be exported to a JSON file and imported on another machine.
+///
+/// Missing fields take their defaults, so files from older versions still import.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    version: u32,
+    /// Folder the save dialog opens in when the loop has no earlier output, the working directory if empty
+    pub output_dir: String,
+    /// The format the save dialog suggests for a new loop
+    pub format: OutputFormat,
+    /// VBR quality of MP3 loops, from 0, the best, to 9, the smallest
+    pub mp3_quality: u8,
+    /// Output name suggested in the save dialog, see [`naming::TOKENS`]
+    pub name_template: String,
+    pub overwrite: OverwritePolicy,
+    /// Folder job workspaces are made in, the system temp folder if empty
+    pub scratch_dir: String,
+    pub keep_intermediates: bool,
+    /// Crossfade EchoBlend starts with, in seconds
+    pub crossfade_s: f64,
+    pub placement: CrossfadePlacement,
+    pub curve: CrossfadeCurve,
+    pub start_unit: Unit,
+    pub end_unit: Unit,
+    pub crossfade_unit: Unit,
+    pub theme: Theme,
+    pub console: ConsoleVerbosity,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            output_dir: String::new(),
+            format: OutputFormat::default(),
+            mp3_quality: DEFAULT_MP3_QUALITY,
+            name_template: naming::DEFAULT_TEMPLATE.to_string(),
+            overwrite: OverwritePolicy::default(),
+            scratch_dir: String::new(),
+            keep_intermediates: false,
+            crossfade_s: 0.0,
+            placement: CrossfadePlacement::default(),
+            curve: CrossfadeCurve::default(),
+            start_unit: Unit::default(),
+            end_unit: Unit::default(),
+            crossfade_unit: Unit::default(),
+            theme: Theme::default(),
+            console: ConsoleVerbosity::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read settings {}: {}", path.display(), e))?;
+        let settings: Settings = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid settings {}: {}", path.display(), e))?;
+        if settings.version > FORMAT_VERSION {
+            return Err(format!(
+                "The settings {} were exported by a newer version of EchoBlend, update to import them",
+                path.display()
+            ));
+        }
+        if settings.mp3_quality > 9
+            || !settings.crossfade_s.is_finite()
+            || settings.crossfade_s < 0.0
+        {
+            return Err(format!(
+                "Invalid settings {}: the MP3 quality or crossfade is out of range",
+                path.display()
+            ));
+        }
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut settings = self.clone();
+        settings.version = FORMAT_VERSION;
+        let json = serde_json::to_string_pretty(&settings).map_err(|e| e.to_string())?;
+        std::fs::write(path, json)
+            .map_err(|e| format!("Failed to save settings {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "echo_blend_settings_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Writes `json` as a settings file and loads it back
+    fn load(name: &str, json: &str) -> Result<Settings, String> {
+        let path = temp_dir(name).join("settings.json");
+        std::fs::write(&path, json).unwrap();
+        Settings::load(&path)
+    }
+
+    #[test]
+    fn loads_what_it_saved() {
+        let path = temp_dir("saved").join("settings.json");
+        let settings = Settings {
+            output_dir: "loops".to_string(),
+            format: OutputFormat::Mp3,
+            mp3_quality: 5,
+            overwrite: OverwritePolicy::AutoNumber,
+            crossfade_s: 1.5,
+            placement: CrossfadePlacement::Centred,
+            theme: Theme::Dark,
+            console: ConsoleVerbosity::Verbose,
+            ..Settings::default()
+        };
+        settings.save(&path).unwrap();
+        assert!(Settings::load(&path).unwrap() == settings);
+    }
+
+    #[test]
+    fn missing_fields_take_their_defaults() {
+        let settings = load("older", r#"{ "version": 1, "mp3_quality": 4 }"#).unwrap();
+        assert_eq!(settings.mp3_quality, 4);
+        assert_eq!(settings.name_template, naming::DEFAULT_TEMPLATE);
+    }
+
+    #[test]
+    fn rejects_settings_from_a_newer_version() {
+        let error = load("newer", r#"{ "version": 2 }"#).err().unwrap();
+        assert!(error.contains("newer version"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_an_mp3_quality_out_of_range() {
+        assert!(load("quality", r#"{ "version": 1, "mp3_quality": 10 }"#).is_err());
+    }
+
+    #[test]
+    fn rejects_a_crossfade_out_of_range() {
+        assert!(load("negative", r#"{ "version": 1, "crossfade_s": -1.0 }"#).is_err());
+        assert!(load("infinite", r#"{ "version": 1, "crossfade_s": 1e999 }"#).is_err());
+    }
+}
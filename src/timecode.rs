@@ -0,0 +1,162 @@
+#[cfg(feature = "gui")]
+/// The unit a time is shown in
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, Default)]
+pub enum Unit {
+    #[default]
+    Milliseconds,
+    Seconds,
+}
+
+#[cfg(feature = "gui")]
+/// Text state of a time entry field
+#[derive(Default)]
+pub struct TimeInput {
+    pub text: String,
+    pub error: Option<String>,
+}
+
+/// Parses a time into seconds.
+///
+/// Accepted forms are `1:23.456`, `01:02:03.5`, plain seconds (`83.456` or `83.456s`),
+/// milliseconds (`83456ms`) and sample positions (`s:12345`), which need the source sample rate.
+pub fn parse(input: &str, sample_rate: Option<u32>) -> Result<f64, String> {
+    let input = input.trim().to_lowercase();
+    if input.is_empty() {
+        return Err("Enter a time such as 1:23.456".to_string());
+    }
+
+    if let Some(samples) = input.strip_prefix("s:") {
+        let samples: u64 = samples
+            .trim()
+            .parse()
+            .map_err(|_| format!("\"{}\" is not a whole number of samples", samples.trim()))?;
+        return match sample_rate {
+            Some(rate) if rate > 0 => Ok(samples as f64 / rate as f64),
+            _ => Err("Sample positions need the sample rate of a loaded file".to_string()),
+        };
+    }
+
+    if let Some(ms) = input.strip_suffix("ms") {
+        return Ok(parse_number(ms)? / 1000.0);
+    }
+
+    if input.contains(':') {
+        let parts: Vec<&str> = input.split(':').collect();
+        if parts.len() > 3 {
+            return Err("Use at most hours:minutes:seconds".to_string());
+        }
+        let mut seconds = 0.0;
+        for (i, part) in parts.iter().enumerate() {
+            let is_last = i == parts.len() - 1;
+            let value = if is_last {
+                parse_number(part)?
+            } else {
+                part.trim()
+                    .parse::<u32>()
+                    .map_err(|_| format!("\"{}\" is not a whole number", part.trim()))?
+                    as f64
+            };
+            // Only the leading component may exceed 59
+            if i > 0 && value >= 60.0 {
+                return Err(format!("\"{}\" must be less than 60", part.trim()));
+            }
+            seconds = seconds * 60.0 + value;
+        }
+        return Ok(seconds);
+    }
+
+    parse_number(input.strip_suffix('s').unwrap_or(&input))
+}
+
+#[cfg(feature = "gui")]
+/// Formats seconds for display, as `83456ms` or as a `1:23.456` timecode depending on the unit.
+pub fn format(seconds: f64, unit: Unit) -> String {
+    let ms = (seconds * 1000.0).round().max(0.0) as u64;
+    match unit {
+        Unit::Milliseconds => format!("{}ms", ms),
+        Unit::Seconds => {
+            let (hours, minutes, secs, millis) =
+                (ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000);
+            if hours > 0 {
+                format!("{}:{:02}:{:02}.{:03}", hours, minutes, secs, millis)
+            } else {
+                format!("{}:{:02}.{:03}", minutes, secs, millis)
+            }
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Result<f64, String> {
+    let text = text.trim();
+    match text.parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => Ok(value),
+        Ok(_) => Err(format!("\"{}\" must be a positive time", text)),
+        Err(_) => Err(format!("\"{}\" is not a number", text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_parses(input: &str, sample_rate: Option<u32>, expected: f64) {
+        match parse(input, sample_rate) {
+            Ok(seconds) => assert!(
+                (seconds - expected).abs() < 1e-9,
+                "{} parsed as {}, not {}",
+                input,
+                seconds,
+                expected
+            ),
+            Err(e) => panic!("{} did not parse: {}", input, e),
+        }
+    }
+
+    #[test]
+    fn parses_timecodes() {
+        assert_parses("1:23.456", None, 83.456);
+        assert_parses("01:02:03.5", None, 3723.5);
+        assert_parses("90:00", None, 5400.0);
+        assert_parses(" 0:05 ", None, 5.0);
+    }
+
+    #[test]
+    fn parses_seconds_and_milliseconds() {
+        assert_parses("83.456", None, 83.456);
+        assert_parses("83.456s", None, 83.456);
+        assert_parses("12345ms", None, 12.345);
+        assert_parses("12345MS", None, 12.345);
+    }
+
+    #[test]
+    fn parses_sample_positions_with_a_sample_rate() {
+        assert_parses("s:44100", Some(44100), 1.0);
+        assert_parses("s:12345", Some(48000), 12345.0 / 48000.0);
+        assert!(parse("s:12345", None).is_err());
+        assert!(parse("s:12345", Some(0)).is_err());
+        assert!(parse("s:1.5", Some(44100)).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        for input in [
+            "", "abc", "-1", "1:60", "1:2:3:4", "1.5:00", "inf", "NaN", "12 ms x", "1:-5",
+        ] {
+            assert!(parse(input, Some(44100)).is_err(), "{} parsed", input);
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn formats_and_parses_back() {
+        assert_eq!(format(83.456, Unit::Seconds), "1:23.456");
+        assert_eq!(format(3723.5, Unit::Seconds), "1:02:03.500");
+        assert_eq!(format(83.456, Unit::Milliseconds), "83456ms");
+        for seconds in [0.0, 0.001, 59.999, 83.456, 3599.999, 3723.5, 86400.0] {
+            for unit in [Unit::Milliseconds, Unit::Seconds] {
+                let text = format(seconds, unit);
+                assert_parses(&text, None, seconds);
+            }
+        }
+    }
+}
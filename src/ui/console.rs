@@ -1,13 +1,30 @@
-use egui::{Context, Ui};
+use egui::{text::LayoutJob, Color32, Context, FontId, TextFormat, Ui};
 
-use crate::{app::ConsoleText, App};
+use crate::{
+    app::{ConsoleText, ConsoleView},
+    App,
+};
+
+// Lines ffmpeg repeats on every run, which are hidden behind one line each when collapsing
+#[derive(PartialEq, Clone, Copy)]
+enum Noise {
+    Banner,
+    Progress,
+}
+
+// A row of the console, either a line or a collapsed run of noise lines
+enum Row {
+    Line(usize),
+    Collapsed { noise: Noise, lines: Vec<usize> },
+}
 
 pub fn create_console_view(app: &mut App, ctx: &Context, ui: &mut Ui, new_line: bool) {
     egui::CollapsingHeader::new("Console")
         .default_open(true)
         .show(ui, |ui| {
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                ui.style_mut().visuals.window_fill = egui::Color32::from_rgb(0, 0, 0);
+            let mut copy = false;
+            let mut save = false;
+            ui.horizontal(|ui| {
                 if ui
                     .add(egui::Button::new("Clear Console"))
                     .on_hover_text("Clear the console.")
@@ -15,10 +32,107 @@ pub fn create_console_view(app: &mut App, ctx: &Context, ui: &mut Ui, new_line:
                 {
                     app.clear_console();
                 }
-                ui.separator();
-                let is_dark_mode = ctx.style().visuals.dark_mode;
-                for line in app.iter_console() {
-                    line_to_text(ui, is_dark_mode, line);
+                save = ui
+                    .button("Save Log")
+                    .on_hover_text("Save every line of the console to a text file.")
+                    .clicked();
+                let (_, view) = app.console_mut();
+                copy = ui
+                    .add_enabled(!view.selected.is_empty(), egui::Button::new("Copy Selected"))
+                    .on_hover_text("Copy the selected lines. Click a line to select it, Ctrl+click to add to the selection and Shift+click to select a range.")
+                    .clicked();
+            });
+            if copy {
+                app.copy_console_selection(ctx);
+            }
+            if save {
+                app.save_console();
+            }
+
+            let is_dark_mode = ctx.style().visuals.dark_mode;
+            let (lines, view) = app.console_mut();
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut view.show_program, "EchoBlend")
+                    .on_hover_text("Show EchoBlend's own progress.");
+                ui.checkbox(&mut view.show_success, "Results")
+                    .on_hover_text("Show what a finished loop wrote.");
+                ui.checkbox(&mut view.show_stdout, "Output")
+                    .on_hover_text("Show ffmpeg's output and warnings.");
+                ui.checkbox(&mut view.show_stderr, "Errors")
+                    .on_hover_text("Show errors.");
+                ui.checkbox(&mut view.collapse, "Collapse repeats")
+                    .on_hover_text("Show each run of ffmpeg banner and progress lines as a single line.");
+            });
+            ui.horizontal(|ui| {
+                ui.label("Search:");
+                ui.add(
+                    egui::TextEdit::singleline(&mut view.search)
+                        .hint_text("Show only lines containing...")
+                        .desired_width(250.0),
+                );
+                if !view.search.is_empty() && ui.small_button("x").clicked() {
+                    view.search.clear();
+                }
+            });
+
+            if !view.pinned.is_empty() {
+                egui::Frame::group(ui.style())
+                    .stroke(egui::Stroke::new(1.0, ui.visuals().error_fg_color))
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.strong("The last job failed:");
+                            if ui.small_button("Dismiss").clicked() {
+                                view.pinned.clear();
+                            }
+                        });
+                        for error in &view.pinned {
+                            ui.monospace(
+                                egui::RichText::new(error).color(ui.visuals().error_fg_color),
+                            );
+                        }
+                    });
+            }
+            ui.separator();
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.style_mut().visuals.window_fill = egui::Color32::from_rgb(0, 0, 0);
+                let mut clicked = None;
+                for row in rows(lines, view) {
+                    match row {
+                        Row::Line(i) => {
+                            let line = &lines[i];
+                            let job = highlighted(
+                                line.text(),
+                                &view.search,
+                                colour(line, is_dark_mode),
+                                ui,
+                            );
+                            if ui
+                                .add(egui::SelectableLabel::new(view.selected.contains(&i), job))
+                                .clicked()
+                            {
+                                clicked = Some(i);
+                            }
+                        }
+                        Row::Collapsed { noise, lines: run } => {
+                            let text = match noise {
+                                Noise::Banner => {
+                                    format!("[ffmpeg version and build details, {} lines]", run.len())
+                                }
+                                // The last progress line has the final size and time
+                                Noise::Progress => format!(
+                                    "{}  [{} earlier progress lines]",
+                                    lines[run[run.len() - 1]].text(),
+                                    run.len() - 1
+                                ),
+                            };
+                            ui.monospace(egui::RichText::new(text).weak())
+                                .on_hover_text("Untick Collapse repeats to show every line.");
+                        }
+                    }
+                }
+                if let Some(i) = clicked {
+                    select(view, i, ui.input(|input| input.modifiers));
                 }
                 if new_line {
                     ui.scroll_to_cursor(Some(egui::Align::BOTTOM));
@@ -27,18 +141,235 @@ pub fn create_console_view(app: &mut App, ctx: &Context, ui: &mut Ui, new_line:
         });
 }
 
-fn line_to_text(ui: &mut Ui, is_dark_mode: bool, line: &ConsoleText) {
+// The rows left after filtering, with runs of noise collapsed if asked to
+fn rows(lines: &[ConsoleText], view: &ConsoleView) -> Vec<Row> {
+    let search = view.search.to_lowercase();
+    let mut rows = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let shown = match line {
+            ConsoleText::Program(_) => view.show_program,
+            ConsoleText::Success(_) => view.show_success,
+            ConsoleText::Stdout(_) => view.show_stdout,
+            ConsoleText::Stderr(_) => view.show_stderr,
+        };
+        if !shown || !line.text().to_lowercase().contains(&search) {
+            continue;
+        }
+        let noise = match line {
+            ConsoleText::Stdout(text) if view.collapse => noise(text),
+            _ => None,
+        };
+        match (noise, rows.last_mut()) {
+            (Some(noise), Some(Row::Collapsed { noise: last, lines })) if *last == noise => {
+                lines.push(i)
+            }
+            (Some(noise), _) => rows.push(Row::Collapsed {
+                noise,
+                lines: vec![i],
+            }),
+            (None, _) => rows.push(Row::Line(i)),
+        }
+    }
+    // A lone noise line is shown as it is
+    rows.into_iter()
+        .map(|row| match row {
+            Row::Collapsed { lines, .. } if lines.len() == 1 => Row::Line(lines[0]),
+            row => row,
+        })
+        .collect()
+}
+
+fn noise(text: &str) -> Option<Noise> {
+    let trimmed = text.trim_start();
+    let is_library = ["libav", "libsw", "libpostproc"]
+        .iter()
+        .any(|library| trimmed.starts_with(library));
+    if trimmed.starts_with("ffmpeg version")
+        || trimmed.starts_with("built with")
+        || trimmed.starts_with("configuration:")
+        || is_library
+    {
+        Some(Noise::Banner)
+    } else if trimmed.starts_with("size=")
+        || (trimmed.contains("time=") && trimmed.contains("speed="))
+    {
+        Some(Noise::Progress)
+    } else {
+        None
+    }
+}
+
+// Click selects one line, Ctrl+click adds or removes a line and Shift+click selects a range
+fn select(view: &mut ConsoleView, i: usize, modifiers: egui::Modifiers) {
+    match view.anchor {
+        Some(anchor) if modifiers.shift => {
+            view.selected = (anchor.min(i)..=anchor.max(i)).collect();
+            return;
+        }
+        _ => {}
+    }
+    if modifiers.command {
+        if !view.selected.remove(&i) {
+            view.selected.insert(i);
+        }
+    } else if view.selected.len() == 1 && view.selected.contains(&i) {
+        view.selected.clear();
+    } else {
+        view.selected = std::iter::once(i).collect();
+    }
+    view.anchor = Some(i);
+}
+
+// The line with every match of the search highlighted
+fn highlighted(text: &str, search: &str, colour: Color32, ui: &Ui) -> LayoutJob {
+    let font_id = FontId::monospace(ui.style().text_styles[&egui::TextStyle::Monospace].size);
+    let plain = TextFormat::simple(font_id.clone(), colour);
+    let highlight = TextFormat {
+        background: ui.visuals().selection.bg_fill,
+        ..TextFormat::simple(font_id, ui.visuals().strong_text_color())
+    };
+    let mut job = LayoutJob::default();
+    let mut start = 0;
+    for found in matches(text, search) {
+        job.append(&text[start..found.start], 0.0, plain.clone());
+        job.append(&text[found.clone()], 0.0, highlight.clone());
+        start = found.end;
+    }
+    job.append(&text[start..], 0.0, plain);
+    job
+}
+
+// Where the search is found in the text, ignoring case
+fn matches(text: &str, search: &str) -> Vec<std::ops::Range<usize>> {
+    // Lowercasing can change byte lengths outside ASCII, where matches are left unhighlighted
+    let lower = text.to_lowercase();
+    if search.is_empty() || lower.len() != text.len() {
+        return Vec::new();
+    }
+    let search = search.to_lowercase();
+    lower
+        .match_indices(&search)
+        .map(|(found, _)| found..found + search.len())
+        .collect()
+}
+
+pub fn colour(line: &ConsoleText, is_dark_mode: bool) -> Color32 {
     let mode_to_colour = |dark: (u8, u8, u8), light: (u8, u8, u8)| {
         let (r, g, b) = if is_dark_mode { dark } else { light };
         egui::Color32::from_rgb(r, g, b)
     };
 
-    let (text, colour) = match line {
-        ConsoleText::Program(text) => (text, mode_to_colour((255, 255, 255), (0, 0, 0))),
-        ConsoleText::Success(text) => (text, mode_to_colour((100, 255, 100), (0, 100, 0))),
-        ConsoleText::Stdout(text) => (text, mode_to_colour((255, 255, 100), (100, 100, 0))),
-        ConsoleText::Stderr(text) => (text, mode_to_colour((255, 100, 100), (100, 0, 0))),
-    };
+    match line {
+        ConsoleText::Program(_) => mode_to_colour((255, 255, 255), (0, 0, 0)),
+        ConsoleText::Success(_) => mode_to_colour((100, 255, 100), (0, 100, 0)),
+        ConsoleText::Stdout(_) => mode_to_colour((255, 255, 100), (100, 100, 0)),
+        ConsoleText::Stderr(_) => mode_to_colour((255, 100, 100), (100, 0, 0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stdout(text: &str) -> ConsoleText {
+        ConsoleText::Stdout(text.to_string())
+    }
+
+    // Each row as the indices of the lines it shows
+    fn row_lines(lines: &[ConsoleText], view: &ConsoleView) -> Vec<Vec<usize>> {
+        rows(lines, view)
+            .into_iter()
+            .map(|row| match row {
+                Row::Line(i) => vec![i],
+                Row::Collapsed { lines, .. } => lines,
+            })
+            .collect()
+    }
+
+    fn run_output() -> Vec<ConsoleText> {
+        vec![
+            ConsoleText::Program("Rendering intro...".to_string()),
+            stdout("ffmpeg version 6.1.1 Copyright (c) 2000-2023 the FFmpeg developers"),
+            stdout("  built with gcc 13"),
+            stdout("  libavutil      58. 29.100 / 58. 29.100"),
+            stdout("Input #0, wav, from 'song.wav':"),
+            stdout("size=     512kB time=00:00:02.97 bitrate=1411.2kbits/s speed=5.9x"),
+            stdout("size=    1024kB time=00:00:05.94 bitrate=1411.2kbits/s speed=5.9x"),
+            stdout("size=    1536kB time=00:00:08.91 bitrate=1411.2kbits/s speed=6.0x"),
+            ConsoleText::Stderr("song.wav: Invalid data found when processing input".to_string()),
+        ]
+    }
+
+    #[test]
+    fn collapses_runs_of_banner_and_progress_lines() {
+        let lines = run_output();
+        let view = ConsoleView::default();
+        assert_eq!(
+            row_lines(&lines, &view),
+            vec![vec![0], vec![1, 2, 3], vec![4], vec![5, 6, 7], vec![8]]
+        );
+        assert!(matches!(
+            rows(&lines, &view)[3],
+            Row::Collapsed {
+                noise: Noise::Progress,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn shows_every_line_when_not_collapsing() {
+        let lines = run_output();
+        let view = ConsoleView {
+            collapse: false,
+            ..ConsoleView::default()
+        };
+        assert_eq!(row_lines(&lines, &view).len(), lines.len());
+    }
+
+    #[test]
+    fn a_lone_noise_line_is_not_collapsed() {
+        let lines = vec![
+            stdout("size=     512kB time=00:00:02.97 bitrate=1411.2kbits/s speed=5.9x"),
+            ConsoleText::Program("Rendering loop...".to_string()),
+        ];
+        assert!(matches!(
+            rows(&lines, &ConsoleView::default())[0],
+            Row::Line(0)
+        ));
+    }
+
+    #[test]
+    fn searches_ignoring_case() {
+        let lines = run_output();
+        let view = ConsoleView {
+            search: "INVALID data".to_string(),
+            ..ConsoleView::default()
+        };
+        assert_eq!(row_lines(&lines, &view), vec![vec![8]]);
+        assert_eq!(
+            matches("Invalid data, invalid DATA", "invalid data"),
+            vec![0..12, 14..26]
+        );
+        assert_eq!(matches("Rendering intro...", ""), Vec::new());
+        assert_eq!(matches("Rendering intro...", "outro"), Vec::new());
+    }
+
+    #[test]
+    fn the_errors_of_a_failed_job_stay_pinned() {
+        let mut lines = run_output();
+        for i in 0..10 {
+            lines.push(ConsoleText::Stderr(format!("error {}", i)));
+        }
+        let mut view = ConsoleView::default();
+        view.pin_errors(&lines);
+        let expected: Vec<String> = (2..10).map(|i| format!("error {}", i)).collect();
+        assert_eq!(view.pinned, expected);
 
-    ui.monospace(egui::RichText::new(text).color(colour));
+        // Hiding the errors or searching for something else leaves them pinned
+        view.show_stderr = false;
+        view.search = "intro".to_string();
+        assert_eq!(row_lines(&lines, &view), vec![vec![0]]);
+        assert_eq!(view.pinned, expected);
+    }
 }
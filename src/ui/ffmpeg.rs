@@ -1,24 +1,55 @@
 use egui::Ui;
 
-use crate::{ffmpeg, App};
+use crate::{provision, App};
 
 pub fn initial_ffmpeg_info(app: &mut App, ui: &mut Ui) {
     ui.horizontal(|ui| {
         ui.label(
             "This program requires FFMPEG.\nPlease provide the path to the FFMPEG executable.",
         );
-        if ui.button("Browse for ffmpeg.exe").clicked() {
+        if ui.button("Browse for ffmpeg").clicked() {
             app.ffmpeg_button_functionality();
-        } else if std::env::consts::OS == "windows" && ui.button("Download FFMPEG").clicked() {
-            app.set_ffmpeg_loading(true);
-            let (tx, rx) = std::sync::mpsc::channel();
-            ffmpeg::get_ffmpeg(tx);
-            app.set_ffmpeg_channel(rx);
+        } else if provision::is_supported_platform()
+            && ui
+                .add_enabled(
+                    !app.is_ffmpeg_loading(),
+                    egui::Button::new("Download FFMPEG"),
+                )
+                .on_hover_text("Download a verified static build of FFMPEG for this computer.")
+                .clicked()
+        {
+            app.download_ffmpeg();
         }
         if app.is_ffmpeg_loading() {
             ui.add(egui::widgets::Spinner::new());
         }
     });
+
+    if app.is_ffmpeg_loading() {
+        let (status, progress) = app.ffmpeg_download_status();
+        ui.label(status);
+        if let Some((downloaded, total)) = progress {
+            let mb = |bytes: u64| bytes as f32 / 1_048_576.0;
+            let bar = match total {
+                Some(total) if total > 0 => egui::ProgressBar::new(
+                    downloaded as f32 / total as f32,
+                )
+                .text(format!("{:.1} / {:.1} MB", mb(downloaded), mb(total))),
+                _ => egui::ProgressBar::new(0.0)
+                    .animate(true)
+                    .text(format!("{:.1} MB", mb(downloaded))),
+            };
+            ui.add(bar);
+        }
+    } else if provision::is_supported_platform() {
+        ui.collapsing("Download options", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Download source:");
+                ui.text_edit_singleline(app.ffmpeg_source_mut())
+                    .on_hover_text("Base URL to download the FFMPEG archive from instead of its official location.\nLeave empty to use the official location.");
+            });
+        });
+    }
 }
 
 pub fn ffmpeg_info(app: &mut App, ui: &mut Ui) {
@@ -27,5 +58,42 @@ pub fn ffmpeg_info(app: &mut App, ui: &mut Ui) {
             app.ffmpeg_button_functionality();
         }
         ui.label(format!("FFMPEG Path: {}", app.get_ffmpeg_path()));
+        if let Some(Ok(capabilities)) = app.get_ffmpeg_capabilities() {
+            ui.label(format!("(version {})", capabilities.version));
+        }
     });
+
+    let error_color = ui.visuals().error_fg_color;
+    let warn_color = ui.visuals().warn_fg_color;
+    match app.get_ffmpeg_capabilities() {
+        Some(Err(e)) => {
+            ui.label(
+                egui::RichText::new(format!("FFMPEG could not be checked: {}", e))
+                    .color(error_color),
+            );
+        }
+        Some(Ok(capabilities)) => {
+            if let Some(missing) = capabilities.missing_summary() {
+                ui.label(
+                    egui::RichText::new(format!(
+                        "{}. Loops cannot be created with this FFMPEG, try a newer or full build.",
+                        missing
+                    ))
+                    .color(error_color),
+                );
+            }
+            if !capabilities.mp3_output {
+                ui.label(
+                    egui::RichText::new(
+                        "Missing encoder: libmp3lame. Loops can only be saved as .wav.",
+                    )
+                    .color(warn_color),
+                );
+            }
+        }
+        None if !app.get_ffmpeg_path().is_empty() => {
+            ui.label("Checking FFMPEG...");
+        }
+        None => {}
+    }
 }
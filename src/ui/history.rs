@@ -0,0 +1,49 @@
+use egui::Ui;
+
+use crate::App;
+
+pub fn history_panel(app: &mut App, ui: &mut Ui) {
+    ui.collapsing("History", |ui| {
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(app.can_undo(), egui::Button::new("Undo"))
+                .on_hover_text("Undo the last edit. (Ctrl+Z)")
+                .clicked()
+            {
+                app.undo();
+            }
+            if ui
+                .add_enabled(app.can_redo(), egui::Button::new("Redo"))
+                .on_hover_text("Redo the last undone edit. (Ctrl+Shift+Z)")
+                .clicked()
+            {
+                app.redo();
+            }
+        });
+
+        // Newest first, with undone edits greyed out until something new is edited
+        let (labels, current) = app.history_labels();
+        let mut jump = None;
+        egui::ScrollArea::vertical()
+            .id_source("history_scroll")
+            .max_height(150.0)
+            .show(ui, |ui| {
+                for (i, label) in labels.iter().enumerate().rev() {
+                    let mut text = egui::RichText::new(label);
+                    if i > current {
+                        text = text.weak();
+                    }
+                    if ui
+                        .selectable_label(i == current, text)
+                        .on_hover_text("Go back to the settings after this edit.")
+                        .clicked()
+                    {
+                        jump = Some(i);
+                    }
+                }
+            });
+        if let Some(i) = jump {
+            app.jump_history(i);
+        }
+    });
+}
@@ -0,0 +1,168 @@
+use egui::Ui;
+
+use crate::{
+    job_history::{JobOutcome, JobRecord},
+    timecode::{self, Unit},
+    ui::console,
+    App,
+};
+
+// What was clicked for a job, acted on once the history is no longer borrowed
+enum Action {
+    Select(Option<u64>),
+    Reopen(u64),
+    Rerun(u64),
+    Reveal(std::path::PathBuf),
+    Clear,
+}
+
+pub fn job_history_panel(app: &mut App, ui: &mut Ui) {
+    ui.collapsing("Render History", |ui| {
+        let selected = *app.selected_job_mut();
+        let is_dark_mode = ui.visuals().dark_mode;
+        let mut action = None;
+        let records = app.job_history_mut().records();
+        if records.is_empty() {
+            ui.label(egui::RichText::new("Loops you render are listed here.").weak());
+            return;
+        }
+
+        egui::ScrollArea::vertical()
+            .id_source("job_history_scroll")
+            .max_height(150.0)
+            .show(ui, |ui| {
+                for record in records {
+                    let text = format!(
+                        "{} {}  {} -> {}  ({:.1}s){}",
+                        if record.succeeded() { "✔" } else { "✖" },
+                        ago(record.id),
+                        file_name(&record.input),
+                        file_name(&record.output),
+                        record.duration_s,
+                        if record.test { ", test" } else { "" }
+                    );
+                    let is_selected = selected == Some(record.id);
+                    if ui
+                        .selectable_label(is_selected, text)
+                        .on_hover_text("Show the settings, outputs and log of this job.")
+                        .clicked()
+                    {
+                        action = Some(Action::Select((!is_selected).then_some(record.id)));
+                    }
+                }
+            });
+
+        if let Some(record) = records.iter().find(|record| Some(record.id) == selected) {
+            ui.separator();
+            job_details(ui, record, is_dark_mode, &mut action);
+        }
+        ui.separator();
+        if ui
+            .button("Clear History")
+            .on_hover_text("Delete every job and its log from the history.")
+            .clicked()
+        {
+            action = Some(Action::Clear);
+        }
+
+        match action {
+            Some(Action::Select(id)) => *app.selected_job_mut() = id,
+            Some(Action::Reopen(id)) => {
+                app.reopen_job(id);
+            }
+            Some(Action::Rerun(id)) => app.rerun_job(id),
+            Some(Action::Reveal(path)) => app.reveal(&path),
+            Some(Action::Clear) => {
+                app.job_history_mut().clear();
+                *app.selected_job_mut() = None;
+            }
+            None => {}
+        }
+    });
+}
+
+fn job_details(ui: &mut Ui, record: &JobRecord, is_dark_mode: bool, action: &mut Option<Action>) {
+    let time = |seconds: f64| timecode::format(seconds, Unit::Seconds);
+    ui.label(format!("Song: {}", record.input.display()));
+    ui.label(format!(
+        "Loop {} to {}, {} crossfade ({:?}, {:?}), {} repeats",
+        time(record.times.start_s),
+        time(record.times.end_s),
+        time(record.times.crossfade_s),
+        record.times.placement,
+        record.times.curve,
+        record.times.loop_count
+    ));
+    match &record.outcome {
+        JobOutcome::Succeeded { summary } => {
+            ui.label(summary);
+        }
+        JobOutcome::Failed { error } => {
+            ui.label(egui::RichText::new(error).color(ui.visuals().error_fg_color));
+        }
+    }
+    for output in &record.outputs {
+        ui.horizontal(|ui| {
+            ui.label(output.display().to_string());
+            if ui
+                .small_button("Reveal")
+                .on_hover_text("Show the file in its folder.")
+                .clicked()
+            {
+                *action = Some(Action::Reveal(output.clone()));
+            }
+        });
+    }
+    ui.horizontal(|ui| {
+        if ui
+            .button("Use Settings")
+            .on_hover_text("Open the song with the settings this job used.")
+            .clicked()
+        {
+            *action = Some(Action::Reopen(record.id));
+        }
+        if ui
+            .button("Re-run")
+            .on_hover_text(format!(
+                "Render this job again, overwriting {}.",
+                record.output.display()
+            ))
+            .clicked()
+        {
+            *action = Some(Action::Rerun(record.id));
+        }
+    });
+    ui.collapsing(format!("Log ({} lines)", record.log.len()), |ui| {
+        egui::ScrollArea::vertical()
+            .id_source(("job_log_scroll", record.id))
+            .max_height(200.0)
+            .show(ui, |ui| {
+                for line in &record.log {
+                    ui.monospace(
+                        egui::RichText::new(line.text()).color(console::colour(line, is_dark_mode)),
+                    );
+                }
+            });
+    });
+}
+
+fn file_name(path: &std::path::Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+// How long ago a job started, from its id in milliseconds since the Unix epoch
+fn ago(started_ms: u64) -> String {
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    let minutes = now_ms.saturating_sub(started_ms) / 60_000;
+    match minutes {
+        0 => "just now".to_string(),
+        1..=59 => format!("{} min ago", minutes),
+        60..=1439 => format!("{} h ago", minutes / 60),
+        _ => format!("{} days ago", minutes / 1440),
+    }
+}
@@ -3,4 +3,11 @@ pub mod error;
 pub mod ffmpeg;
 pub mod footer;
 pub mod header;
+pub mod history;
+pub mod jobs;
+pub mod overwrite;
 pub mod parameters;
+pub mod project;
+pub mod recipe;
+pub mod settings;
+pub mod timeline;
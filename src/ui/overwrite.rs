@@ -0,0 +1,38 @@
+use crate::{naming::OverwritePolicy, App};
+
+pub fn overwrite_window(app: &mut App, ctx: &egui::Context) {
+    let output = match app.overwrite_prompt() {
+        Some(output) => output.display().to_string(),
+        None => return,
+    };
+    let mut answer = None;
+    egui::Window::new("File Exists")
+        .resizable(false)
+        .collapsible(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            ui.label(format!("{} already exists.", output));
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Overwrite")
+                    .on_hover_text("Replace the existing file with the new loop.")
+                    .clicked()
+                {
+                    answer = Some(Some(OverwritePolicy::Overwrite));
+                }
+                if ui
+                    .button("Keep Both")
+                    .on_hover_text("Write the new loop under a numbered name next to it.")
+                    .clicked()
+                {
+                    answer = Some(Some(OverwritePolicy::AutoNumber));
+                }
+                if ui.button("Cancel").on_hover_text("Don't render.").clicked() {
+                    answer = Some(None);
+                }
+            });
+        });
+    if let Some(policy) = answer {
+        app.answer_overwrite(policy);
+    }
+}
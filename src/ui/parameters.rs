@@ -1,7 +1,8 @@
 use egui::Ui;
 
 use crate::{
-    app::{TimeVariable, Unit},
+    looper::{CrossfadeCurve, CrossfadePlacement},
+    timecode::{self, TimeInput, Unit},
     App,
 };
 
@@ -9,49 +10,71 @@ pub fn create_param_grid(app: &mut App, ui: &mut Ui) {
     egui::Grid::new("time_grid")
         .spacing([50.0, 15.0])
         .show(ui, |ui| {
-            // Make the DragValue widgets a bit wider:
+            // Make the input widgets a bit wider:
             ui.spacing_mut().interact_size.x = 50.0;
 
-            let (value, unit) = app.start_time_params();
+            let sample_rate = app.get_sample_rate();
+
+            let (value, unit, input) = app.start_time_params();
             add_time_param(
                 ui,
                 "Start Time: ",
                 value,
                 unit,
+                input,
+                sample_rate,
                 "The time in the song where the loop will start.",
             );
 
-            let (value, unit) = app.end_time_params();
+            let (value, unit, input) = app.end_time_params();
             add_time_param(
                 ui,
                 "End Time: ",
                 value,
                 unit,
+                input,
+                sample_rate,
                 "The time in the song where the loop will end.",
             );
 
-            let (value, unit) = app.crossfade_params();
+            let (value, unit, input) = app.crossfade_params();
             add_time_param(
                 ui,
                 "Crossfade Duration: ",
                 value,
                 unit,
+                input,
+                sample_rate,
                 "The time it takes for the loop to fade in and out.",
             );
 
+            let tooltip = "Where the crossfade is taken from.\nBefore Start: fades in the audio leading up to the start time.\nAfter End: fades out the audio following the end time.\nCentred: splits the crossfade evenly around the seam.";
+            ui.label("Crossfade Placement: ").on_hover_text(tooltip);
+            ui.horizontal(|ui| {
+                let placement = app.crossfade_placement();
+                ui.selectable_value(placement, CrossfadePlacement::PreRoll, "Before Start");
+                ui.selectable_value(placement, CrossfadePlacement::PostRoll, "After End");
+                ui.selectable_value(placement, CrossfadePlacement::Centred, "Centred");
+            })
+            .response
+            .on_hover_text(tooltip);
+            ui.end_row();
+
+            let tooltip = "The shape of the fades the crossfade is mixed from.\nLinear: straight fades, best when both sides of the seam sound alike.\nEqual Power: keeps the loudness even when the two sides differ.";
+            ui.label("Crossfade Curve: ").on_hover_text(tooltip);
+            ui.horizontal(|ui| {
+                let curve = app.crossfade_curve();
+                ui.selectable_value(curve, CrossfadeCurve::Linear, "Linear");
+                ui.selectable_value(curve, CrossfadeCurve::EqualPower, "Equal Power");
+            })
+            .response
+            .on_hover_text(tooltip);
+            ui.end_row();
+
             ui.label("Loop Count")
                 .on_hover_text("The amount of times the section should loop");
             ui.add(egui::DragValue::new(app.get_loop_count()).speed(1))
                 .on_hover_text("The amount of times the section should loop");
-            ui.label(format!(
-                "Total approximate loop time: {} seconds",
-                f32::max(
-                    0.0,
-                    (app.get_time_var_s(TimeVariable::End)
-                        - app.get_time_var_s(TimeVariable::Start))
-                        * f32::from(*app.get_loop_count())
-                )
-            ));
             ui.end_row();
 
             ui.horizontal(|ui| {
@@ -75,8 +98,38 @@ pub fn create_param_grid(app: &mut App, ui: &mut Ui) {
                 {
                     app.open_file_dialog_and_create_loop("test", true);
                 }
+                if ui
+                    .add_enabled(can_run, egui::Button::new("Copy Commands"))
+                    .on_disabled_hover_text(&reason)
+                    .on_hover_text("Copy the ffmpeg commands that create this loop, as a script for this system's shell.")
+                    .clicked()
+                {
+                    app.copy_commands(ui.ctx());
+                }
+                if ui
+                    .add_enabled(can_run, egui::Button::new("Export Script"))
+                    .on_disabled_hover_text(&reason)
+                    .on_hover_text("Save the ffmpeg commands that create this loop as a shell (.sh) or PowerShell (.ps1) script, to run them elsewhere.")
+                    .clicked()
+                {
+                    app.export_script();
+                }
                 if app.is_running() {
                     ui.add(egui::widgets::Spinner::new());
+                    if let Some((step, done, total)) = app.job_progress() {
+                        ui.add(
+                            egui::ProgressBar::new(done as f32 / total.max(1) as f32)
+                                .desired_width(200.0)
+                                .text(step),
+                        );
+                    }
+                    if ui
+                        .button("Cancel")
+                        .on_hover_text("Stop rendering and delete the intermediate files.")
+                        .clicked()
+                    {
+                        app.cancel_job();
+                    }
                 }
                 if app.has_succeeded_running() {
                     ui.monospace(
@@ -87,19 +140,45 @@ pub fn create_param_grid(app: &mut App, ui: &mut Ui) {
         });
 }
 
-fn add_time_param<T: egui::emath::Numeric>(
+const TIME_FORMAT_HINT: &str =
+    "Accepts 1:23.456, 01:02:03.5, plain seconds, 12345ms or s:12345 for a sample position.";
+
+fn add_time_param(
     ui: &mut egui::Ui,
     label: &str,
-    value: &mut T,
+    value: &mut f64,
     unit: &mut Unit,
+    input: &mut TimeInput,
+    sample_rate: Option<u32>,
     tooltip: &str,
 ) {
     ui.label(label).on_hover_text(tooltip);
-    ui.add(egui::DragValue::new(value).speed(5))
-        .on_hover_text(tooltip);
+    ui.vertical(|ui| {
+        let response = ui
+            .add(egui::TextEdit::singleline(&mut input.text).desired_width(110.0))
+            .on_hover_text(format!("{}\n{}", tooltip, TIME_FORMAT_HINT));
+        if response.changed() {
+            match timecode::parse(&input.text, sample_rate) {
+                Ok(seconds) => {
+                    *value = seconds;
+                    input.error = None;
+                }
+                Err(e) => input.error = Some(e),
+            }
+        }
+        // Show the value in the chosen unit whenever the field is not being edited
+        if !response.has_focus() && input.error.is_none() {
+            input.text = timecode::format(*value, *unit);
+        }
+        if let Some(error) = &input.error {
+            ui.label(egui::RichText::new(error).color(ui.visuals().error_fg_color));
+        }
+    });
     ui.horizontal(|ui| {
-        ui.selectable_value(unit, Unit::Milliseconds, "ms");
-        ui.selectable_value(unit, Unit::Seconds, "s");
+        ui.selectable_value(unit, Unit::Milliseconds, "ms")
+            .on_hover_text("Display the time in milliseconds.");
+        ui.selectable_value(unit, Unit::Seconds, "s")
+            .on_hover_text("Display the time as minutes:seconds.milliseconds.");
     });
     ui.end_row();
 }
@@ -0,0 +1,98 @@
+use egui::Ui;
+
+use crate::{
+    timecode::{self, Unit},
+    App,
+};
+
+pub fn project_bar(app: &mut App, ui: &mut Ui) {
+    ui.horizontal(|ui| {
+        if ui
+            .button("Open Project")
+            .on_hover_text("Open a saved .echoblend project.")
+            .clicked()
+        {
+            app.open_project();
+        }
+        let has_source = app.has_source();
+        if ui
+            .add_enabled(has_source, egui::Button::new("Save Project"))
+            .on_disabled_hover_text("Load a file to save a project for it.")
+            .on_hover_text("Save the file, loop settings, regions and notes. (Ctrl+S)")
+            .clicked()
+        {
+            app.save_project(false);
+        }
+        if ui
+            .add_enabled(has_source, egui::Button::new("Save Project As"))
+            .on_disabled_hover_text("Load a file to save a project for it.")
+            .on_hover_text("Save the project to a new file.")
+            .clicked()
+        {
+            app.save_project(true);
+        }
+        if let Some(path) = app.project_path() {
+            ui.label(format!("Project: {}", path.display()));
+        }
+    });
+}
+
+pub fn project_details(app: &mut App, ui: &mut Ui) {
+    ui.collapsing("Regions and Notes", |ui| {
+        // Applied after the list is drawn, as they change it
+        let mut use_region = None;
+        let mut remove_region = None;
+        egui::Grid::new("region_grid")
+            .spacing([20.0, 5.0])
+            .show(ui, |ui| {
+                for (i, region) in app.regions().iter().enumerate() {
+                    ui.label(&region.name);
+                    ui.label(format!(
+                        "{} to {}",
+                        timecode::format(region.start_s, Unit::Seconds),
+                        timecode::format(region.end_s, Unit::Seconds)
+                    ));
+                    if ui
+                        .button("Use")
+                        .on_hover_text("Set the loop start and end to this region.")
+                        .clicked()
+                    {
+                        use_region = Some(i);
+                    }
+                    if ui.button("Remove").clicked() {
+                        remove_region = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+        if let Some(i) = use_region {
+            app.use_region(i);
+        }
+        if let Some(i) = remove_region {
+            app.remove_region(i);
+        }
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(app.region_name_mut())
+                    .hint_text("Region name")
+                    .desired_width(150.0),
+            );
+            if ui
+                .button("Add Current Loop")
+                .on_hover_text("Remember the current start and end time as a region.")
+                .clicked()
+            {
+                app.add_region();
+            }
+        });
+
+        ui.label("Notes:");
+        ui.add(
+            egui::TextEdit::multiline(app.notes_mut())
+                .hint_text("Anything worth remembering about this loop")
+                .desired_rows(3)
+                .desired_width(f32::INFINITY),
+        );
+    });
+}
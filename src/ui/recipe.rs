@@ -0,0 +1,47 @@
+use crate::{
+    timecode::{self, Unit},
+    App,
+};
+
+pub fn recipe_window(app: &mut App, ctx: &egui::Context) {
+    let recipe = match app.recipe_prompt() {
+        Some(recipe) => recipe.clone(),
+        None => return,
+    };
+    let time = |seconds: f64| timecode::format(seconds, Unit::Seconds);
+    let mut restore = false;
+    let mut dismiss = false;
+    egui::Window::new("Restore Recipe")
+        .resizable(false)
+        .collapsible(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "This loop was rendered by EchoBlend {} from {}.",
+                recipe.echoblend_version, recipe.source_name
+            ));
+            ui.label(format!(
+                "Start {}, end {}, crossfade {}, looped {} times.",
+                time(recipe.times.start_s),
+                time(recipe.times.end_s),
+                time(recipe.times.crossfade_s),
+                recipe.times.loop_count
+            ));
+            ui.label("Open the original song with these settings?");
+            ui.horizontal(|ui| {
+                restore = ui
+                    .button("Restore Recipe")
+                    .on_hover_text("Find the original song and restore the settings the loop was rendered with.")
+                    .clicked();
+                dismiss = ui
+                    .button("Keep This File")
+                    .on_hover_text("Loop the rendered file itself instead.")
+                    .clicked();
+            });
+        });
+    if restore {
+        app.restore_recipe();
+    } else if dismiss {
+        app.dismiss_recipe();
+    }
+}
@@ -0,0 +1,232 @@
+use egui::{Context, Ui};
+
+use crate::{
+    looper::{CrossfadeCurve, CrossfadePlacement},
+    naming::{self, OverwritePolicy},
+    render::OutputFormat,
+    settings::{ConsoleVerbosity, Settings, Theme},
+    timecode::Unit,
+    App,
+};
+
+// A button clicked in the window, acted on once it is closed over
+enum Action {
+    Export,
+    Import,
+}
+
+pub fn settings_window(app: &mut App, ctx: &Context) {
+    let mut open = *app.settings_window_mut();
+    if !open {
+        return;
+    }
+    let before = app.settings();
+    let mut settings = before.clone();
+    let preview = app.has_source().then(|| app.output_name_preview());
+    let supports_mp3_output = app.supports_mp3_output();
+    let mut action = None;
+    egui::Window::new("Settings")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.heading("Output");
+            output_settings(ui, &mut settings, preview, supports_mp3_output);
+            ui.separator();
+            ui.heading("Rendering");
+            rendering_settings(ui, &mut settings);
+            ui.separator();
+            ui.heading("New Sessions");
+            session_settings(ui, &mut settings);
+            ui.separator();
+            ui.heading("Appearance");
+            appearance_settings(ui, &mut settings);
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Export")
+                    .on_hover_text("Save these settings to a file, to import on another computer.")
+                    .clicked()
+                {
+                    action = Some(Action::Export);
+                }
+                if ui
+                    .button("Import")
+                    .on_hover_text("Replace these settings with ones exported earlier.")
+                    .clicked()
+                {
+                    action = Some(Action::Import);
+                }
+                if ui
+                    .button("Reset All")
+                    .on_hover_text("Go back to the settings EchoBlend comes with.")
+                    .clicked()
+                {
+                    settings = Settings::default();
+                }
+            });
+        });
+    *app.settings_window_mut() = open;
+    if settings != before {
+        app.apply_settings(settings);
+    }
+    match action {
+        Some(Action::Export) => app.export_settings(),
+        Some(Action::Import) => app.import_settings(),
+        None => {}
+    }
+}
+
+fn output_settings(
+    ui: &mut Ui,
+    settings: &mut Settings,
+    preview: Option<Result<String, String>>,
+    supports_mp3_output: bool,
+) {
+    folder_field(
+        ui,
+        "Output folder:",
+        "Where the save dialog opens for a loop that hasn't been saved before.",
+        "Working directory",
+        &mut settings.output_dir,
+    );
+    ui.horizontal(|ui| {
+        ui.label("Format:");
+        ui.selectable_value(&mut settings.format, OutputFormat::Wav, "WAV")
+            .on_hover_text("Suggest saving loops as 16-bit WAV.");
+        ui.add_enabled_ui(supports_mp3_output, |ui| {
+            ui.selectable_value(&mut settings.format, OutputFormat::Mp3, "MP3")
+                .on_hover_text("Suggest saving loops as gapless MP3.")
+                .on_disabled_hover_text("The selected FFMPEG has no MP3 encoder (libmp3lame).");
+        });
+    });
+    ui.horizontal(|ui| {
+        ui.label("MP3 quality:")
+            .on_hover_text("The variable bitrate quality MP3 loops are encoded at, 0 being the best and 9 the smallest.");
+        ui.add(egui::Slider::new(&mut settings.mp3_quality, 0..=9));
+    });
+
+    let tokens: Vec<String> = naming::TOKENS
+        .iter()
+        .map(|(token, meaning)| format!("{}: {}", token, meaning))
+        .collect();
+    ui.horizontal(|ui| {
+        ui.label("Output name:")
+            .on_hover_text("The name suggested when saving a loop.");
+        ui.add(egui::TextEdit::singleline(&mut settings.name_template).desired_width(250.0))
+            .on_hover_text(tokens.join("\n"));
+        if ui.small_button("Reset").clicked() {
+            settings.name_template = naming::DEFAULT_TEMPLATE.to_string();
+        }
+    });
+    match preview {
+        Some(Ok(name)) => {
+            ui.label(egui::RichText::new(format!("e.g. {}", name)).weak());
+        }
+        Some(Err(e)) => {
+            ui.label(egui::RichText::new(e).color(ui.visuals().error_fg_color));
+        }
+        None => {}
+    }
+    let overwrite = &mut settings.overwrite;
+    ui.horizontal(|ui| {
+        ui.label("If the file exists:");
+        ui.selectable_value(overwrite, OverwritePolicy::Ask, "Ask")
+            .on_hover_text("Ask before replacing it.");
+        ui.selectable_value(overwrite, OverwritePolicy::Skip, "Skip")
+            .on_hover_text("Leave it alone and don't render.");
+        ui.selectable_value(overwrite, OverwritePolicy::AutoNumber, "Number")
+            .on_hover_text("Write the new loop under a numbered name, e.g. song_loop (2).wav.");
+        ui.selectable_value(overwrite, OverwritePolicy::Overwrite, "Overwrite")
+            .on_hover_text("Replace it without asking.");
+    });
+}
+
+fn rendering_settings(ui: &mut Ui, settings: &mut Settings) {
+    folder_field(
+        ui,
+        "Scratch folder:",
+        "Where each loop's intermediate files are written while it renders.",
+        "System temp folder",
+        &mut settings.scratch_dir,
+    );
+    ui.checkbox(&mut settings.keep_intermediates, "Keep intermediate files")
+        .on_hover_text("Leave the rendered intro, crossfade, loop and outro files in the scratch folder after each loop, for debugging.");
+}
+
+fn session_settings(ui: &mut Ui, settings: &mut Settings) {
+    ui.label(egui::RichText::new("Used each time EchoBlend starts.").weak());
+    ui.horizontal(|ui| {
+        ui.label("Crossfade:");
+        ui.add(
+            egui::DragValue::new(&mut settings.crossfade_s)
+                .clamp_range(0.0..=60.0)
+                .speed(0.01)
+                .suffix(" s"),
+        );
+    });
+    ui.horizontal(|ui| {
+        ui.label("Placement:");
+        let placement = &mut settings.placement;
+        ui.selectable_value(placement, CrossfadePlacement::PreRoll, "Before start");
+        ui.selectable_value(placement, CrossfadePlacement::PostRoll, "After end");
+        ui.selectable_value(placement, CrossfadePlacement::Centred, "Centred");
+    });
+    ui.horizontal(|ui| {
+        ui.label("Curve:");
+        ui.selectable_value(&mut settings.curve, CrossfadeCurve::Linear, "Linear");
+        ui.selectable_value(
+            &mut settings.curve,
+            CrossfadeCurve::EqualPower,
+            "Equal power",
+        );
+    });
+}
+
+fn appearance_settings(ui: &mut Ui, settings: &mut Settings) {
+    ui.horizontal(|ui| {
+        ui.label("Theme:");
+        ui.selectable_value(&mut settings.theme, Theme::System, "System")
+            .on_hover_text("Follow the system's light or dark mode.");
+        ui.selectable_value(&mut settings.theme, Theme::Dark, "Dark");
+        ui.selectable_value(&mut settings.theme, Theme::Light, "Light");
+    });
+    ui.horizontal(|ui| {
+        ui.label("Console:");
+        let console = &mut settings.console;
+        ui.selectable_value(console, ConsoleVerbosity::Quiet, "Quiet")
+            .on_hover_text(
+                "Show EchoBlend's progress, results and errors, but not ffmpeg's output.",
+            );
+        ui.selectable_value(console, ConsoleVerbosity::Normal, "Normal")
+            .on_hover_text("Show everything, collapsing ffmpeg's banner and progress lines.");
+        ui.selectable_value(console, ConsoleVerbosity::Verbose, "Verbose")
+            .on_hover_text("Show every line ffmpeg prints.");
+    });
+    for (label, unit) in [
+        ("Start shown in:", &mut settings.start_unit),
+        ("End shown in:", &mut settings.end_unit),
+        ("Crossfade shown in:", &mut settings.crossfade_unit),
+    ] {
+        ui.horizontal(|ui| {
+            ui.label(label);
+            ui.selectable_value(unit, Unit::Milliseconds, "ms");
+            ui.selectable_value(unit, Unit::Seconds, "s");
+        });
+    }
+}
+
+fn folder_field(ui: &mut Ui, label: &str, tooltip: &str, hint: &str, dir: &mut String) {
+    ui.horizontal(|ui| {
+        ui.label(label).on_hover_text(tooltip);
+        ui.add(
+            egui::TextEdit::singleline(dir)
+                .hint_text(hint)
+                .desired_width(250.0),
+        );
+        if ui.button("Browse").clicked() {
+            if let Some(picked) = rfd::FileDialog::new().pick_folder() {
+                *dir = picked.display().to_string();
+            }
+        }
+    });
+}
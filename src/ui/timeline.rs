@@ -0,0 +1,127 @@
+use egui::{Color32, FontId, Rect, Sense, Ui};
+
+use crate::{
+    looper::{Section, SectionKind},
+    render,
+    timecode::{self, Unit},
+    App,
+};
+
+const BAR_HEIGHT: f32 = 28.0;
+
+pub fn loop_timeline(app: &mut App, ui: &mut Ui) {
+    ui.label("Loop Preview").on_hover_text(
+        "The parts the loop will be joined from, in order, with the current settings.",
+    );
+    let sections = match app.loop_plan() {
+        Ok(sections) => sections,
+        Err(reason) => {
+            ui.label(egui::RichText::new(reason).weak());
+            return;
+        }
+    };
+    let total_s = sections
+        .last()
+        .map_or(0.0, |last| last.start_s + last.duration_s);
+
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(ui.available_width(), BAR_HEIGHT + 16.0),
+        Sense::hover(),
+    );
+    let bar = Rect::from_min_size(rect.min, egui::vec2(rect.width(), BAR_HEIGHT));
+    let x_at = |time_s: f32| bar.left() + bar.width() * time_s / total_s.max(f32::EPSILON);
+    let painter = ui.painter_at(rect);
+    let font = FontId::proportional(11.0);
+    let text_color = ui.visuals().strong_text_color();
+
+    let mut loop_number = 0;
+    // Right edge of the last boundary time written under the bar, so the times don't overlap
+    let mut last_time_right = f32::NEG_INFINITY;
+    for (i, section) in sections.iter().enumerate() {
+        if section.kind == SectionKind::Loop {
+            loop_number += 1;
+        }
+        let block = Rect::from_x_y_ranges(
+            x_at(section.start_s)..=x_at(section.start_s + section.duration_s),
+            bar.y_range(),
+        );
+        painter.rect_filled(
+            block.shrink2(egui::vec2(0.5, 0.0)),
+            2.0,
+            color(section, loop_number),
+        );
+
+        let name = match section.kind {
+            SectionKind::Intro => "Intro".to_string(),
+            SectionKind::Crossfade => "Crossfade".to_string(),
+            SectionKind::Loop => format!("Loop {}", loop_number),
+            SectionKind::Outro => "Outro".to_string(),
+        };
+        let label = ui.fonts(|fonts| fonts.layout_no_wrap(name.clone(), font.clone(), text_color));
+        if label.size().x + 4.0 < block.width() {
+            painter.galley(block.center() - label.size() / 2.0, label, text_color);
+        }
+        ui.interact(block, ui.id().with(("timeline", i)), Sense::hover())
+            .on_hover_text(format!(
+                "{}\n{} to {} ({})",
+                name,
+                time(section.start_s),
+                time(section.start_s + section.duration_s),
+                time(section.duration_s)
+            ));
+
+        let boundary =
+            ui.fonts(|fonts| fonts.layout_no_wrap(time(section.start_s), font.clone(), text_color));
+        let left = x_at(section.start_s).min(bar.right() - boundary.size().x);
+        if left > last_time_right + 6.0 {
+            last_time_right = left + boundary.size().x;
+            painter.galley(egui::pos2(left, bar.bottom() + 2.0), boundary, text_color);
+        }
+    }
+
+    let info = app.source_info();
+    let mp3_quality = app.mp3_quality();
+    let mut formats = vec!["wav"];
+    if app.supports_mp3_output() {
+        formats.push("mp3");
+    }
+    let sizes: Vec<String> = formats
+        .iter()
+        .filter_map(|format| {
+            let output = std::path::Path::new("loop").with_extension(format);
+            let size = render::estimated_size(&output, total_s, info, mp3_quality)?;
+            Some(format!(
+                "≈ {} as {}",
+                file_size(size),
+                format.to_uppercase()
+            ))
+        })
+        .collect();
+    ui.label(format!("Total: {}, {}", time(total_s), sizes.join(", ")))
+        .on_hover_text("WAV loops are 16-bit at the song's sample rate, MP3 loops are variable bitrate so their size is only a guess.");
+}
+
+fn color(section: &Section, loop_number: usize) -> Color32 {
+    match section.kind {
+        SectionKind::Intro | SectionKind::Outro => Color32::from_rgb(70, 90, 130),
+        SectionKind::Crossfade => Color32::from_rgb(200, 130, 40),
+        // Alternate shades tell neighbouring repeats apart
+        SectionKind::Loop if loop_number % 2 == 0 => Color32::from_rgb(50, 130, 70),
+        SectionKind::Loop => Color32::from_rgb(70, 160, 90),
+    }
+}
+
+fn time(seconds: f32) -> String {
+    timecode::format(f64::from(seconds), Unit::Seconds)
+}
+
+fn file_size(bytes: u64) -> String {
+    let bytes = bytes as f64;
+    if bytes >= 1024.0 * 1024.0 * 1024.0 {
+        format!("{:.2} GB", bytes / (1024.0 * 1024.0 * 1024.0))
+    } else if bytes >= 1024.0 * 1024.0 {
+        format!("{:.1} MB", bytes / (1024.0 * 1024.0))
+    } else {
+        format!("{:.0} KB", bytes / 1024.0)
+    }
+}
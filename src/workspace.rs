@@ -0,0 +1,190 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Counts the workspaces made by this process so concurrent jobs never share one
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A scratch directory holding the intermediate files of a single job.
+///
+/// The directory and everything in it is removed when the workspace is dropped,
+/// unless it was created to keep its intermediates for debugging.
+pub struct Workspace {
+    dir: PathBuf,
+    keep: bool,
+}
+
+impl Workspace {
+    /// Creates a new, empty directory under `root`, or the system temp directory if `root` is `None`.
+    ///
+    /// A relative `root` is taken from the current directory, so the workspace holds
+    /// the same files however the paths into it are later resolved.
+    pub fn create(root: Option<&Path>, keep: bool) -> Result<Self, String> {
+        let root = match root {
+            Some(root) if root.is_relative() => std::env::current_dir()
+                .map_err(|e| format!("Failed to find the current directory: {}", e))?
+                .join(root),
+            Some(root) => root.to_path_buf(),
+            None => std::env::temp_dir(),
+        };
+        std::fs::create_dir_all(&root).map_err(|e| {
+            format!(
+                "Failed to create scratch directory {}: {}",
+                root.display(),
+                e
+            )
+        })?;
+        loop {
+            let dir = root.join(format!(
+                "echo_blend_{}_{}",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            // create_dir fails if the directory exists, so a leftover from an earlier run is never reused
+            match std::fs::create_dir(&dir) {
+                Ok(_) => return Ok(Self { dir, keep }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(format!(
+                        "Failed to create scratch directory {}: {}",
+                        dir.display(),
+                        e
+                    ))
+                }
+            }
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether the intermediate files are left behind for debugging
+    pub fn keeps_intermediates(&self) -> bool {
+        self.keep
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if !self.keep {
+            std::fs::remove_dir_all(&self.dir).unwrap_or_default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{FailingBackend, MediaInfo, RecordingBackend},
+        job::{CancelToken, JobError},
+        render::{render, LoopSpec},
+    };
+
+    fn scratch_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "echo_blend_workspace_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    fn spec(root: &Path, keep: bool) -> LoopSpec {
+        LoopSpec::builder(root.join("song.wav"), root.join("out.wav"))
+            .start(10.0)
+            .end(20.0)
+            .crossfade(1.0)
+            .scratch_dir(root)
+            .keep_intermediates(keep)
+            .build()
+            .unwrap()
+    }
+
+    // The workspaces left under `root` once a job is over
+    fn workspaces(root: &Path) -> Vec<PathBuf> {
+        std::fs::read_dir(root)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_dir())
+            .collect()
+    }
+
+    #[test]
+    fn a_relative_root_is_made_absolute() {
+        let root = Path::new("target").join(format!("echo_blend_workspace_{}", std::process::id()));
+        let workspace = Workspace::create(Some(&root), false).unwrap();
+        assert!(workspace.dir().is_absolute());
+        assert_eq!(
+            workspace.dir().parent(),
+            Some(std::env::current_dir().unwrap().join(&root).as_path())
+        );
+        drop(workspace);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn is_removed_on_drop_unless_kept() {
+        let root = scratch_root("drop");
+        let removed = Workspace::create(Some(&root), false).unwrap();
+        let kept = Workspace::create(Some(&root), true).unwrap();
+        let (removed_dir, kept_dir) = (removed.dir().to_path_buf(), kept.dir().to_path_buf());
+        drop(removed);
+        drop(kept);
+        assert!(!removed_dir.exists());
+        assert!(kept_dir.is_dir());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn is_removed_after_a_failed_job() {
+        let root = scratch_root("failed");
+        let result = render(
+            &spec(&root, false),
+            &FailingBackend::default(),
+            &mut |_| {},
+            &CancelToken::default(),
+        );
+        assert!(
+            matches!(result, Err(JobError::Step(_))),
+            "{:?}",
+            result.err()
+        );
+        assert_eq!(workspaces(&root), Vec::<PathBuf>::new());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn is_removed_after_a_cancelled_job() {
+        let root = scratch_root("cancelled");
+        let backend = RecordingBackend::new(MediaInfo::default());
+        let cancel = CancelToken::default();
+        cancel.cancel();
+        let result = render(&spec(&root, false), &backend, &mut |_| {}, &cancel);
+        assert!(
+            matches!(result, Err(JobError::Cancelled)),
+            "{:?}",
+            result.err()
+        );
+        assert_eq!(workspaces(&root), Vec::<PathBuf>::new());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn is_kept_after_a_failed_job_when_asked() {
+        let root = scratch_root("kept");
+        let result = render(
+            &spec(&root, true),
+            &FailingBackend::default(),
+            &mut |_| {},
+            &CancelToken::default(),
+        );
+        assert!(result.is_err());
+        assert_eq!(workspaces(&root).len(), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}